@@ -0,0 +1,68 @@
+//! Loads the rows of every Parquet file in a directory into an Elasticsearch
+//! index through its bulk API.
+//!
+//! ```sh
+//! cargo run --example parquet-elasticsearch --features parquet -- data/ http://localhost:9200 events
+//! ```
+
+use data_proc::batch::Rows;
+use data_proc::{HttpOutput, Pipeline, Process, Result, parquet};
+use futures::{Stream, StreamExt};
+use http::header::{CONTENT_TYPE, HeaderMap, HeaderValue};
+use serde_json::{Value, json};
+
+/// Turns rows into bulk API request bodies of `size` documents each.
+struct BulkRequests {
+    index: String,
+    size: usize,
+}
+
+impl Process<Value, String> for BulkRequests {
+    async fn process<S>(&self, stream: S) -> impl Stream<Item = Result<String>> + Send
+    where
+        S: Stream<Item = Value> + Send,
+    {
+        let action = json!({ "index": { "_index": self.index } }).to_string();
+        stream.chunks(self.size).map(move |rows| {
+            let mut body = String::new();
+            for row in rows {
+                body.push_str(&action);
+                body.push('\n');
+                body.push_str(&row.to_string());
+                body.push('\n');
+            }
+            Ok(body)
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (Some(dir), Some(cluster), Some(index)) = (args.next(), args.next(), args.next()) else {
+        eprintln!("usage: parquet-elasticsearch <dir> <cluster url> <index>");
+        std::process::exit(2);
+    };
+
+    let endpoint = reqwest::Url::parse(&cluster)
+        .and_then(|url| url.join("_bulk"))
+        .map_err(|e| data_proc::Error::config(format!("cluster `{cluster}`: {e}")))?;
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/x-ndjson"),
+    );
+
+    let summary = Pipeline::new(Rows::<_, Value>::new(parquet::Input::new(dir)))
+        .process(BulkRequests { index, size: 500 })
+        .output(HttpOutput::new(
+            endpoint,
+            http::Method::POST,
+            Some(headers),
+        )?)
+        .run()
+        .await;
+
+    println!("indexed {} rows in {:?}", summary.items_in, summary.elapsed);
+    summary.error.map_or(Ok(()), Err)
+}
@@ -1,8 +1,12 @@
-use data_proc::{Input, Output, Stdin, Stdout};
+use data_proc::{Pipeline, Stdin, Stdout};
 
 #[tokio::main]
 async fn main() {
-    let stdin = Stdin {};
-    let stdout = Stdout {};
-    stdout.output(stdin.into_stream()).await.unwrap();
+    let summary = Pipeline::new(Stdin::new())
+        .output(Stdout::new())
+        .run()
+        .await;
+    if let Some(e) = summary.error {
+        eprintln!("pipeline failed: {e}");
+    }
 }
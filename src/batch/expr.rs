@@ -0,0 +1,667 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use arrow::array::{
+    Array, ArrayRef, AsArray, BooleanArray, Datum, Float64Array, Int64Array, NullArray,
+    RecordBatch, Scalar, StringArray, UInt32Array,
+};
+use arrow::compute::kernels::{cmp, numeric};
+use arrow::compute::{and_kleene, cast, is_not_null, is_null, not, nullif, or_kleene, take};
+use arrow::datatypes::DataType;
+use serde::{Deserialize, Deserializer};
+
+use crate::{Error, Result};
+
+/// An expression over the columns of a record batch, such as
+/// `price * quantity > 100 and country == 'NZ'`.
+///
+/// Columns are referred to by name, with dotted paths for struct fields and
+/// backquotes around names that are not plain identifiers. Expressions know
+/// numbers, strings in single or double quotes, `true`, `false` and `null`;
+/// `+ - * / %`, comparisons (`== != < <= > >=`), `and`, `or`, `not`, and
+/// `is null`/`is not null`. Numbers of different types are compared and
+/// combined as 64-bit integers or floats. Dividing integers by zero, or
+/// taking the remainder, gives null.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    source: String,
+    node: Node,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Column(Vec<String>),
+    Literal(Literal),
+    Neg(Box<Node>),
+    Not(Box<Node>),
+    IsNull(Box<Node>, bool),
+    Binary(Op, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl Op {
+    /// Binding power; operators of higher precedence bind tighter.
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Or => 1,
+            Op::And => 2,
+            Op::Eq | Op::Ne | Op::Lt | Op::Le | Op::Gt | Op::Ge => 3,
+            Op::Add | Op::Sub => 4,
+            Op::Mul | Op::Div | Op::Rem => 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Quoted(String),
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Op(Op),
+    Not,
+    Is,
+    Dot,
+    Open,
+    Close,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '.' if !chars.peek().is_some_and(|(_, c)| c.is_ascii_digit()) => Token::Dot,
+            '+' => Token::Op(Op::Add),
+            '-' => Token::Op(Op::Sub),
+            '*' => Token::Op(Op::Mul),
+            '/' => Token::Op(Op::Div),
+            '%' => Token::Op(Op::Rem),
+            '=' => {
+                chars.next_if(|(_, c)| *c == '=');
+                Token::Op(Op::Eq)
+            }
+            '!' if chars.next_if(|(_, c)| *c == '=').is_some() => Token::Op(Op::Ne),
+            '!' => Token::Not,
+            '<' if chars.next_if(|(_, c)| *c == '=').is_some() => Token::Op(Op::Le),
+            '<' if chars.next_if(|(_, c)| *c == '>').is_some() => Token::Op(Op::Ne),
+            '<' => Token::Op(Op::Lt),
+            '>' if chars.next_if(|(_, c)| *c == '=').is_some() => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '&' if chars.next_if(|(_, c)| *c == '&').is_some() => Token::Op(Op::And),
+            '|' if chars.next_if(|(_, c)| *c == '|').is_some() => Token::Op(Op::Or),
+            '\'' | '"' | '`' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, 'n')) => text.push('\n'),
+                            Some((_, 't')) => text.push('\t'),
+                            Some((_, c)) => text.push(c),
+                            None => return Err(format!("unterminated {c} at {start}")),
+                        },
+                        Some((_, end)) if end == c => break,
+                        Some((_, c)) => text.push(c),
+                        None => return Err(format!("unterminated {c} at {start}")),
+                    }
+                }
+                if c == '`' {
+                    Token::Quoted(text)
+                } else {
+                    Token::Str(text)
+                }
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut end = start + c.len_utf8();
+                let mut float = c == '.';
+                while let Some(&(i, c)) = chars.peek() {
+                    let exponent_sign = (c == '+' || c == '-')
+                        && matches!(source[..i].chars().last(), Some('e' | 'E'));
+                    if !(c.is_ascii_alphanumeric() || c == '.' || c == '_' || exponent_sign) {
+                        break;
+                    }
+                    float |= c == '.' || c == 'e' || c == 'E';
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                let text = source[start..end].replace('_', "");
+                let number = if float {
+                    text.parse().map(Token::Float).ok()
+                } else {
+                    text.parse().map(Token::Int).ok()
+                };
+                number.ok_or_else(|| format!("invalid number `{}`", &source[start..end]))?
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) = chars.next_if(|(_, c)| c.is_alphanumeric() || *c == '_') {
+                    end = i + c.len_utf8();
+                }
+                let word = &source[start..end];
+                match word.to_ascii_lowercase().as_str() {
+                    "and" => Token::Op(Op::And),
+                    "or" => Token::Op(Op::Or),
+                    "not" => Token::Not,
+                    "is" => Token::Is,
+                    _ => Token::Ident(word.to_string()),
+                }
+            }
+            c => return Err(format!("unexpected `{c}` at {start}")),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+}
+
+impl Parser {
+    fn expect(&mut self, expected: Token, what: &str) -> Result<(), String> {
+        match self.tokens.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!("expected {what}, found {token}")),
+            None => Err(format!("expected {what}, found the end")),
+        }
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Result<Node, String> {
+        let mut lhs = self.unary()?;
+        loop {
+            match self.tokens.peek() {
+                Some(Token::Op(op)) if op.precedence() >= min_precedence => {
+                    let op = *op;
+                    self.tokens.next();
+                    let rhs = self.binary(op.precedence() + 1)?;
+                    lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+                }
+                Some(Token::Is) if min_precedence <= Op::Eq.precedence() => {
+                    self.tokens.next();
+                    let negated = self.tokens.next_if_eq(&Token::Not).is_some();
+                    match self.tokens.next() {
+                        Some(Token::Ident(word)) if word.eq_ignore_ascii_case("null") => {}
+                        _ => return Err("expected `null` after `is`".to_string()),
+                    }
+                    lhs = Node::IsNull(Box::new(lhs), negated);
+                }
+                _ => return Ok(lhs),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.tokens.peek() {
+            // `not` binds looser than comparisons, as in SQL
+            Some(Token::Not) => {
+                self.tokens.next();
+                Ok(Node::Not(Box::new(self.binary(Op::Eq.precedence())?)))
+            }
+            Some(Token::Op(Op::Sub)) => {
+                self.tokens.next();
+                Ok(match self.unary()? {
+                    Node::Literal(Literal::Int(n)) => Node::Literal(Literal::Int(-n)),
+                    Node::Literal(Literal::Float(n)) => Node::Literal(Literal::Float(-n)),
+                    node => Node::Neg(Box::new(node)),
+                })
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let node = match self.tokens.next() {
+            Some(Token::Open) => {
+                let node = self.binary(0)?;
+                self.expect(Token::Close, "`)`")?;
+                return Ok(node);
+            }
+            Some(Token::Int(n)) => Node::Literal(Literal::Int(n)),
+            Some(Token::Float(n)) => Node::Literal(Literal::Float(n)),
+            Some(Token::Str(s)) => Node::Literal(Literal::Str(s)),
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("null") => {
+                Node::Literal(Literal::Null)
+            }
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("true") => {
+                Node::Literal(Literal::Bool(true))
+            }
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("false") => {
+                Node::Literal(Literal::Bool(false))
+            }
+            Some(Token::Ident(name) | Token::Quoted(name)) => {
+                let mut path = vec![name];
+                while self.tokens.next_if_eq(&Token::Dot).is_some() {
+                    match self.tokens.next() {
+                        Some(Token::Ident(name) | Token::Quoted(name)) => path.push(name),
+                        _ => return Err("expected a field name after `.`".to_string()),
+                    }
+                }
+                Node::Column(path)
+            }
+            Some(token) => return Err(format!("unexpected {token}")),
+            None => return Err("unexpected end".to_string()),
+        };
+        Ok(node)
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "`{name}`"),
+            Token::Quoted(name) => write!(f, "`{name}`"),
+            Token::Str(s) => write!(f, "{s:?}"),
+            Token::Int(n) => write!(f, "`{n}`"),
+            Token::Float(n) => write!(f, "`{n}`"),
+            Token::Op(op) => write!(f, "`{op:?}`"),
+            Token::Not => f.write_str("`not`"),
+            Token::Is => f.write_str("`is`"),
+            Token::Dot => f.write_str("`.`"),
+            Token::Open => f.write_str("`(`"),
+            Token::Close => f.write_str("`)`"),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Self> {
+        let parse = || {
+            let mut parser = Parser {
+                tokens: tokenize(source)?.into_iter().peekable(),
+            };
+            let node = parser.binary(0)?;
+            match parser.tokens.next() {
+                Some(token) => Err(format!("unexpected {token}")),
+                None => Ok(node),
+            }
+        };
+        let node = parse().map_err(|e| Error::config(format!("expression `{source}`: {e}")))?;
+        Ok(Self {
+            source: source.to_string(),
+            node,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Expr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        source.parse().map_err(|e| match e {
+            Error::Config(msg) => serde::de::Error::custom(msg),
+            e => serde::de::Error::custom(e),
+        })
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// The result of evaluating part of an expression: a column, or a single
+/// value standing for every row.
+enum Evaluated {
+    Array(ArrayRef),
+    Scalar(ArrayRef),
+}
+
+impl Evaluated {
+    fn array(&self) -> &ArrayRef {
+        match self {
+            Evaluated::Array(array) | Evaluated::Scalar(array) => array,
+        }
+    }
+
+    fn is_scalar(&self) -> bool {
+        matches!(self, Evaluated::Scalar(_))
+    }
+
+    fn map(&self, array: ArrayRef) -> Self {
+        match self {
+            Evaluated::Array(_) => Evaluated::Array(array),
+            Evaluated::Scalar(_) => Evaluated::Scalar(array),
+        }
+    }
+
+    fn cast(self, to: &DataType) -> Result<Self> {
+        if self.array().data_type() == to {
+            return Ok(self);
+        }
+        let array = cast(self.array(), to).map_err(Error::codec)?;
+        Ok(self.map(array))
+    }
+
+    /// Repeats a scalar for every one of `rows` rows.
+    fn into_array(self, rows: usize) -> Result<ArrayRef> {
+        match self {
+            Evaluated::Array(array) => Ok(array),
+            Evaluated::Scalar(array) => {
+                take(&array, &UInt32Array::from(vec![0; rows]), None).map_err(Error::codec)
+            }
+        }
+    }
+}
+
+fn is_float(data_type: &DataType) -> bool {
+    data_type.is_floating()
+        || matches!(
+            data_type,
+            DataType::Decimal128(..) | DataType::Decimal256(..)
+        )
+}
+
+/// Brings both sides of a binary operator to the same type.
+fn coerce(lhs: Evaluated, rhs: Evaluated) -> Result<(Evaluated, Evaluated)> {
+    let (left, right) = (
+        lhs.array().data_type().clone(),
+        rhs.array().data_type().clone(),
+    );
+    if left == right {
+        return Ok((lhs, rhs));
+    }
+    let numeric = |t: &DataType| t.is_numeric() || t == &DataType::Null;
+    if numeric(&left) && numeric(&right) && !left.is_null() && !right.is_null() {
+        let common = if is_float(&left) || is_float(&right) {
+            DataType::Float64
+        } else {
+            DataType::Int64
+        };
+        return Ok((lhs.cast(&common)?, rhs.cast(&common)?));
+    }
+    if lhs.is_scalar() && !rhs.is_scalar() {
+        Ok((lhs.cast(&right)?, rhs))
+    } else {
+        let rhs = rhs.cast(&left)?;
+        Ok((lhs, rhs))
+    }
+}
+
+fn boolean(array: &ArrayRef) -> Result<&BooleanArray> {
+    array
+        .as_boolean_opt()
+        .ok_or_else(|| Error::codec(format!("expected a boolean, found {}", array.data_type())))
+}
+
+/// Replaces the zeros of an integer array by nulls, so that dividing by
+/// them gives null rather than failing the whole batch.
+fn null_zeros(array: &ArrayRef) -> Result<ArrayRef> {
+    let zero = cast(&Int64Array::from(vec![0]), array.data_type()).map_err(Error::codec)?;
+    let zeros = cmp::eq(array, &Scalar::new(zero)).map_err(Error::codec)?;
+    nullif(array, &zeros).map_err(Error::codec)
+}
+
+impl Node {
+    fn evaluate(&self, batch: &RecordBatch) -> Result<Evaluated> {
+        match self {
+            Node::Column(path) => {
+                let missing = || Error::config(format!("no column `{}`", path.join(".")));
+                let mut column = batch.column_by_name(&path[0]).ok_or_else(missing)?;
+                for field in &path[1..] {
+                    column = column
+                        .as_struct_opt()
+                        .and_then(|parent| parent.column_by_name(field))
+                        .ok_or_else(missing)?;
+                }
+                Ok(Evaluated::Array(column.clone()))
+            }
+            Node::Literal(literal) => Ok(Evaluated::Scalar(match literal {
+                Literal::Null => Arc::new(NullArray::new(1)),
+                Literal::Bool(b) => Arc::new(BooleanArray::from(vec![*b])),
+                Literal::Int(n) => Arc::new(Int64Array::from(vec![*n])),
+                Literal::Float(n) => Arc::new(Float64Array::from(vec![*n])),
+                Literal::Str(s) => Arc::new(StringArray::from(vec![s.as_str()])),
+            })),
+            Node::Neg(node) => {
+                let value = node.evaluate(batch)?;
+                let array = numeric::neg(value.array()).map_err(Error::codec)?;
+                Ok(value.map(array))
+            }
+            Node::Not(node) => {
+                let value = node.evaluate(batch)?;
+                let array = not(boolean(value.array())?).map_err(Error::codec)?;
+                Ok(value.map(Arc::new(array)))
+            }
+            Node::IsNull(node, negated) => {
+                let value = node.evaluate(batch)?;
+                let array = if *negated {
+                    is_not_null(value.array())
+                } else {
+                    is_null(value.array())
+                };
+                Ok(value.map(Arc::new(array.map_err(Error::codec)?)))
+            }
+            Node::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.evaluate(batch)?, rhs.evaluate(batch)?);
+                if matches!(op, Op::And | Op::Or) {
+                    let rows = batch.num_rows();
+                    let (lhs, rhs) = (lhs.into_array(rows)?, rhs.into_array(rows)?);
+                    let (lhs, rhs) = (boolean(&lhs)?, boolean(&rhs)?);
+                    let array = match op {
+                        Op::And => and_kleene(lhs, rhs),
+                        _ => or_kleene(lhs, rhs),
+                    };
+                    return Ok(Evaluated::Array(Arc::new(array.map_err(Error::codec)?)));
+                }
+                let (lhs, mut rhs) = coerce(lhs, rhs)?;
+                if matches!(op, Op::Div | Op::Rem) && rhs.array().data_type().is_integer() {
+                    rhs = rhs.map(null_zeros(rhs.array())?);
+                }
+                let scalar = lhs.is_scalar() && rhs.is_scalar();
+                let datum = |value: &Evaluated| -> Box<dyn Datum> {
+                    match value {
+                        Evaluated::Array(array) => Box::new(array.clone()),
+                        Evaluated::Scalar(array) => Box::new(Scalar::new(array.clone())),
+                    }
+                };
+                let (l, r) = (datum(&lhs), datum(&rhs));
+                let (l, r) = (l.as_ref(), r.as_ref());
+                let compared = |result: Result<BooleanArray, _>| {
+                    result.map(|array| Arc::new(array) as ArrayRef)
+                };
+                let array = match op {
+                    Op::Eq => compared(cmp::eq(l, r)),
+                    Op::Ne => compared(cmp::neq(l, r)),
+                    Op::Lt => compared(cmp::lt(l, r)),
+                    Op::Le => compared(cmp::lt_eq(l, r)),
+                    Op::Gt => compared(cmp::gt(l, r)),
+                    Op::Ge => compared(cmp::gt_eq(l, r)),
+                    Op::Add => numeric::add(l, r),
+                    Op::Sub => numeric::sub(l, r),
+                    Op::Mul => numeric::mul(l, r),
+                    Op::Div => numeric::div(l, r),
+                    Op::Rem => numeric::rem(l, r),
+                    Op::And | Op::Or => unreachable!("handled above"),
+                }
+                .map_err(Error::codec)?;
+                Ok(if scalar {
+                    Evaluated::Scalar(array)
+                } else {
+                    Evaluated::Array(array)
+                })
+            }
+        }
+    }
+}
+
+impl Expr {
+    /// Evaluates the expression for every row of `batch`.
+    pub fn evaluate(&self, batch: &RecordBatch) -> Result<ArrayRef> {
+        self.node
+            .evaluate(batch)
+            .and_then(|value| value.into_array(batch.num_rows()))
+            .map_err(|e| match e {
+                Error::Config(msg) => Error::config(format!("expression `{self}`: {msg}")),
+                Error::Codec(e) => Error::codec(format!("expression `{self}`: {e}")),
+                e => e,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{Int32Array, StructArray};
+    use arrow::datatypes::{Field, Schema};
+
+    use super::*;
+
+    fn batch() -> RecordBatch {
+        let user = StructArray::from(vec![(
+            Arc::new(Field::new("age", DataType::Int64, true)),
+            Arc::new(Int64Array::from(vec![Some(30), None, Some(17)])) as ArrayRef,
+        )]);
+        let schema = Schema::new(vec![
+            Field::new("n", DataType::Int32, true),
+            Field::new("price", DataType::Float64, true),
+            Field::new("country", DataType::Utf8, true),
+            Field::new("user", user.data_type().clone(), true),
+            Field::new("two words", DataType::Int32, true),
+        ]);
+        RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(Int32Array::from(vec![Some(4), Some(0), None])),
+                Arc::new(Float64Array::from(vec![1.5, 20.0, 3.0])),
+                Arc::new(StringArray::from(vec!["NZ", "AU", "NZ"])),
+                Arc::new(user),
+                Arc::new(Int32Array::from(vec![1, 2, 3])),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn evaluate(source: &str) -> Result<ArrayRef> {
+        source.parse::<Expr>()?.evaluate(&batch())
+    }
+
+    fn booleans(source: &str) -> Vec<Option<bool>> {
+        evaluate(source).unwrap().as_boolean().iter().collect()
+    }
+
+    fn integers(source: &str) -> Vec<Option<i64>> {
+        let array = cast(&evaluate(source).unwrap(), &DataType::Int64).unwrap();
+        array
+            .as_primitive::<arrow::datatypes::Int64Type>()
+            .iter()
+            .collect()
+    }
+
+    #[test]
+    fn binds_operators_by_precedence() {
+        assert_eq!(integers("1 + 2 * 3 - 4 % 3"), [Some(6); 3]);
+        assert_eq!(integers("(1 + 2) * 3"), [Some(9); 3]);
+        assert_eq!(integers("10 - 4 - 3"), [Some(3); 3]);
+        assert_eq!(integers("-n * 2"), [Some(-8), Some(0), None]);
+        assert_eq!(
+            booleans("country == 'NZ' or price > 10 and n > 1"),
+            [Some(true), Some(false), Some(true)]
+        );
+        assert_eq!(
+            booleans("not n > 1 and country = \"NZ\""),
+            [Some(false), Some(false), None]
+        );
+    }
+
+    #[test]
+    fn mixes_number_types() {
+        assert_eq!(booleans("n * price >= 6"), [Some(true), Some(false), None]);
+        assert_eq!(
+            booleans("price < 2"),
+            [Some(true), Some(false), Some(false)]
+        );
+    }
+
+    #[test]
+    fn reads_nested_and_quoted_columns() {
+        assert_eq!(
+            booleans("user.age is null"),
+            [Some(false), Some(true), Some(false)]
+        );
+        assert_eq!(
+            booleans("user.age is not null and user.age >= 18"),
+            [Some(true), Some(false), Some(false)]
+        );
+        assert_eq!(integers("`two words` + 1"), [Some(2), Some(3), Some(4)]);
+    }
+
+    #[test]
+    fn divides_integers_by_zero_into_null() {
+        assert_eq!(integers("8 / n"), [Some(2), None, None]);
+        assert_eq!(integers("9 % n"), [Some(1), None, None]);
+        assert_eq!(integers("n / 0"), [None; 3]);
+        let quotients = evaluate("price / 0").unwrap();
+        assert!(
+            quotients
+                .as_primitive::<arrow::datatypes::Float64Type>()
+                .value(0)
+                .is_infinite()
+        );
+    }
+
+    #[test]
+    fn explains_what_does_not_parse() {
+        let message = |source: &str| source.parse::<Expr>().unwrap_err().to_string();
+        assert!(
+            message("n >").contains("unexpected end"),
+            "{}",
+            message("n >")
+        );
+        assert!(
+            message("(n > 1").contains("expected `)`"),
+            "{}",
+            message("(n > 1")
+        );
+        assert!(message("n > 1 2").contains("unexpected `2`"));
+        assert!(message("'open").contains("unterminated '"));
+        assert!(message("n is 1").contains("expected `null` after `is`"));
+        assert!(message("n # 1").contains("unexpected `#`"));
+    }
+
+    #[test]
+    fn names_missing_columns_and_mistyped_operands() {
+        let e = evaluate("missing > 1").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "invalid configuration: expression `missing > 1`: no column `missing`"
+        );
+        assert!(evaluate("n and true").is_err());
+        assert!(
+            evaluate("user.nope == 1")
+                .unwrap_err()
+                .to_string()
+                .contains("no column `user.nope`")
+        );
+    }
+}
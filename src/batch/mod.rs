@@ -0,0 +1,625 @@
+//! Arrow [`RecordBatch`]es as pipeline items: process stages working on
+//! whole batches, and conversions between batches and rows.
+
+mod expr;
+
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use arrow::array::ArrayRef;
+use arrow::compute::{CastOptions, cast_with_options, filter_record_batch};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::json::reader::{ReaderBuilder, infer_json_schema_from_iterator};
+use arrow::json::writer::{JsonArray, WriterBuilder};
+use futures::{Stream, StreamExt, stream};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use crate::pipeline::{Failure, until_err};
+use crate::{Error, Input, Output, Process, Result};
+
+pub use arrow::array::RecordBatch;
+pub use expr::Expr;
+
+const DEFAULT_BATCH_SIZE: usize = 1024;
+
+fn default_batch_size() -> usize {
+    DEFAULT_BATCH_SIZE
+}
+
+/// Deserializes a count of rows, which must be at least 1.
+pub(crate) fn at_least_one<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    match usize::deserialize(deserializer)? {
+        0 => Err(serde::de::Error::custom("must be at least 1")),
+        n => Ok(n),
+    }
+}
+
+/// Infers a schema covering every row in `rows`.
+pub(crate) fn infer_schema<T: Serialize>(rows: &[T]) -> Result<SchemaRef> {
+    let values = rows
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::codec)?;
+    let schema = infer_json_schema_from_iterator(values.iter().map(Ok)).map_err(Error::codec)?;
+    Ok(Arc::new(schema))
+}
+
+/// Builds a batch of `rows` laid out according to `schema`. Fields missing
+/// from the schema are dropped.
+pub(crate) fn from_rows<T: Serialize>(rows: &[T], schema: SchemaRef) -> Result<RecordBatch> {
+    let mut decoder = ReaderBuilder::new(schema.clone())
+        .with_batch_size(rows.len().max(1))
+        .build_decoder()
+        .map_err(Error::codec)?;
+    decoder.serialize(rows).map_err(Error::codec)?;
+    let batch = decoder.flush().map_err(Error::codec)?;
+    Ok(batch.unwrap_or_else(|| RecordBatch::new_empty(schema)))
+}
+
+/// A schema of nullable columns from their types by name, like the columns
+/// of [`Cast`], such as `{id: int64, name: string}`. Columns are laid out
+/// in the order of their names.
+pub(crate) fn schema_of(columns: Value) -> Result<SchemaRef> {
+    let columns = BTreeMap::<String, String>::deserialize(columns)
+        .map_err(|e| Error::config(format!("schema: {e}")))?;
+    let fields = columns
+        .into_iter()
+        .map(|(name, data_type)| Ok(Field::new(name, parse_type(&data_type)?, true)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Arc::new(Schema::new(fields)))
+}
+
+fn deserialize_schema<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<SchemaRef>, D::Error> {
+    schema_of(Value::deserialize(deserializer)?)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Groups `rows` into batches of `batch_size`, laid out according to
+/// `schema`, or to the schema inferred from the first batch.
+fn batches<S, T>(
+    rows: S,
+    batch_size: usize,
+    mut schema: Option<SchemaRef>,
+) -> impl Stream<Item = Result<RecordBatch>> + Send
+where
+    S: Stream<Item = T> + Send,
+    T: Serialize + Send,
+{
+    rows.chunks(batch_size).map(move |rows| {
+        let schema = match &schema {
+            Some(schema) => Arc::clone(schema),
+            None => schema.insert(infer_schema(&rows)?).clone(),
+        };
+        from_rows(&rows, schema)
+    })
+}
+
+/// Splits `batch` into its rows. Null columns are kept as explicit nulls.
+pub(crate) fn to_rows<T: DeserializeOwned>(batch: &RecordBatch) -> Result<Vec<T>> {
+    if batch.num_rows() == 0 {
+        return Ok(Vec::new());
+    }
+    let mut buf = Vec::new();
+    let mut writer = WriterBuilder::new()
+        .with_explicit_nulls(true)
+        .build::<_, JsonArray>(&mut buf);
+    writer.write(batch).map_err(Error::codec)?;
+    writer.finish().map_err(Error::codec)?;
+    drop(writer);
+    serde_json::from_slice(&buf).map_err(Error::codec)
+}
+
+/// Reads the rows of an input of record batches, deserializing each into a `T`.
+pub struct Rows<I, T> {
+    input: I,
+    item: PhantomData<fn() -> T>,
+}
+
+impl<I, T> Rows<I, T> {
+    pub fn new(input: I) -> Self {
+        Self {
+            input,
+            item: PhantomData,
+        }
+    }
+}
+
+impl<I, T> Input<T> for Rows<I, T>
+where
+    I: Input<RecordBatch> + Send,
+    T: DeserializeOwned + Send,
+{
+    fn into_stream(self) -> impl Stream<Item = Result<T>> + Send {
+        async_stream::try_stream! {
+            let batches = self.input.into_stream();
+            futures::pin_mut!(batches);
+            while let Some(batch) = batches.next().await {
+                for row in to_rows(&batch?)? {
+                    yield row;
+                }
+            }
+        }
+    }
+
+    fn checkpoints(&self) -> Option<crate::Checkpoints> {
+        self.input.checkpoints()
+    }
+}
+
+/// Writes rows to an output of record batches, `batch_size` rows at a time.
+///
+/// Batches are laid out according to `schema` if one is given: fields of
+/// rows not in it are dropped, and columns rows lack are null. Otherwise
+/// the schema is inferred from the first batch of rows, which may miss
+/// fields that later rows add, or guess wrong types for columns that are
+/// null throughout the first batch.
+#[derive(Debug, Clone)]
+pub struct Batches<O> {
+    output: O,
+    batch_size: usize,
+    schema: Option<SchemaRef>,
+}
+
+impl<O> Batches<O> {
+    pub fn new(output: O) -> Self {
+        Self {
+            output,
+            batch_size: DEFAULT_BATCH_SIZE,
+            schema: None,
+        }
+    }
+
+    pub fn schema(mut self, schema: SchemaRef) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+}
+
+impl<O, T> Output<T> for Batches<O>
+where
+    O: Output<RecordBatch> + Sync,
+    T: Serialize + Send,
+{
+    async fn output<S>(&self, stream: S) -> Result<()>
+    where
+        S: Stream<Item = T> + Send,
+    {
+        let batches = batches(stream, self.batch_size, self.schema.clone());
+        let failure = Failure::default();
+        self.output
+            .output(until_err(batches, failure.clone()))
+            .await?;
+        failure.take().map_or(Ok(()), Err)
+    }
+}
+
+/// Turns rows into record batches of `batch_size` rows, laid out like
+/// [`Batches`] lays them out: according to `schema`, given as column types
+/// by name, or to the schema inferred from the first batch of rows.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ToBatches {
+    #[serde(default = "default_batch_size", deserialize_with = "at_least_one")]
+    batch_size: usize,
+    #[serde(default, deserialize_with = "deserialize_schema")]
+    schema: Option<SchemaRef>,
+}
+
+impl ToBatches {
+    pub fn new(batch_size: usize) -> Self {
+        Self {
+            batch_size: batch_size.max(1),
+            schema: None,
+        }
+    }
+
+    pub fn schema(mut self, schema: SchemaRef) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+}
+
+impl Default for ToBatches {
+    fn default() -> Self {
+        Self::new(DEFAULT_BATCH_SIZE)
+    }
+}
+
+impl<T: Serialize + Send> Process<T, RecordBatch> for ToBatches {
+    async fn process<S>(&self, stream: S) -> impl Stream<Item = Result<RecordBatch>> + Send
+    where
+        S: Stream<Item = T> + Send,
+    {
+        batches(stream, self.batch_size, self.schema.clone())
+    }
+}
+
+/// Splits record batches into their rows, deserializing each into a `T`.
+pub struct ToRows<T> {
+    item: PhantomData<fn() -> T>,
+}
+
+impl<T> ToRows<T> {
+    pub fn new() -> Self {
+        Self { item: PhantomData }
+    }
+}
+
+impl<T> Default for ToRows<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: DeserializeOwned + Send> Process<RecordBatch, T> for ToRows<T> {
+    async fn process<S>(&self, stream: S) -> impl Stream<Item = Result<T>> + Send
+    where
+        S: Stream<Item = RecordBatch> + Send,
+    {
+        stream.flat_map(|batch| match to_rows(&batch) {
+            Ok(rows) => stream::iter(rows.into_iter().map(Ok).collect::<Vec<_>>()),
+            Err(e) => stream::iter(vec![Err(e)]),
+        })
+    }
+}
+
+/// Keeps only `columns` of every batch, in the order given.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Select {
+    columns: Vec<String>,
+}
+
+impl Select {
+    pub fn new<I: IntoIterator<Item = S>, S: Into<String>>(columns: I) -> Self {
+        Self {
+            columns: columns.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    fn select(&self, batch: &RecordBatch) -> Result<RecordBatch> {
+        let schema = batch.schema();
+        let indices = self
+            .columns
+            .iter()
+            .map(|name| {
+                schema
+                    .index_of(name)
+                    .map_err(|_| Error::config(format!("no column `{name}`")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        batch.project(&indices).map_err(Error::codec)
+    }
+}
+
+impl Process<RecordBatch, RecordBatch> for Select {
+    async fn process<S>(&self, stream: S) -> impl Stream<Item = Result<RecordBatch>> + Send
+    where
+        S: Stream<Item = RecordBatch> + Send,
+    {
+        let select = self.clone();
+        stream.map(move |batch| select.select(&batch))
+    }
+}
+
+/// Keeps the rows for which `expr` is true; rows where it is false or null
+/// are dropped, and so are batches left empty.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn new(expr: Expr) -> Self {
+        Self { expr }
+    }
+
+    fn filter(&self, batch: &RecordBatch) -> Result<RecordBatch> {
+        let mask = self.expr.evaluate(batch)?;
+        let mask = mask.as_any().downcast_ref().ok_or_else(|| {
+            Error::config(format!(
+                "filter `{}` is a {}, not a boolean",
+                self.expr,
+                mask.data_type()
+            ))
+        })?;
+        filter_record_batch(batch, mask).map_err(Error::codec)
+    }
+}
+
+impl Process<RecordBatch, RecordBatch> for Filter {
+    async fn process<S>(&self, stream: S) -> impl Stream<Item = Result<RecordBatch>> + Send
+    where
+        S: Stream<Item = RecordBatch> + Send,
+    {
+        let filter = self.clone();
+        stream
+            .map(move |batch| filter.filter(&batch))
+            .filter(|batch| {
+                futures::future::ready(!matches!(batch, Ok(batch) if batch.num_rows() == 0))
+            })
+    }
+}
+
+/// Parses a column type: an Arrow type name such as `Int64` or
+/// `Timestamp(Millisecond, None)`, or a lowercase alias like `int64`,
+/// `float64`, `string` or `bool`.
+fn parse_type(name: &str) -> Result<DataType> {
+    let data_type = match name {
+        "bool" | "boolean" => DataType::Boolean,
+        "int8" => DataType::Int8,
+        "int16" => DataType::Int16,
+        "int32" => DataType::Int32,
+        "int64" | "int" => DataType::Int64,
+        "uint8" => DataType::UInt8,
+        "uint16" => DataType::UInt16,
+        "uint32" => DataType::UInt32,
+        "uint64" => DataType::UInt64,
+        "float32" => DataType::Float32,
+        "float64" | "float" | "double" => DataType::Float64,
+        "string" | "utf8" => DataType::Utf8,
+        "binary" => DataType::Binary,
+        "date" | "date32" => DataType::Date32,
+        name => name
+            .parse()
+            .map_err(|e| Error::config(format!("type `{name}`: {e}")))?,
+    };
+    Ok(data_type)
+}
+
+fn column_types<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, DataType>, D::Error> {
+    BTreeMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(column, name)| match parse_type(&name) {
+            Ok(data_type) => Ok((column, data_type)),
+            Err(Error::Config(msg)) => Err(serde::de::Error::custom(msg)),
+            Err(e) => Err(serde::de::Error::custom(e)),
+        })
+        .collect()
+}
+
+/// Casts `columns` to new types, given by column name.
+///
+/// Values that do not fit the new type fail the stage, or become null with
+/// `lenient` set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cast {
+    #[serde(deserialize_with = "column_types")]
+    columns: BTreeMap<String, DataType>,
+    #[serde(default)]
+    lenient: bool,
+}
+
+impl Cast {
+    pub fn new() -> Self {
+        Self {
+            columns: BTreeMap::new(),
+            lenient: false,
+        }
+    }
+
+    pub fn column<S: Into<String>>(mut self, name: S, data_type: DataType) -> Self {
+        self.columns.insert(name.into(), data_type);
+        self
+    }
+
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    fn cast(&self, batch: &RecordBatch) -> Result<RecordBatch> {
+        let schema = batch.schema();
+        if let Some(name) = self
+            .columns
+            .keys()
+            .find(|name| schema.index_of(name).is_err())
+        {
+            return Err(Error::config(format!("no column `{name}`")));
+        }
+        let options = CastOptions {
+            safe: self.lenient,
+            ..Default::default()
+        };
+        let mut fields = Vec::with_capacity(schema.fields().len());
+        let mut columns = Vec::with_capacity(schema.fields().len());
+        for (field, column) in schema.fields().iter().zip(batch.columns()) {
+            match self.columns.get(field.name()) {
+                Some(data_type) => {
+                    let cast = cast_with_options(column, data_type, &options).map_err(|e| {
+                        Error::codec(format!("cast `{}` to {data_type}: {e}", field.name()))
+                    })?;
+                    fields.push(Arc::new(
+                        field
+                            .as_ref()
+                            .clone()
+                            .with_data_type(data_type.clone())
+                            .with_nullable(true),
+                    ));
+                    columns.push(cast);
+                }
+                None => {
+                    fields.push(field.clone());
+                    columns.push(column.clone());
+                }
+            }
+        }
+        let schema = Schema::new_with_metadata(fields, schema.metadata().clone());
+        RecordBatch::try_new(Arc::new(schema), columns).map_err(Error::codec)
+    }
+}
+
+impl Default for Cast {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Process<RecordBatch, RecordBatch> for Cast {
+    async fn process<S>(&self, stream: S) -> impl Stream<Item = Result<RecordBatch>> + Send
+    where
+        S: Stream<Item = RecordBatch> + Send,
+    {
+        let cast = self.clone();
+        stream.map(move |batch| cast.cast(&batch))
+    }
+}
+
+/// A column added by [`Compute`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Computed {
+    pub name: String,
+    pub expr: Expr,
+}
+
+/// Adds a column for every entry of `columns`, computed from an expression
+/// over the batch. A column replaces an existing one of the same name, and
+/// the expressions of later columns may use earlier ones.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Compute {
+    columns: Vec<Computed>,
+}
+
+impl Compute {
+    pub fn new() -> Self {
+        Self {
+            columns: Vec::new(),
+        }
+    }
+
+    pub fn column<S: Into<String>>(mut self, name: S, expr: Expr) -> Self {
+        self.columns.push(Computed {
+            name: name.into(),
+            expr,
+        });
+        self
+    }
+
+    fn compute(&self, mut batch: RecordBatch) -> Result<RecordBatch> {
+        for Computed { name, expr } in &self.columns {
+            let column: ArrayRef = expr.evaluate(&batch)?;
+            let field = Arc::new(Field::new(name, column.data_type().clone(), true));
+            let schema = batch.schema();
+            let mut fields = schema.fields().to_vec();
+            let mut columns = batch.columns().to_vec();
+            match schema.index_of(name) {
+                Ok(i) => {
+                    fields[i] = field;
+                    columns[i] = column;
+                }
+                Err(_) => {
+                    fields.push(field);
+                    columns.push(column);
+                }
+            }
+            let schema = Schema::new_with_metadata(fields, schema.metadata().clone());
+            batch = RecordBatch::try_new(Arc::new(schema), columns).map_err(Error::codec)?;
+        }
+        Ok(batch)
+    }
+}
+
+impl Default for Compute {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Process<RecordBatch, RecordBatch> for Compute {
+    async fn process<S>(&self, stream: S) -> impl Stream<Item = Result<RecordBatch>> + Send
+    where
+        S: Stream<Item = RecordBatch> + Send,
+    {
+        let compute = self.clone();
+        stream.map(move |batch| compute.compute(batch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+    use serde_json::json;
+
+    use super::*;
+
+    async fn to_batches(stage: ToBatches, rows: Vec<Value>) -> Result<Vec<RecordBatch>> {
+        stage.process(stream::iter(rows)).await.try_collect().await
+    }
+
+    #[tokio::test]
+    async fn infers_the_schema_from_the_first_batch() {
+        let rows = vec![json!({"a": 1}), json!({"a": 2}), json!({"a": 3, "b": "x"})];
+        let batches = to_batches(ToBatches::new(2), rows).await.unwrap();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[1].schema(), batches[0].schema());
+        assert_eq!(to_rows::<Value>(&batches[1]).unwrap(), [json!({"a": 3})]);
+    }
+
+    #[tokio::test]
+    async fn lays_batches_out_according_to_a_given_schema() {
+        let stage: ToBatches = serde_json::from_value(
+            json!({"batch_size": 2, "schema": {"a": "int64", "b": "string"}}),
+        )
+        .unwrap();
+        let rows = vec![
+            json!({"a": 1}),
+            json!({"a": 2}),
+            json!({"a": 3, "b": "x", "c": 0}),
+        ];
+        let batches = to_batches(stage, rows).await.unwrap();
+        let schema = batches[0].schema();
+        assert_eq!(
+            schema.field_with_name("b").unwrap().data_type(),
+            &DataType::Utf8
+        );
+        assert_eq!(
+            to_rows::<Value>(&batches[1]).unwrap(),
+            [json!({"a": 3, "b": "x"})]
+        );
+    }
+
+    #[test]
+    fn rejects_empty_batches() {
+        let e = serde_json::from_value::<ToBatches>(json!({"batch_size": 0})).unwrap_err();
+        assert!(e.to_string().contains("must be at least 1"), "{e}");
+    }
+
+    #[test]
+    fn rejects_unknown_column_types() {
+        let e = schema_of(json!({"a": "nope"})).unwrap_err();
+        assert!(e.to_string().contains("type `nope`"), "{e}");
+    }
+
+    #[tokio::test]
+    async fn converts_rows_back_from_batches() {
+        let rows = vec![json!({"a": 1, "b": null}), json!({"a": 2, "b": "y"})];
+        let batches = to_batches(ToBatches::default(), rows.clone())
+            .await
+            .unwrap();
+        let back: Vec<Value> = ToRows::new()
+            .process(stream::iter(batches))
+            .await
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(back, rows);
+    }
+}
@@ -0,0 +1,347 @@
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{Error, Result};
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+/// Identifies a file independently of the path it is currently reachable by.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileId {
+    pub dev: u64,
+    pub ino: u64,
+}
+
+impl FileId {
+    #[cfg(unix)]
+    pub fn of(metadata: &Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn of(_metadata: &Metadata) -> Self {
+        Self::default()
+    }
+}
+
+/// How far an input has read a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub file: FileId,
+    pub offset: u64,
+}
+
+impl Checkpoint {
+    /// The offset to resume `file` from, if this checkpoint still applies to it.
+    pub fn resume(&self, file: FileId, len: u64) -> Option<u64> {
+        (self.file == file && self.offset <= len).then_some(self.offset)
+    }
+}
+
+/// Durable storage for checkpoints, keyed by input.
+pub trait CheckpointStore: Send + Sync {
+    fn load(&self, key: &str) -> Result<Option<Checkpoint>>;
+
+    /// Saves all `checkpoints` at once; a failed save leaves the store as it was.
+    fn save(&self, checkpoints: &BTreeMap<String, Checkpoint>) -> Result<()>;
+}
+
+/// Keeps checkpoints in a JSON file, replaced atomically on every save.
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    /// Nothing is read or written until the store is first used.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn read(&self) -> Result<BTreeMap<String, Checkpoint>> {
+        match std::fs::read(&self.path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| {
+                Error::config(format!("checkpoint file {}: {e}", self.path.display()))
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(crate::fio::with_path(&self.path)(e).into()),
+        }
+    }
+}
+
+impl CheckpointStore for FileStore {
+    fn load(&self, key: &str) -> Result<Option<Checkpoint>> {
+        Ok(self.read()?.remove(key))
+    }
+
+    fn save(&self, checkpoints: &BTreeMap<String, Checkpoint>) -> Result<()> {
+        let mut all = self.read()?;
+        all.extend(checkpoints.iter().map(|(k, v)| (k.clone(), *v)));
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let json = serde_json::to_vec_pretty(&all).map_err(Error::codec)?;
+        {
+            use std::io::Write;
+            let mut file = std::fs::File::create(&tmp).map_err(crate::fio::with_path(&tmp))?;
+            file.write_all(&json)?;
+            file.sync_all()?;
+        }
+        std::fs::rename(&tmp, &self.path).map_err(crate::fio::with_path(&self.path))?;
+        Ok(())
+    }
+}
+
+/// Counts the items an output has safely written, in the order it was
+/// given them; see [`Output::output_acked`](crate::Output::output_acked).
+///
+/// Clones share the same count.
+#[derive(Debug, Default, Clone)]
+pub struct Acks(Arc<AtomicU64>);
+
+impl Acks {
+    /// Acknowledges the first `items` items.
+    pub fn acknowledge(&self, items: u64) {
+        self.0.fetch_max(items, Ordering::AcqRel);
+    }
+
+    /// How many items have been acknowledged.
+    pub fn count(&self) -> u64 {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+type Positions = Arc<BTreeMap<String, Checkpoint>>;
+
+#[derive(Debug, Default)]
+struct Staged {
+    latest: Positions,
+    /// The positions staged before the latest ones, which every item read so
+    /// far but the last is within.
+    settled: Positions,
+    /// The staged positions as items reached the output, by the number of
+    /// the item: the first one not yet committed, and the last one.
+    first: Option<(u64, Positions)>,
+    last: Option<(u64, Positions)>,
+}
+
+/// Read positions staged by an input and committed to a [`CheckpointStore`]
+/// once the output has accepted everything read up to them.
+///
+/// Clones share the same staged positions. Stores are used on blocking
+/// threads, and one save at a time.
+#[derive(Clone)]
+pub struct Checkpoints {
+    store: Arc<dyn CheckpointStore>,
+    staged: Arc<Mutex<Staged>>,
+    saving: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Checkpoints {
+    pub fn new<S: CheckpointStore + 'static>(store: S) -> Self {
+        Self {
+            store: Arc::new(store),
+            staged: Arc::default(),
+            saving: Arc::default(),
+        }
+    }
+
+    /// Opens the store at `path`: SQLite for `.db` and `.sqlite` files when the
+    /// `sqlite` feature is enabled, a JSON [`FileStore`] otherwise.
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        #[cfg(feature = "sqlite")]
+        if matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("db" | "sqlite")
+        ) {
+            return Self::new(SqliteStore::new(path));
+        }
+        Self::new(FileStore::new(path))
+    }
+
+    /// The last committed checkpoint for `key`.
+    pub async fn load(&self, key: &str) -> Result<Option<Checkpoint>> {
+        let store = self.store.clone();
+        let key = key.to_string();
+        tokio::task::spawn_blocking(move || store.load(&key))
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(e)))?
+    }
+
+    /// Records that everything up to `checkpoint` has been read. Inputs stage
+    /// the position after every item as they read it.
+    pub fn stage(&self, key: &str, checkpoint: Checkpoint) {
+        let mut staged = self.staged.lock().unwrap();
+        staged.settled = staged.latest.clone();
+        Arc::make_mut(&mut staged.latest).insert(key.to_string(), checkpoint);
+    }
+
+    /// Notes the positions staged before the item the input read last, as
+    /// item number `item` reaches the output.
+    ///
+    /// Every item read before that one has passed through all process stages
+    /// by then, unless a stage holds on to items (see
+    /// [`Process::holds_items`](crate::Process::holds_items)). The item read
+    /// last may not have: a stage may be turning it into several, as record
+    /// batches are split into rows.
+    pub(crate) fn mark(&self, item: u64) {
+        let mut staged = self.staged.lock().unwrap();
+        let positions = (item, staged.settled.clone());
+        match staged.first {
+            None => staged.first = Some(positions),
+            Some(_) => staged.last = Some(positions),
+        }
+    }
+
+    /// Persists the positions noted by [`mark`](Self::mark) for the last of
+    /// the first `acked` items.
+    pub(crate) async fn commit_acked(&self, acked: u64) -> Result<()> {
+        let positions = {
+            let mut staged = self.staged.lock().unwrap();
+            let acked = |(item, _): &mut (u64, Positions)| *item <= acked;
+            match staged.last.take_if(acked) {
+                Some(last) => {
+                    staged.first = None;
+                    Some(last)
+                }
+                None => staged.first.take_if(acked).inspect(|_| {
+                    staged.first = staged.last.take();
+                }),
+            }
+        };
+        match positions {
+            Some((_, positions)) => self.save(positions).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Persists every staged checkpoint.
+    pub async fn commit(&self) -> Result<()> {
+        let latest = {
+            let mut staged = self.staged.lock().unwrap();
+            staged.first = None;
+            staged.last = None;
+            staged.settled = Positions::default();
+            std::mem::take(&mut staged.latest)
+        };
+        self.save(latest).await
+    }
+
+    async fn save(&self, checkpoints: Positions) -> Result<()> {
+        if checkpoints.is_empty() {
+            return Ok(());
+        }
+        let _saving = self.saving.lock().await;
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || store.save(&checkpoints))
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(e)))?
+    }
+}
+
+impl fmt::Debug for Checkpoints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Checkpoints")
+            .field("staged", &*self.staged.lock().unwrap().latest)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Deserializes from the path of the store, see [`Checkpoints::open`].
+impl<'de> Deserialize<'de> for Checkpoints {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        PathBuf::deserialize(deserializer).map(Self::open)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(offset: u64) -> Checkpoint {
+        Checkpoint {
+            file: FileId { dev: 1, ino: 2 },
+            offset,
+        }
+    }
+
+    #[test]
+    fn resumes_only_the_same_file_within_its_length() {
+        assert_eq!(at(10).resume(FileId { dev: 1, ino: 2 }, 10), Some(10));
+        assert_eq!(at(10).resume(FileId { dev: 1, ino: 2 }, 9), None);
+        assert_eq!(at(10).resume(FileId { dev: 1, ino: 3 }, 100), None);
+    }
+
+    #[test]
+    fn file_store_merges_saves_and_leaves_other_files_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        std::fs::write(dir.path().join("state.tmp"), "unrelated").unwrap();
+        let store = FileStore::new(&path);
+
+        assert_eq!(store.load("a").unwrap(), None);
+        store
+            .save(&BTreeMap::from([("a".to_string(), at(1))]))
+            .unwrap();
+        store
+            .save(&BTreeMap::from([("b".to_string(), at(2))]))
+            .unwrap();
+        assert_eq!(store.load("a").unwrap(), Some(at(1)));
+        assert_eq!(FileStore::new(&path).load("b").unwrap(), Some(at(2)));
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("state.tmp")).unwrap(),
+            "unrelated"
+        );
+        assert!(!dir.path().join("state.json.tmp").exists());
+    }
+
+    #[tokio::test]
+    async fn commits_marked_positions_once_acknowledged() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let checkpoints = Checkpoints::new(FileStore::new(&path));
+
+        checkpoints.stage("a", at(1));
+        checkpoints.mark(1);
+        checkpoints.stage("a", at(2));
+        checkpoints.mark(2);
+        checkpoints.stage("a", at(3));
+        checkpoints.mark(3);
+        checkpoints.commit_acked(0).await.unwrap();
+        assert_eq!(checkpoints.load("a").await.unwrap(), None);
+        // items are marked with the positions before the one read last
+        checkpoints.commit_acked(2).await.unwrap();
+        assert_eq!(checkpoints.load("a").await.unwrap(), None);
+        checkpoints.commit_acked(3).await.unwrap();
+        assert_eq!(checkpoints.load("a").await.unwrap(), Some(at(2)));
+
+        checkpoints.stage("a", at(4));
+        checkpoints.commit().await.unwrap();
+        assert_eq!(checkpoints.load("a").await.unwrap(), Some(at(4)));
+        // nothing is left to commit for acknowledgements coming late
+        checkpoints.commit_acked(4).await.unwrap();
+        assert_eq!(checkpoints.load("a").await.unwrap(), Some(at(4)));
+    }
+
+    #[test]
+    fn acks_only_move_forward() {
+        let acks = Acks::default();
+        acks.acknowledge(3);
+        acks.clone().acknowledge(2);
+        assert_eq!(acks.count(), 3);
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use rusqlite::{Connection, OptionalExtension, params};
+
+use super::{Checkpoint, CheckpointStore, FileId};
+use crate::{Error, Result};
+
+/// Keeps checkpoints in a SQLite database, one row per key.
+pub struct SqliteStore {
+    path: PathBuf,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl SqliteStore {
+    /// The database is opened, and created if needed, when first used.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            connection: Mutex::new(None),
+        }
+    }
+
+    fn with_connection<R>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> rusqlite::Result<R>,
+    ) -> Result<R> {
+        let mut connection = self.connection.lock().unwrap();
+        if connection.is_none() {
+            let opened = Connection::open(&self.path).and_then(|connection| {
+                connection.execute(
+                    "CREATE TABLE IF NOT EXISTS checkpoints (
+                        key TEXT PRIMARY KEY,
+                        dev INTEGER NOT NULL,
+                        ino INTEGER NOT NULL,
+                        offset INTEGER NOT NULL
+                    )",
+                    [],
+                )?;
+                Ok(connection)
+            });
+            *connection = Some(opened.map_err(|e| self.error(e))?);
+        }
+        f(connection.as_mut().unwrap()).map_err(|e| self.error(e))
+    }
+
+    fn error(&self, e: rusqlite::Error) -> Error {
+        std::io::Error::other(format!("{}: {e}", self.path.display())).into()
+    }
+}
+
+impl CheckpointStore for SqliteStore {
+    fn load(&self, key: &str) -> Result<Option<Checkpoint>> {
+        self.with_connection(|connection| {
+            connection
+                .query_row(
+                    "SELECT dev, ino, offset FROM checkpoints WHERE key = ?1",
+                    params![key],
+                    |row| {
+                        Ok(Checkpoint {
+                            file: FileId {
+                                dev: row.get::<_, i64>(0)? as u64,
+                                ino: row.get::<_, i64>(1)? as u64,
+                            },
+                            offset: row.get::<_, i64>(2)? as u64,
+                        })
+                    },
+                )
+                .optional()
+        })
+    }
+
+    fn save(&self, checkpoints: &BTreeMap<String, Checkpoint>) -> Result<()> {
+        self.with_connection(|connection| {
+            let tx = connection.transaction()?;
+            for (key, checkpoint) in checkpoints {
+                tx.execute(
+                    "INSERT INTO checkpoints (key, dev, ino, offset) VALUES (?1, ?2, ?3, ?4)
+                     ON CONFLICT (key) DO UPDATE
+                     SET dev = excluded.dev, ino = excluded.ino, offset = excluded.offset",
+                    params![
+                        key,
+                        checkpoint.file.dev as i64,
+                        checkpoint.file.ino as i64,
+                        checkpoint.offset as i64
+                    ],
+                )?;
+            }
+            tx.commit()
+        })
+    }
+}
@@ -0,0 +1,641 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::path::PathBuf;
+
+use apache_avro::schema_compatibility::SchemaCompatibility;
+use apache_avro::{DeflateSettings, Schema, ZstandardSettings};
+use bytes::{Buf, BytesMut};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{Decoder, Encoder};
+use crate::fio::with_path;
+use crate::{Error, Result};
+
+/// Starts every object container file.
+const MAGIC: &[u8] = b"Obj\x01";
+
+const DEFAULT_BLOCK_SIZE: usize = 64 * 1024;
+
+fn default_block_size() -> usize {
+    DEFAULT_BLOCK_SIZE
+}
+
+/// How [`Avro`] compresses the blocks it writes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AvroCompression {
+    /// No compression, Avro's `null` codec.
+    #[default]
+    #[serde(alias = "none")]
+    Null,
+    Deflate,
+    Snappy,
+    Zstd,
+}
+
+impl From<AvroCompression> for apache_avro::Codec {
+    fn from(compression: AvroCompression) -> Self {
+        match compression {
+            AvroCompression::Null => Self::Null,
+            AvroCompression::Deflate => Self::Deflate(DeflateSettings::default()),
+            AvroCompression::Snappy => Self::Snappy,
+            AvroCompression::Zstd => Self::Zstandard(ZstandardSettings::default()),
+        }
+    }
+}
+
+/// What the header of a container file being read says.
+struct Header {
+    schema: Schema,
+    codec: apache_avro::Codec,
+    sync: [u8; 16],
+}
+
+/// Reads Avro's variable-length values from the front of a buffer, or
+/// returns `None` while the buffer does not hold all of one.
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn long(&mut self) -> Result<Option<i64>> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let Some(&byte) = self.buf.get(self.pos) else {
+                return Ok(None);
+            };
+            self.pos += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(Some((value >> 1) as i64 ^ -((value & 1) as i64)));
+            }
+        }
+        Err(Error::codec("invalid Avro long"))
+    }
+
+    fn len(&mut self) -> Result<Option<usize>> {
+        match self.long()? {
+            Some(n) => usize::try_from(n)
+                .map(Some)
+                .map_err(|_| Error::codec(format!("invalid Avro length {n}"))),
+            None => Ok(None),
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.buf.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+}
+
+/// Returns `None` from the enclosing function while a [`Cursor`] runs out.
+macro_rules! need {
+    ($e:expr) => {
+        match $e {
+            Some(value) => value,
+            None => return Ok(None),
+        }
+    };
+}
+
+fn write_long(n: i64, buf: &mut Vec<u8>) {
+    let mut n = ((n << 1) ^ (n >> 63)) as u64;
+    while n >= 0x80 {
+        buf.push((n & 0x7f) as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn write_bytes(bytes: &[u8], buf: &mut Vec<u8>) {
+    write_long(bytes.len() as i64, buf);
+    buf.extend_from_slice(bytes);
+}
+
+/// Parses a schema given as JSON text, as a JSON object, or by the name of a
+/// primitive type.
+fn parse_schema(schema: &Value) -> Result<Schema, String> {
+    match schema {
+        Value::String(text) if text.trim_start().starts_with(['{', '[', '"']) => {
+            Schema::parse_str(text)
+        }
+        schema => Schema::parse(schema),
+    }
+    .map_err(|e| format!("schema: {e}"))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AvroConfig {
+    #[serde(default)]
+    schema: Option<Value>,
+    #[serde(default)]
+    schema_file: Option<PathBuf>,
+    #[serde(default)]
+    compression: AvroCompression,
+    #[serde(default = "default_block_size")]
+    block_size: usize,
+}
+
+/// Avro object container files, with items deserialized into a `T` (by
+/// default, any [`Value`]).
+///
+/// Reading takes files compressed with `deflate`, `snappy` or `zstd`. Items
+/// are read with the writer's schema from the file header, or resolved into
+/// `schema` if one is given, which lets readers follow schema evolution:
+/// fields the writer added are dropped, fields it lacks take their default.
+///
+/// Writing needs a `schema`, given inline or in `schema_file`, and starts a
+/// new block every `block_size` bytes. Container files cannot be appended
+/// to, and inputs fail to resume from a checkpoint in the middle of one.
+#[derive(Deserialize)]
+#[serde(try_from = "AvroConfig", bound = "")]
+pub struct Avro<T = Value> {
+    schema: Option<Schema>,
+    compression: AvroCompression,
+    block_size: usize,
+    header: Option<Header>,
+    items: VecDeque<T>,
+    sync: Option<[u8; 16]>,
+    block: Vec<u8>,
+    count: i64,
+}
+
+impl<T> TryFrom<AvroConfig> for Avro<T> {
+    type Error = String;
+
+    fn try_from(config: AvroConfig) -> Result<Self, String> {
+        let schema = match (config.schema, config.schema_file) {
+            (Some(_), Some(_)) => return Err("either schema or schema_file, not both".into()),
+            (Some(schema), None) => Some(parse_schema(&schema)?),
+            (None, Some(path)) => {
+                let text = std::fs::read_to_string(&path)
+                    .map_err(with_path(&path))
+                    .map_err(|e| format!("schema_file: {e}"))?;
+                Some(parse_schema(&Value::String(text))?)
+            }
+            (None, None) => None,
+        };
+        let mut avro = Self::new().compression(config.compression);
+        avro.schema = schema;
+        avro.block_size = config.block_size.max(1);
+        Ok(avro)
+    }
+}
+
+impl<T> Avro<T> {
+    pub fn new() -> Self {
+        Self {
+            schema: None,
+            compression: AvroCompression::default(),
+            block_size: DEFAULT_BLOCK_SIZE,
+            header: None,
+            items: VecDeque::new(),
+            sync: None,
+            block: Vec::new(),
+            count: 0,
+        }
+    }
+
+    /// The schema to write with, and to resolve what is read into.
+    pub fn schema(mut self, schema: Schema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    pub fn compression(mut self, compression: AvroCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    /// Parses the file header at the front of `buf`, returning it with its
+    /// length.
+    fn header(&self, buf: &[u8]) -> Result<Option<(Header, usize)>> {
+        let mut cursor = Cursor { buf, pos: 0 };
+        if need!(cursor.bytes(MAGIC.len())) != MAGIC {
+            return Err(Error::codec("not an Avro container file"));
+        }
+        let (mut schema, mut codec) = (None, None);
+        loop {
+            let count = need!(cursor.long()?);
+            if count == 0 {
+                break;
+            }
+            if count < 0 {
+                // a negative count is followed by the size of the block
+                need!(cursor.long()?);
+            }
+            for _ in 0..count.unsigned_abs() {
+                let len = need!(cursor.len()?);
+                let key = need!(cursor.bytes(len));
+                let len = need!(cursor.len()?);
+                let value = need!(cursor.bytes(len));
+                match key {
+                    b"avro.schema" => schema = Some(value),
+                    b"avro.codec" => codec = Some(value),
+                    _ => {}
+                }
+            }
+        }
+        let sync = need!(cursor.bytes(16)).try_into().unwrap();
+
+        let schema = schema.ok_or_else(|| Error::codec("Avro file without a schema"))?;
+        let schema = std::str::from_utf8(schema)
+            .map_err(Error::codec)
+            .and_then(|schema| Schema::parse_str(schema).map_err(Error::codec))?;
+        if let Some(reader) = &self.schema {
+            SchemaCompatibility::can_read(&schema, reader).map_err(|e| {
+                Error::codec(format!(
+                    "cannot read the file's schema as the one given: {e}"
+                ))
+            })?;
+        }
+        let codec = match codec {
+            None | Some(b"") => apache_avro::Codec::Null,
+            Some(name) => String::from_utf8_lossy(name).parse().map_err(|_| {
+                Error::codec(format!(
+                    "unsupported Avro codec `{}`",
+                    String::from_utf8_lossy(name)
+                ))
+            })?,
+        };
+        let header = Header {
+            schema,
+            codec,
+            sync,
+        };
+        Ok(Some((header, cursor.pos)))
+    }
+
+    fn write_header(&mut self, schema: &Schema, buf: &mut Vec<u8>) -> Result<()> {
+        let codec: &str = apache_avro::Codec::from(self.compression).into();
+        let mut sync = [0; 16];
+        // sync markers only need to be unlikely to show up in the data
+        let state = std::collections::hash_map::RandomState::new();
+        for (i, chunk) in sync.chunks_mut(8).enumerate() {
+            let mut hasher = state.build_hasher();
+            hasher.write_usize(i);
+            hasher.write_u128(
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos(),
+            );
+            chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+        }
+        buf.extend_from_slice(MAGIC);
+        write_long(2, buf);
+        write_bytes(b"avro.schema", buf);
+        write_bytes(&serde_json::to_vec(schema).map_err(Error::codec)?, buf);
+        write_bytes(b"avro.codec", buf);
+        write_bytes(codec.as_bytes(), buf);
+        write_long(0, buf);
+        buf.extend_from_slice(&sync);
+        self.sync = Some(sync);
+        Ok(())
+    }
+
+    fn write_block(&mut self, buf: &mut Vec<u8>) -> Result<()> {
+        let Some(sync) = self.sync else {
+            return Ok(());
+        };
+        if self.count == 0 {
+            return Ok(());
+        }
+        let mut block = std::mem::take(&mut self.block);
+        apache_avro::Codec::from(self.compression)
+            .compress(&mut block)
+            .map_err(Error::codec)?;
+        write_long(self.count, buf);
+        write_bytes(&block, buf);
+        buf.extend_from_slice(&sync);
+        self.count = 0;
+        Ok(())
+    }
+}
+
+impl<T> Default for Avro<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Clones start over as for a new input or output.
+impl<T> Clone for Avro<T> {
+    fn clone(&self) -> Self {
+        let mut avro = Self::new()
+            .compression(self.compression)
+            .block_size(self.block_size);
+        avro.schema = self.schema.clone();
+        avro
+    }
+}
+
+impl<T> fmt::Debug for Avro<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Avro")
+            .field("schema", &self.schema)
+            .field("compression", &self.compression)
+            .field("block_size", &self.block_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: DeserializeOwned> Decoder for Avro<T> {
+    type Item = T;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<T>> {
+        loop {
+            if let Some(item) = self.items.pop_front() {
+                return Ok(Some(item));
+            }
+            let header = match &self.header {
+                Some(header) => header,
+                None => {
+                    let (header, len) = need!(self.header(buf)?);
+                    buf.advance(len);
+                    self.header.insert(header)
+                }
+            };
+            let mut cursor = Cursor { buf, pos: 0 };
+            let count = need!(cursor.len()?);
+            let len = need!(cursor.len()?);
+            let data = need!(cursor.bytes(len));
+            if need!(cursor.bytes(16)) != header.sync {
+                return Err(Error::codec("Avro block does not end in the sync marker"));
+            }
+            let mut data = data.to_vec();
+            let read = cursor.pos;
+            header.codec.decompress(&mut data).map_err(Error::codec)?;
+            let mut reader = data.as_slice();
+            for _ in 0..count {
+                let value =
+                    apache_avro::from_avro_datum(&header.schema, &mut reader, self.schema.as_ref())
+                        .map_err(Error::codec)?;
+                self.items
+                    .push_back(apache_avro::from_value(&value).map_err(Error::codec)?);
+            }
+            buf.advance(read);
+        }
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<T>> {
+        if let Some(item) = self.decode(buf)? {
+            return Ok(Some(item));
+        }
+        let left = buf.len();
+        self.header = None;
+        buf.clear();
+        if left > 0 {
+            return Err(Error::codec(format!(
+                "Avro file cut off, {left} bytes left at the end of the input"
+            )));
+        }
+        Ok(None)
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        Err(Error::config(
+            "Avro container files start with the schema and sync marker of their blocks, \
+             so they cannot be resumed",
+        ))
+    }
+}
+
+impl<T, U: Serialize + ?Sized> Encoder<U> for Avro<T> {
+    fn encode(&mut self, item: &U, buf: &mut Vec<u8>) -> Result<()> {
+        let schema = self
+            .schema
+            .clone()
+            .ok_or_else(|| Error::config("writing Avro needs a schema"))?;
+        if self.sync.is_none() {
+            self.write_header(&schema, buf)?;
+        }
+        let value = apache_avro::to_value(item)
+            .and_then(|value| value.resolve(&schema))
+            .map_err(Error::codec)?;
+        let datum = apache_avro::to_avro_datum(&schema, value).map_err(Error::codec)?;
+        self.block.extend_from_slice(&datum);
+        self.count += 1;
+        if self.block.len() >= self.block_size {
+            self.write_block(buf)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, buf: &mut Vec<u8>) -> Result<()> {
+        if let Some(schema) = self.schema.clone()
+            && self.sync.is_none()
+        {
+            self.write_header(&schema, buf)?;
+        }
+        self.write_block(buf)?;
+        self.sync = None;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        Err(Error::config(
+            "Avro container files hold a single header with the sync marker of their blocks, \
+             so they cannot be appended to",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use apache_avro::{Codec, Reader, Writer};
+    use serde_json::json;
+
+    use super::*;
+    use crate::codec::decode_in_chunks;
+
+    const SCHEMA: &str = r#"{
+        "type": "record",
+        "name": "Event",
+        "fields": [
+            {"name": "id", "type": "long"},
+            {"name": "name", "type": ["null", "string"]}
+        ]
+    }"#;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Event {
+        id: i64,
+        name: Option<String>,
+    }
+
+    fn events(n: i64) -> Vec<Event> {
+        (0..n)
+            .map(|id| Event {
+                id,
+                name: (id % 2 == 0).then(|| format!("event {id}")),
+            })
+            .collect()
+    }
+
+    fn schema() -> Schema {
+        Schema::parse_str(SCHEMA).unwrap()
+    }
+
+    fn encode(avro: &mut Avro, items: &[Event]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for item in items {
+            avro.encode(item, &mut buf).unwrap();
+        }
+        Encoder::<Event>::finish(avro, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn writes_files_apache_avro_reads() {
+        for compression in [
+            AvroCompression::Null,
+            AvroCompression::Deflate,
+            AvroCompression::Snappy,
+            AvroCompression::Zstd,
+        ] {
+            let mut avro = Avro::new()
+                .schema(schema())
+                .compression(compression)
+                .block_size(64);
+            let bytes = encode(&mut avro, &events(50));
+            let read: Vec<Event> = Reader::new(bytes.as_slice())
+                .unwrap()
+                .map(|value| apache_avro::from_value(&value.unwrap()).unwrap())
+                .collect();
+            assert_eq!(read, events(50), "{compression:?}");
+        }
+    }
+
+    #[test]
+    fn reads_files_apache_avro_writes() {
+        let schema = schema();
+        let mut writer = Writer::with_codec(
+            &schema,
+            Vec::new(),
+            Codec::Deflate(DeflateSettings::default()),
+        );
+        for (i, event) in events(30).iter().enumerate() {
+            writer.append_ser(event).unwrap();
+            // several blocks
+            if i % 7 == 6 {
+                writer.flush().unwrap();
+            }
+        }
+        let bytes = writer.into_inner().unwrap();
+        for chunk in [1, 13, bytes.len()] {
+            let read = decode_in_chunks(&mut Avro::<Event>::new(), &bytes, chunk).unwrap();
+            assert_eq!(read, events(30), "by {chunk}");
+        }
+    }
+
+    #[test]
+    fn resolves_items_into_the_reader_schema() {
+        let schema = schema();
+        let mut writer = Writer::new(&schema, Vec::new());
+        writer.extend_ser(events(3)).unwrap();
+        let bytes = writer.into_inner().unwrap();
+
+        let reader = Schema::parse_str(
+            r#"{
+                "type": "record",
+                "name": "Event",
+                "fields": [
+                    {"name": "id", "type": "long"},
+                    {"name": "source", "type": "string", "default": "unknown"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let read = decode_in_chunks(&mut Avro::<Value>::new().schema(reader), &bytes, 8).unwrap();
+        assert_eq!(read[1], json!({"id": 1, "source": "unknown"}));
+
+        let incompatible = Schema::parse_str(
+            r#"{"type": "record", "name": "Event", "fields": [{"name": "other", "type": "long"}]}"#,
+        )
+        .unwrap();
+        let e = decode_in_chunks(&mut Avro::<Value>::new().schema(incompatible), &bytes, 8)
+            .unwrap_err();
+        assert!(
+            e.to_string().contains("cannot read the file's schema"),
+            "{e}"
+        );
+    }
+
+    #[test]
+    fn fails_on_cut_off_and_foreign_files() {
+        let bytes = encode(&mut Avro::new().schema(schema()), &events(5));
+        let e =
+            decode_in_chunks(&mut Avro::<Event>::new(), &bytes[..bytes.len() - 3], 16).unwrap_err();
+        assert!(e.to_string().contains("cut off"), "{e}");
+        let e = decode_in_chunks(&mut Avro::<Event>::new(), b"not avro", 16).unwrap_err();
+        assert!(e.to_string().contains("not an Avro container file"), "{e}");
+    }
+
+    #[test]
+    fn needs_a_schema_to_write_and_refuses_to_resume() {
+        let e = Avro::<Value>::new()
+            .encode(&json!({"id": 1}), &mut Vec::new())
+            .unwrap_err();
+        assert!(e.to_string().contains("needs a schema"), "{e}");
+        assert!(Decoder::resume(&mut Avro::<Value>::new()).is_err());
+    }
+
+    #[tokio::test]
+    async fn refuses_to_append_to_files_that_hold_items() {
+        use crate::Output as _;
+        use crate::codec::Encode;
+        use crate::fio::{Output, WriteMode};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.avro");
+        let write = |output: Output, items: Vec<Event>| async move {
+            Encode::new(output, Avro::<Value>::new().schema(schema()))
+                .output(futures::stream::iter(items))
+                .await
+        };
+        let append = || Output::new(&path).mode(WriteMode::Append);
+        // an empty file is written from the start
+        std::fs::write(&path, b"").unwrap();
+        write(append(), events(2)).await.unwrap();
+        let written = std::fs::read(&path).unwrap();
+        let e = write(append(), events(1)).await.unwrap_err();
+        assert!(e.to_string().contains("cannot append to"), "{e}");
+        assert_eq!(std::fs::read(&path).unwrap(), written);
+        let read = decode_in_chunks(&mut Avro::<Event>::new(), &written, 64).unwrap();
+        assert_eq!(read, events(2));
+
+        // nor to partitions closed to make room for others
+        let partitioned = Output::new(dir.path().join("part.avro"))
+            .partition_by(["id"])
+            .max_open_files(1);
+        let mut items = events(2);
+        items.push(Event { id: 0, name: None });
+        let e = write(partitioned, items).await.unwrap_err();
+        assert!(e.to_string().contains("max_open_files"), "{e}");
+        let first = std::fs::read(dir.path().join("id=0/part.avro")).unwrap();
+        let read = decode_in_chunks(&mut Avro::<Event>::new(), &first, 64).unwrap();
+        assert_eq!(read, events(1));
+    }
+
+    #[test]
+    fn takes_schemas_from_config() {
+        let avro: Avro =
+            serde_json::from_value(json!({"schema": SCHEMA, "compression": "zstd"})).unwrap();
+        assert_eq!(avro.schema, Some(schema()));
+        let e = serde_json::from_value::<Avro>(json!({"schema": "{", "schema_file": "x.avsc"}))
+            .unwrap_err();
+        assert!(e.to_string().contains("not both"), "{e}");
+    }
+}
@@ -0,0 +1,304 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use bytes::BytesMut;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::framing::{Framing, Head, Nested, Scan, argument, covering};
+use super::{Decoder, Encoder};
+use crate::{Error, Result};
+
+/// CBOR values, deserialized into a `T` (by default, any [`Value`]).
+///
+/// Items are framed according to `framing`. Errors for malformed items name
+/// the item number within the current input, counting from 1.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, bound = "")]
+pub struct Cbor<T = Value> {
+    #[serde(default)]
+    framing: Framing,
+    #[serde(skip)]
+    decoded: u64,
+    #[serde(skip)]
+    scan: Scan,
+    #[serde(skip)]
+    item: PhantomData<fn() -> T>,
+}
+
+impl<T> Cbor<T> {
+    pub fn new() -> Self {
+        Self {
+            framing: Framing::default(),
+            decoded: 0,
+            scan: Scan::default(),
+            item: PhantomData,
+        }
+    }
+
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+}
+
+/// Reads the head of the CBOR value at the front of `bytes`.
+fn head(bytes: &[u8]) -> Result<Option<Head>, String> {
+    let Some(&first) = bytes.first() else {
+        return Ok(None);
+    };
+    let (major, info) = (first >> 5, first & 0x1f);
+    let invalid = || format!("invalid CBOR head {first:#04x}");
+    let size = match info {
+        0..=23 => 0,
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        31 => {
+            let nested = match major {
+                2..=5 => Nested::UntilBreak,
+                7 => Nested::Break,
+                _ => return Err(invalid()),
+            };
+            return Ok(Some(Head { len: 1, nested }));
+        }
+        _ => return Err(invalid()),
+    };
+    let argument = match size {
+        0 => u64::from(info),
+        size => match argument(bytes, size) {
+            Some(argument) => argument,
+            None => return Ok(None),
+        },
+    };
+    let len = 1 + size;
+    let head = match major {
+        // strings
+        2 | 3 => Head {
+            len: covering(len, argument)?,
+            nested: Nested::Values(0),
+        },
+        4 => Head {
+            len,
+            nested: Nested::Values(argument),
+        },
+        // maps, of keys and values
+        5 => Head {
+            len,
+            nested: Nested::Values(argument.checked_mul(2).ok_or_else(invalid)?),
+        },
+        // tags, of the value after them
+        6 => Head {
+            len,
+            nested: Nested::Values(1),
+        },
+        _ => Head {
+            len,
+            nested: Nested::Values(0),
+        },
+    };
+    Ok(Some(head))
+}
+
+impl<T> Default for Cbor<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Clones start over at item 1.
+impl<T> Clone for Cbor<T> {
+    fn clone(&self) -> Self {
+        Self::new().framing(self.framing)
+    }
+}
+
+impl<T> fmt::Debug for Cbor<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cbor")
+            .field("framing", &self.framing)
+            .field("decoded", &self.decoded)
+            .finish()
+    }
+}
+
+impl<T: DeserializeOwned> Decoder for Cbor<T> {
+    type Item = T;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<T>> {
+        let n = self.decoded + 1;
+        let Some(bytes) = self
+            .framing
+            .split(buf, &mut self.scan, head)
+            .map_err(|e| Error::codec(format!("item {n}: {e}")))?
+        else {
+            return Ok(None);
+        };
+        self.decoded = n;
+        ciborium::from_reader(bytes.as_ref())
+            .map(Some)
+            .map_err(|e| Error::codec(format!("item {n}: {e}")))
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<T>> {
+        if let Some(item) = self.decode(buf)? {
+            return Ok(Some(item));
+        }
+        let left = buf.len();
+        self.decoded = 0;
+        self.scan = Scan::default();
+        buf.clear();
+        if left > 0 {
+            return Err(Error::codec(format!(
+                "CBOR item cut off, {left} bytes left at the end of the input"
+            )));
+        }
+        Ok(None)
+    }
+}
+
+impl<T, U: Serialize + ?Sized> Encoder<U> for Cbor<T> {
+    fn encode(&mut self, item: &U, buf: &mut Vec<u8>) -> Result<()> {
+        self.framing.write(buf, |buf| {
+            ciborium::into_writer(item, buf).map_err(|e| Error::codec(e.to_string()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::codec::decode_in_chunks;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Event {
+        id: u64,
+        tags: Vec<String>,
+        score: Option<f64>,
+    }
+
+    fn events() -> Vec<Event> {
+        (0..5)
+            .map(|id| Event {
+                id,
+                tags: vec!["a".repeat(id as usize); 2],
+                score: (id % 2 == 1).then_some(id as f64 / 2.0),
+            })
+            .collect()
+    }
+
+    fn encode(codec: &mut Cbor, items: &[Event]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for item in items {
+            codec.encode(item, &mut buf).unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn round_trips_in_both_framings_and_any_chunks() {
+        for framing in [Framing::Concatenated, Framing::LengthPrefixed] {
+            let bytes = encode(&mut Cbor::new().framing(framing), &events());
+            for chunk in [1, 5, bytes.len()] {
+                let read =
+                    decode_in_chunks(&mut Cbor::<Event>::new().framing(framing), &bytes, chunk)
+                        .unwrap();
+                assert_eq!(read, events(), "{framing:?} by {chunk}");
+            }
+        }
+    }
+
+    #[test]
+    fn writes_what_the_reference_library_reads() {
+        let bytes = encode(&mut Cbor::new(), &events()[1..2]);
+        let read: Event = ciborium::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(read, events()[1]);
+        let dynamic = decode_in_chunks(&mut Cbor::<Value>::new(), &bytes, 3).unwrap();
+        assert_eq!(
+            dynamic,
+            [json!({"id": 1, "tags": ["a", "a"], "score": 0.5})]
+        );
+    }
+
+    #[test]
+    fn reads_what_the_reference_library_writes() {
+        let bytes: Vec<u8> = events()
+            .iter()
+            .flat_map(|item| {
+                let mut buf = Vec::new();
+                ciborium::into_writer(item, &mut buf).unwrap();
+                buf
+            })
+            .collect();
+        assert_eq!(
+            decode_in_chunks(&mut Cbor::<Event>::new(), &bytes, 2).unwrap(),
+            events()
+        );
+    }
+
+    #[test]
+    fn names_the_malformed_item() {
+        let mut bytes = encode(&mut Cbor::new(), &events()[..2]);
+        // the last item loses its last byte
+        bytes.truncate(bytes.len() - 1);
+        let e = decode_in_chunks(&mut Cbor::<Event>::new(), &bytes, 4).unwrap_err();
+        assert!(e.to_string().contains("CBOR item cut off"), "{e}");
+
+        let bytes = encode(
+            &mut Cbor::new().framing(Framing::LengthPrefixed),
+            &events()[..2],
+        );
+        let e = decode_in_chunks(
+            &mut Cbor::<Vec<u64>>::new().framing(Framing::LengthPrefixed),
+            &bytes,
+            64,
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains("item 1:"), "{e}");
+    }
+
+    fn values() -> Vec<Value> {
+        vec![
+            json!(null),
+            json!([true, false, -1, -1000, 70000, u64::MAX, i64::MIN, 1.5]),
+            json!({"short": "a", "long": "b".repeat(300), "longer": "c".repeat(70_000)}),
+            json!((0..30).collect::<Vec<_>>()),
+            json!([[], {}, [[[]]], {"a": {"b": []}}]),
+        ]
+    }
+
+    #[test]
+    fn tells_where_every_kind_of_value_ends() {
+        let bytes: Vec<u8> = values()
+            .iter()
+            .flat_map(|value| {
+                let mut buf = Vec::new();
+                ciborium::into_writer(value, &mut buf).unwrap();
+                buf
+            })
+            .collect();
+        for chunk in [1, 7, bytes.len()] {
+            let read = decode_in_chunks(&mut Cbor::<Value>::new(), &bytes, chunk).unwrap();
+            assert_eq!(read, values(), "by {chunk}");
+        }
+    }
+
+    #[test]
+    fn tells_where_values_of_indefinite_length_end() {
+        let bytes = [
+            0x9f, 0x01, 0x82, 0x02, 0x03, 0xff, // [1, [2, 3]]
+            0x7f, 0x61, b'a', 0x61, b'b', 0xff, // "ab"
+            0xbf, 0x61, b'k', 0x9f, 0xff, 0xff, // {"k": []}
+        ];
+        let read = decode_in_chunks(&mut Cbor::<Value>::new(), &bytes, 1).unwrap();
+        assert_eq!(read, [json!([1, [2, 3]]), json!("ab"), json!({"k": []})]);
+        let e = decode_in_chunks(&mut Cbor::<Value>::new(), &[0x01, 0xff], 1).unwrap_err();
+        assert!(e.to_string().contains("item 2: break"), "{e}");
+        let e = decode_in_chunks(&mut Cbor::<Value>::new(), &[0x1c], 1).unwrap_err();
+        assert!(e.to_string().contains("invalid CBOR head 0x1c"), "{e}");
+    }
+}
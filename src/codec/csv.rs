@@ -0,0 +1,1148 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use bytes::{Buf, BytesMut};
+use csv_core::ReadRecordResult;
+use serde::de::value::{Error as DeError, MapDeserializer, SeqDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::ser::{self, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, forward_to_deserialize_any};
+use serde_json::Value;
+
+use super::{Decoder, Encoder};
+use crate::{Error, Result};
+
+/// Whether the first row of a [`Csv`] input names the columns.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Header {
+    #[default]
+    Present,
+    Absent,
+    /// Treat the first row as a header if its fields are distinct, not
+    /// empty, and none of them is a number.
+    Detect,
+}
+
+fn ascii<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let c = char::deserialize(deserializer)?;
+    u8::try_from(c)
+        .ok()
+        .filter(u8::is_ascii)
+        .ok_or_else(|| de::Error::custom(format!("`{c}` is not an ASCII character")))
+}
+
+fn comma() -> u8 {
+    b','
+}
+
+fn double_quote() -> u8 {
+    b'"'
+}
+
+fn yes() -> bool {
+    true
+}
+
+/// Comma-separated values, or any other delimiter, such as tabs for TSV.
+///
+/// Rows are decoded into a `T` (by default, a [`Value`]) by column name if
+/// there is a header row or `columns` are given, and by position otherwise:
+/// dynamic records then are objects or arrays, respectively. With
+/// `infer_types`, fields read as dynamic values turn into booleans, numbers
+/// without leading zeros and, when empty, nulls; otherwise they stay
+/// strings. Typed fields are
+/// parsed from their text either way.
+///
+/// Items are encoded from their serialized form: objects are written in the
+/// order of `columns`, after a header row unless `header` is `absent`, and
+/// fields not among them are left out. Without `columns`, the fields of the
+/// first item name the columns, in the order it serializes them in (sorted,
+/// for a [`Value`]), and later items with other fields fail to encode.
+/// Arrays are written as they are, without a header.
+///
+/// A file input resuming from a checkpoint reads the first row again from
+/// the start of the file before moving on to the checkpoint, so that columns
+/// are named the same as before.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, bound = "")]
+pub struct Csv<T = Value> {
+    #[serde(default = "comma", deserialize_with = "ascii")]
+    delimiter: u8,
+    #[serde(default = "double_quote", deserialize_with = "ascii")]
+    quote: u8,
+    #[serde(default = "yes")]
+    quoting: bool,
+    #[serde(default)]
+    header: Header,
+    #[serde(default)]
+    columns: Vec<String>,
+    #[serde(default = "yes")]
+    infer_types: bool,
+    /// Allow rows with more or fewer fields than the header or first row.
+    #[serde(default)]
+    flexible: bool,
+    #[serde(skip)]
+    read: ReadState,
+    #[serde(skip)]
+    write: WriteState,
+    #[serde(skip)]
+    item: PhantomData<fn() -> T>,
+}
+
+#[derive(Default)]
+struct ReadState {
+    reader: Option<csv_core::Reader>,
+    /// The fields of the row being read, and where each of them ends.
+    fields: Vec<u8>,
+    ends: Vec<usize>,
+    len: usize,
+    count: usize,
+    /// The line the row being read starts at.
+    line: u64,
+    /// Whether the first row was seen, and what it said about the columns.
+    started: bool,
+    names: Option<Vec<String>>,
+    width: Option<usize>,
+}
+
+#[derive(Default)]
+struct WriteState {
+    columns: Option<Vec<String>>,
+    started: bool,
+}
+
+impl<T> Csv<T> {
+    pub fn new() -> Self {
+        Self {
+            delimiter: comma(),
+            quote: double_quote(),
+            quoting: true,
+            header: Header::default(),
+            columns: Vec::new(),
+            infer_types: true,
+            flexible: false,
+            read: ReadState::default(),
+            write: WriteState::default(),
+            item: PhantomData,
+        }
+    }
+
+    /// Tab-separated values.
+    pub fn tsv() -> Self {
+        Self::new().delimiter(b'\t')
+    }
+
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    pub fn quote(mut self, quote: u8) -> Self {
+        self.quote = quote;
+        self
+    }
+
+    /// Whether quotes are special at all.
+    pub fn quoting(mut self, quoting: bool) -> Self {
+        self.quoting = quoting;
+        self
+    }
+
+    pub fn header(mut self, header: Header) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// The names of the columns, in order, overriding a header row.
+    pub fn columns<I, S>(mut self, columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.columns = columns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn infer_types(mut self, infer_types: bool) -> Self {
+        self.infer_types = infer_types;
+        self
+    }
+
+    pub fn flexible(mut self, flexible: bool) -> Self {
+        self.flexible = flexible;
+        self
+    }
+
+    /// Reads the next row, or returns `None` while `buf` ends within one.
+    /// Once the input has ended, returns `None` when all rows were read.
+    fn read_row(&mut self, buf: &mut BytesMut, eof: bool) -> Result<Option<Vec<String>>> {
+        let (delimiter, quote, quoting) = (self.delimiter, self.quote, self.quoting);
+        let state = &mut self.read;
+        let reader = state.reader.get_or_insert_with(|| {
+            csv_core::ReaderBuilder::new()
+                .delimiter(delimiter)
+                .quote(quote)
+                .quoting(quoting)
+                .build()
+        });
+        loop {
+            if state.len == 0 && state.count == 0 {
+                state.line = reader.line();
+            }
+            if state.len == state.fields.len() {
+                state.fields.resize((state.fields.len() * 2).max(1024), 0);
+            }
+            if state.count == state.ends.len() {
+                state.ends.resize((state.ends.len() * 2).max(16), 0);
+            }
+            if buf.is_empty() && !eof {
+                return Ok(None);
+            }
+            let (result, read, written, ended) = reader.read_record(
+                buf,
+                &mut state.fields[state.len..],
+                &mut state.ends[state.count..],
+            );
+            buf.advance(read);
+            state.len += written;
+            state.count += ended;
+            match result {
+                ReadRecordResult::InputEmpty
+                | ReadRecordResult::OutputFull
+                | ReadRecordResult::OutputEndsFull => {}
+                ReadRecordResult::Record => {
+                    let mut start = 0;
+                    let row = state.ends[..std::mem::take(&mut state.count)]
+                        .iter()
+                        .map(|&end| {
+                            let field = std::str::from_utf8(&state.fields[start..end]);
+                            start = end;
+                            field.map(str::to_string)
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|e| Error::codec(format!("line {}: {e}", state.line)));
+                    state.len = 0;
+                    return row.map(Some);
+                }
+                ReadRecordResult::End => return Ok(None),
+            }
+        }
+    }
+
+    /// Whether `row` looks like a header rather than data.
+    fn looks_like_header(row: &[String]) -> bool {
+        let mut seen = std::collections::HashSet::new();
+        row.iter().all(|field| {
+            !field.is_empty() && field.parse::<f64>().is_err() && seen.insert(field.as_str())
+        })
+    }
+
+    /// Takes what the first row of an input says about the columns, and
+    /// returns whether it is the header.
+    fn start(&mut self, row: &[String]) -> bool {
+        let state = &mut self.read;
+        state.started = true;
+        let header = match self.header {
+            Header::Present => true,
+            Header::Absent => false,
+            Header::Detect => Self::looks_like_header(row),
+        };
+        if !self.columns.is_empty() {
+            state.names = Some(self.columns.clone());
+        } else if header {
+            state.names = Some(row.to_vec());
+        }
+        state.width = Some(state.names.as_ref().map_or(row.len(), Vec::len));
+        header
+    }
+}
+
+impl<T: DeserializeOwned> Csv<T> {
+    /// Turns `row` into an item, or returns `None` if it is the header.
+    fn item(&mut self, row: Vec<String>) -> Result<Option<T>> {
+        if !self.read.started && self.start(&row) {
+            return Ok(None);
+        }
+        let state = &self.read;
+        let line = state.line;
+        if let Some(width) = state.width
+            && !self.flexible
+            && row.len() != width
+        {
+            return Err(Error::codec(format!(
+                "line {line}: expected {width} fields, found {}",
+                row.len()
+            )));
+        }
+        let row = Row {
+            fields: &row,
+            names: state.names.as_deref(),
+            infer: self.infer_types,
+        };
+        T::deserialize(row)
+            .map(Some)
+            .map_err(|e| Error::codec(format!("line {line}: {e}")))
+    }
+}
+
+impl<T> Default for Csv<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Clones start over, as if nothing was read or written yet.
+impl<T> Clone for Csv<T> {
+    fn clone(&self) -> Self {
+        Self {
+            delimiter: self.delimiter,
+            quote: self.quote,
+            quoting: self.quoting,
+            header: self.header,
+            columns: self.columns.clone(),
+            infer_types: self.infer_types,
+            flexible: self.flexible,
+            read: ReadState::default(),
+            write: WriteState::default(),
+            item: PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for Csv<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Csv")
+            .field("delimiter", &char::from(self.delimiter))
+            .field("quote", &char::from(self.quote))
+            .field("quoting", &self.quoting)
+            .field("header", &self.header)
+            .field("columns", &self.columns)
+            .field("infer_types", &self.infer_types)
+            .field("flexible", &self.flexible)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: DeserializeOwned> Decoder for Csv<T> {
+    type Item = T;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<T>> {
+        while let Some(row) = self.read_row(buf, false)? {
+            if let Some(item) = self.item(row)? {
+                return Ok(Some(item));
+            }
+        }
+        Ok(None)
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<T>> {
+        while let Some(row) = self.read_row(buf, true)? {
+            if let Some(item) = self.item(row)? {
+                return Ok(Some(item));
+            }
+        }
+        self.read = ReadState::default();
+        Ok(None)
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        // the first row was read with read_start
+        if self.read.started {
+            return Ok(());
+        }
+        if self.columns.is_empty() && self.header != Header::Absent {
+            return Err(Error::config(
+                "the columns of a CSV input are named in its header row, \
+                 which this input cannot read again; set `columns`",
+            ));
+        }
+        let state = &mut self.read;
+        state.started = true;
+        if !self.columns.is_empty() {
+            state.names = Some(self.columns.clone());
+            state.width = Some(self.columns.len());
+        }
+        Ok(())
+    }
+
+    fn read_start(&mut self, buf: &mut BytesMut, eof: bool) -> Result<bool> {
+        match self.read_row(buf, eof)? {
+            Some(row) => {
+                self.start(&row);
+            }
+            None if !eof => return Ok(false),
+            // an empty input says nothing about the columns
+            None => {}
+        }
+        // rows are read from where the input resumes
+        let state = std::mem::take(&mut self.read);
+        self.read = ReadState {
+            started: state.started,
+            names: state.names,
+            width: state.width,
+            ..ReadState::default()
+        };
+        Ok(true)
+    }
+
+    fn reads_lines(&self) -> bool {
+        true
+    }
+}
+
+impl<T> Csv<T> {
+    fn write_row<'a, I>(&self, fields: I, buf: &mut Vec<u8>)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        for (i, field) in fields.into_iter().enumerate() {
+            if i > 0 {
+                buf.push(self.delimiter);
+            }
+            let special =
+                |b: &u8| matches!(*b, b'\n' | b'\r') || *b == self.delimiter || *b == self.quote;
+            if self.quoting && field.as_bytes().iter().any(special) {
+                buf.push(self.quote);
+                for &b in field.as_bytes() {
+                    if b == self.quote {
+                        buf.push(b);
+                    }
+                    buf.push(b);
+                }
+                buf.push(self.quote);
+            } else {
+                buf.extend_from_slice(field.as_bytes());
+            }
+        }
+        buf.push(b'\n');
+    }
+}
+
+fn field_text(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+    }
+}
+
+impl<T> Csv<T> {
+    /// The fields of a row from the entries of an object, after the header
+    /// row if none was written yet.
+    fn named_fields(
+        &mut self,
+        entries: Vec<(String, Value)>,
+        buf: &mut Vec<u8>,
+    ) -> Result<Vec<String>> {
+        let columns = self.write.columns.get_or_insert_with(|| {
+            if self.columns.is_empty() {
+                entries.iter().map(|(key, _)| key.clone()).collect()
+            } else {
+                self.columns.clone()
+            }
+        });
+        // fields beyond explicit columns are left out on purpose, but ones
+        // missing from columns taken from the first item would go unnoticed
+        if self.columns.is_empty()
+            && let Some((key, _)) = entries.iter().find(|(key, _)| !columns.contains(key))
+        {
+            return Err(Error::codec(format!(
+                "field `{key}` is not among the columns of the first item; set `columns`"
+            )));
+        }
+        let fields = columns
+            .iter()
+            .map(|column| {
+                field_text(
+                    entries
+                        .iter()
+                        .find(|(key, _)| key == column)
+                        .map(|(_, value)| value),
+                )
+            })
+            .collect();
+        if !self.write.started && self.header != Header::Absent {
+            let columns = self.write.columns.as_deref().unwrap_or_default();
+            self.write_row(columns.iter().map(String::as_str), buf);
+        }
+        Ok(fields)
+    }
+}
+
+impl<T, U: Serialize + ?Sized> Encoder<U> for Csv<T> {
+    fn encode(&mut self, item: &U, buf: &mut Vec<u8>) -> Result<()> {
+        let fields = match item.serialize(EntriesSerializer).map_err(Error::codec)? {
+            Some(entries) => self.named_fields(entries, buf)?,
+            None => match serde_json::to_value(item).map_err(Error::codec)? {
+                Value::Object(object) => self.named_fields(object.into_iter().collect(), buf)?,
+                Value::Array(values) => values.iter().map(|v| field_text(Some(v))).collect(),
+                other => vec![field_text(Some(&other))],
+            },
+        };
+        self.write.started = true;
+        self.write_row(fields.iter().map(String::as_str), buf);
+        Ok(())
+    }
+
+    fn finish(&mut self, _buf: &mut Vec<u8>) -> Result<()> {
+        self.write = WriteState::default();
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.write.started = true;
+        Ok(())
+    }
+}
+
+/// Serializes structs and maps into their entries, in the order they are
+/// serialized in, which a [`Value`] does not keep. Anything else serializes
+/// to `None`.
+struct EntriesSerializer;
+
+/// Collects the entries of a struct or map.
+#[derive(Default)]
+struct Entries {
+    entries: Vec<(String, Value)>,
+    key: Option<String>,
+}
+
+/// Ignores the elements of anything [`EntriesSerializer`] does not collect.
+struct NotEntries;
+
+type EntriesResult = Result<Option<Vec<(String, Value)>>, serde_json::Error>;
+
+macro_rules! not_entries {
+    ($($serialize:ident($($ty:ty),*),)*) => {
+        $(
+            fn $serialize(self, $(_: $ty),*) -> EntriesResult {
+                Ok(None)
+            }
+        )*
+    };
+}
+
+impl Serializer for EntriesSerializer {
+    type Ok = Option<Vec<(String, Value)>>;
+    type Error = serde_json::Error;
+    type SerializeSeq = NotEntries;
+    type SerializeTuple = NotEntries;
+    type SerializeTupleStruct = NotEntries;
+    type SerializeTupleVariant = NotEntries;
+    type SerializeMap = Entries;
+    type SerializeStruct = Entries;
+    type SerializeStructVariant = NotEntries;
+
+    not_entries! {
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_i128(i128),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_u128(u128),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+        serialize_none(),
+        serialize_unit(),
+        serialize_unit_struct(&'static str),
+        serialize_unit_variant(&'static str, u32, &'static str),
+    }
+
+    fn serialize_some<V: Serialize + ?Sized>(self, value: &V) -> EntriesResult {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<V: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &V,
+    ) -> EntriesResult {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<V: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &V,
+    ) -> EntriesResult {
+        Ok(None)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<NotEntries, serde_json::Error> {
+        Ok(NotEntries)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<NotEntries, serde_json::Error> {
+        Ok(NotEntries)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<NotEntries, serde_json::Error> {
+        Ok(NotEntries)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<NotEntries, serde_json::Error> {
+        Ok(NotEntries)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Entries, serde_json::Error> {
+        Ok(Entries::default())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Entries, serde_json::Error> {
+        Ok(Entries::default())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<NotEntries, serde_json::Error> {
+        Ok(NotEntries)
+    }
+}
+
+impl ser::SerializeMap for Entries {
+    type Ok = Option<Vec<(String, Value)>>;
+    type Error = serde_json::Error;
+
+    fn serialize_key<K: Serialize + ?Sized>(&mut self, key: &K) -> Result<(), serde_json::Error> {
+        self.key = Some(match serde_json::to_value(key)? {
+            Value::String(key) => key,
+            key => key.to_string(),
+        });
+        Ok(())
+    }
+
+    fn serialize_value<V: Serialize + ?Sized>(
+        &mut self,
+        value: &V,
+    ) -> Result<(), serde_json::Error> {
+        let key = self.key.take().unwrap_or_default();
+        self.entries.push((key, serde_json::to_value(value)?));
+        Ok(())
+    }
+
+    fn end(self) -> EntriesResult {
+        Ok(Some(self.entries))
+    }
+}
+
+impl ser::SerializeStruct for Entries {
+    type Ok = Option<Vec<(String, Value)>>;
+    type Error = serde_json::Error;
+
+    fn serialize_field<V: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &V,
+    ) -> Result<(), serde_json::Error> {
+        self.entries
+            .push((key.to_string(), serde_json::to_value(value)?));
+        Ok(())
+    }
+
+    fn end(self) -> EntriesResult {
+        Ok(Some(self.entries))
+    }
+}
+
+macro_rules! ignore_elements {
+    ($($trait:ident::$serialize:ident($($ty:ty),*),)*) => {
+        $(
+            impl ser::$trait for NotEntries {
+                type Ok = Option<Vec<(String, Value)>>;
+                type Error = serde_json::Error;
+
+                fn $serialize<V: Serialize + ?Sized>(
+                    &mut self,
+                    $(_: $ty,)*
+                    _value: &V,
+                ) -> Result<(), serde_json::Error> {
+                    Ok(())
+                }
+
+                fn end(self) -> EntriesResult {
+                    Ok(None)
+                }
+            }
+        )*
+    };
+}
+
+ignore_elements! {
+    SerializeSeq::serialize_element(),
+    SerializeTuple::serialize_element(),
+    SerializeTupleStruct::serialize_field(),
+    SerializeTupleVariant::serialize_field(),
+    SerializeStructVariant::serialize_field(&'static str),
+}
+
+/// Deserializes a row, as a map if its columns have names and as a
+/// sequence otherwise.
+struct Row<'a> {
+    fields: &'a [String],
+    names: Option<&'a [String]>,
+    infer: bool,
+}
+
+impl<'a> Row<'a> {
+    fn fields(&self) -> impl Iterator<Item = Field<'a>> + use<'a> {
+        let infer = self.infer;
+        self.fields.iter().map(move |value| Field { value, infer })
+    }
+
+    fn visit_seq<'de, V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        let mut seq = SeqDeserializer::new(self.fields());
+        let value = visitor.visit_seq(&mut seq)?;
+        seq.end()?;
+        Ok(value)
+    }
+
+    fn visit_map<'de, V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        let Some(names) = self.names else {
+            return Err(de::Error::custom(
+                "rows without column names cannot be maps",
+            ));
+        };
+        let entries = names.iter().map(String::as_str).zip(self.fields());
+        visitor.visit_map(MapDeserializer::new(entries))
+    }
+}
+
+impl<'de> Deserializer<'de> for Row<'_> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self.names {
+            Some(_) => self.visit_map(visitor),
+            None => self.visit_seq(visitor),
+        }
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.visit_map(visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.visit_seq(visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        self.visit_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        self.visit_seq(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct struct enum identifier ignored_any
+    }
+}
+
+/// Deserializes a single field from its text.
+struct Field<'a> {
+    value: &'a str,
+    infer: bool,
+}
+
+impl Field<'_> {
+    fn parse<T: std::str::FromStr>(&self) -> Result<T, DeError>
+    where
+        T::Err: fmt::Display,
+    {
+        self.value
+            .trim()
+            .parse()
+            .map_err(|e| de::Error::custom(format!("`{}`: {e}", self.value)))
+    }
+}
+
+impl<'de> IntoDeserializer<'de, DeError> for Field<'_> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! parse_field {
+    ($($deserialize:ident => $visit:ident,)*) => {
+        $(
+            fn $deserialize<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+                visitor.$visit(self.parse()?)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Field<'_> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        if !self.infer {
+            return visitor.visit_str(self.value);
+        }
+        let numeric = self
+            .value
+            .bytes()
+            .all(|b| b.is_ascii_digit() || matches!(b, b'-' | b'+' | b'.' | b'e' | b'E'));
+        // leading zeros mark codes such as ZIP codes rather than numbers
+        let digits = self.value.trim_start_matches('-').as_bytes();
+        if digits.len() > 1 && digits[0] == b'0' && digits[1].is_ascii_digit() {
+            return visitor.visit_str(self.value);
+        }
+        match self.value {
+            "" => visitor.visit_unit(),
+            "true" => visitor.visit_bool(true),
+            "false" => visitor.visit_bool(false),
+            value => match (
+                value.parse::<i64>(),
+                value.parse::<u64>(),
+                value.parse::<f64>(),
+            ) {
+                (Ok(n), _, _) => visitor.visit_i64(n),
+                (_, Ok(n), _) => visitor.visit_u64(n),
+                (_, _, Ok(n)) if numeric => visitor.visit_f64(n),
+                _ => visitor.visit_str(value),
+            },
+        }
+    }
+
+    parse_field! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_str(self.value)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_str(self.value)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        if self.value.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        visitor.visit_enum(self.value.into_deserializer())
+    }
+
+    forward_to_deserialize_any! {
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+
+    fn decode_all<T: DeserializeOwned>(csv: &mut Csv<T>, input: &str) -> Result<Vec<T>> {
+        let mut buf = BytesMut::new();
+        let mut items = Vec::new();
+        // feed a byte at a time, so that rows and quoted fields are split
+        for &b in input.as_bytes() {
+            buf.extend_from_slice(&[b]);
+            while let Some(item) = csv.decode(&mut buf)? {
+                items.push(item);
+            }
+        }
+        while let Some(item) = csv.decode_eof(&mut buf)? {
+            items.push(item);
+        }
+        Ok(items)
+    }
+
+    fn encode_all<U: Serialize>(csv: &mut Csv, items: &[U]) -> Result<String> {
+        let mut buf = Vec::new();
+        for item in items {
+            csv.encode(item, &mut buf)?;
+        }
+        Encoder::<U>::finish(csv, &mut buf)?;
+        Ok(String::from_utf8(buf).unwrap())
+    }
+
+    #[test]
+    fn reads_quoted_fields_across_chunks() {
+        let input = "name,note\r\nann,\"says \"\"hi\"\",\nthen leaves\"\nbob,\n";
+        let items = decode_all(&mut Csv::<Value>::new(), input).unwrap();
+        assert_eq!(
+            items,
+            [
+                json!({"name": "ann", "note": "says \"hi\",\nthen leaves"}),
+                json!({"name": "bob", "note": null}),
+            ]
+        );
+    }
+
+    #[test]
+    fn infers_types_unless_asked_not_to() {
+        let input = "a,b,c,d,e\n1,-2.5,true,007,x1\n";
+        let items = decode_all(&mut Csv::<Value>::new(), input).unwrap();
+        assert_eq!(
+            items,
+            [json!({"a": 1, "b": -2.5, "c": true, "d": "007", "e": "x1"})]
+        );
+        let items = decode_all(&mut Csv::<Value>::new().infer_types(false), input).unwrap();
+        assert_eq!(
+            items,
+            [json!({"a": "1", "b": "-2.5", "c": "true", "d": "007", "e": "x1"})]
+        );
+    }
+
+    #[test]
+    fn reads_rows_by_position_without_a_header() {
+        let mut csv = Csv::<Value>::tsv().header(Header::Absent);
+        let items = decode_all(&mut csv, "1\ta b\n2\tc\n").unwrap();
+        assert_eq!(items, [json!([1, "a b"]), json!([2, "c"])]);
+    }
+
+    #[test]
+    fn detects_headers() {
+        let mut csv = Csv::<Value>::new().header(Header::Detect);
+        assert_eq!(
+            decode_all(&mut csv, "a,b\n1,2\n").unwrap(),
+            [json!({"a": 1, "b": 2})]
+        );
+        let mut csv = Csv::<Value>::new().header(Header::Detect);
+        assert_eq!(
+            decode_all(&mut csv, "x,2\ny,3\n").unwrap(),
+            [json!(["x", 2]), json!(["y", 3])]
+        );
+    }
+
+    #[test]
+    fn deserializes_typed_rows() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Row {
+            id: u32,
+            code: String,
+            score: Option<f64>,
+        }
+
+        let items =
+            decode_all(&mut Csv::<Row>::new(), "id,code,score\n7,0042,\n8,1,0.5\n").unwrap();
+        assert_eq!(
+            items,
+            [
+                Row {
+                    id: 7,
+                    code: "0042".into(),
+                    score: None
+                },
+                Row {
+                    id: 8,
+                    code: "1".into(),
+                    score: Some(0.5)
+                },
+            ]
+        );
+        let e = decode_all(&mut Csv::<Row>::new(), "id,code,score\nx,1,2\n").unwrap_err();
+        assert!(e.to_string().contains("line 2: `x`"), "{e}");
+    }
+
+    #[test]
+    fn names_the_line_of_rows_of_the_wrong_width() {
+        let e = decode_all(&mut Csv::<Value>::new(), "a,b\n1,2\n3\n").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "codec error: line 3: expected 2 fields, found 1"
+        );
+        let items = decode_all(&mut Csv::<Value>::new().flexible(true), "a,b\n1,2\n3\n").unwrap();
+        assert_eq!(items.len(), 2);
+    }
+
+    #[test]
+    fn refuses_to_resume_past_an_unread_header() {
+        let e = Decoder::resume(&mut Csv::<Value>::new()).unwrap_err();
+        assert!(e.to_string().contains("set `columns`"), "{e}");
+        let e = Decoder::resume(&mut Csv::<Value>::new().header(Header::Detect)).unwrap_err();
+        assert!(e.to_string().contains("set `columns`"), "{e}");
+    }
+
+    #[test]
+    fn resumes_with_columns_or_without_a_header() {
+        let mut csv = Csv::<Value>::new().columns(["a", "b"]);
+        Decoder::resume(&mut csv).unwrap();
+        assert_eq!(
+            decode_all(&mut csv, "3,4\n").unwrap(),
+            [json!({"a": 3, "b": 4})]
+        );
+
+        let mut csv = Csv::<Value>::new().header(Header::Absent);
+        Decoder::resume(&mut csv).unwrap();
+        assert_eq!(decode_all(&mut csv, "3,4\n").unwrap(), [json!([3, 4])]);
+    }
+
+    /// Has `csv` read the start of `input`, fed a byte at a time, and resume.
+    fn resume_after(csv: &mut Csv, input: &str) {
+        let mut buf = BytesMut::new();
+        let mut bytes = input.as_bytes().iter();
+        loop {
+            let next = bytes.next();
+            if let Some(&b) = next {
+                buf.extend_from_slice(&[b]);
+            }
+            if csv.read_start(&mut buf, next.is_none()).unwrap() {
+                break;
+            }
+        }
+        Decoder::resume(csv).unwrap();
+    }
+
+    #[test]
+    fn resumes_with_the_columns_of_the_first_row() {
+        let mut csv = Csv::<Value>::new();
+        resume_after(&mut csv, "a,\"b\nc\"\n1,2\n");
+        assert_eq!(
+            decode_all(&mut csv, "3,4\n").unwrap(),
+            [json!({"a": 3, "b\nc": 4})]
+        );
+
+        // a first row of data sets the width all the same
+        let mut csv = Csv::<Value>::new().header(Header::Detect);
+        resume_after(&mut csv, "1,2\n3,4\n");
+        let e = decode_all(&mut csv, "5,6\n7\n").unwrap_err();
+        assert!(e.to_string().contains("expected 2 fields"), "{e}");
+
+        let mut csv = Csv::<Value>::new().columns(["x", "y"]);
+        resume_after(&mut csv, "a,b\n");
+        assert_eq!(
+            decode_all(&mut csv, "1,2\n").unwrap(),
+            [json!({"x": 1, "y": 2})]
+        );
+    }
+
+    #[test]
+    fn writes_struct_fields_in_order_and_quotes_as_needed() {
+        #[derive(Serialize)]
+        struct Row<'a> {
+            zone: &'a str,
+            id: u32,
+            note: Option<&'a str>,
+        }
+
+        let rows = [
+            Row {
+                zone: "a,b",
+                id: 1,
+                note: Some("say \"hi\""),
+            },
+            Row {
+                zone: "c",
+                id: 2,
+                note: None,
+            },
+        ];
+        assert_eq!(
+            encode_all(&mut Csv::<Value>::new(), &rows).unwrap(),
+            "zone,id,note\n\"a,b\",1,\"say \"\"hi\"\"\"\nc,2,\n"
+        );
+    }
+
+    #[test]
+    fn round_trips_through_encoding() {
+        let items = [
+            json!({"a": 1, "b": "x,\ny", "c": null}),
+            json!({"a": 2.5, "b": "\"q\"", "c": true}),
+        ];
+        let text = encode_all(&mut Csv::<Value>::new(), &items).unwrap();
+        assert_eq!(decode_all(&mut Csv::<Value>::new(), &text).unwrap(), items);
+    }
+
+    #[test]
+    fn leaves_out_fields_only_beyond_explicit_columns() {
+        let items = [json!({"a": 1}), json!({"a": 2, "b": 3})];
+        let e = encode_all(&mut Csv::<Value>::new(), &items).unwrap_err();
+        assert!(e.to_string().contains("field `b`"), "{e}");
+
+        let mut csv = Csv::<Value>::new().columns(["a"]).header(Header::Absent);
+        assert_eq!(encode_all(&mut csv, &items).unwrap(), "1\n2\n");
+    }
+
+    #[test]
+    fn resumed_encoders_write_no_header() {
+        let mut csv = Csv::<Value>::new();
+        Encoder::<Value>::resume(&mut csv).unwrap();
+        assert_eq!(encode_all(&mut csv, &[json!({"a": 1})]).unwrap(), "1\n");
+    }
+}
@@ -0,0 +1,285 @@
+use bytes::{Buf, BytesMut};
+use serde::Deserialize;
+
+use crate::{Error, Result};
+
+/// The length of the prefix of [`Framing::LengthPrefixed`] items.
+const PREFIX: usize = 4;
+
+/// How items are told apart in a stream of binary values.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Framing {
+    /// Items follow one another, relying on every value to mark its own end.
+    #[default]
+    Concatenated,
+    /// Every item is prefixed by its length in bytes, as a 4-byte big-endian
+    /// integer, so that an item can be skipped without parsing it.
+    LengthPrefixed,
+}
+
+/// What the head of a value, the bytes it starts with, says about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Head {
+    /// The length of the head together with the bytes it covers, such as
+    /// those of a string.
+    pub(super) len: usize,
+    pub(super) nested: Nested,
+}
+
+/// The values nested in a value after its head.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Nested {
+    /// A number of values, none for scalars.
+    Values(u64),
+    /// Any number of values, up to a [`Nested::Break`].
+    UntilBreak,
+    /// None, the head ends the innermost value nested [`Nested::UntilBreak`].
+    Break,
+}
+
+/// Tells where [`Framing::Concatenated`] items end by reading the head of
+/// every value nested in them, picking up where it left off when an item is
+/// cut off, so that every head is read once however many chunks an item
+/// arrives in.
+#[derive(Debug, Default, Clone)]
+pub(super) struct Scan {
+    /// How many bytes at the front of the buffer were read.
+    pos: usize,
+    /// How many values are still to come in those open at `pos`, innermost
+    /// last, or `None` for those nested up to a break.
+    open: Vec<Option<u64>>,
+}
+
+impl Scan {
+    /// The length of the value at the front of `buf`, or `None` while it is
+    /// cut off. `head` reads the head at the front of a slice, returning
+    /// `None` if it is cut off, or why it is malformed.
+    fn measure(
+        &mut self,
+        buf: &[u8],
+        head: impl Fn(&[u8]) -> Result<Option<Head>, String>,
+    ) -> Result<Option<usize>, String> {
+        loop {
+            let Some(Head { len, nested }) = head(&buf[self.pos..])? else {
+                return Ok(None);
+            };
+            if buf.len() - self.pos < len {
+                return Ok(None);
+            }
+            self.pos += len;
+            match nested {
+                Nested::Values(0) => {}
+                Nested::Values(n) => {
+                    self.open.push(Some(n));
+                    continue;
+                }
+                Nested::UntilBreak => {
+                    self.open.push(None);
+                    continue;
+                }
+                Nested::Break => {
+                    if self.open.pop() != Some(None) {
+                        return Err("break outside of a value of indefinite length".to_string());
+                    }
+                }
+            }
+            // a value ended, and so did the ones it was the last value of
+            loop {
+                match self.open.last_mut() {
+                    None => return Ok(Some(std::mem::take(self).pos)),
+                    Some(Some(n)) if *n > 1 => {
+                        *n -= 1;
+                        break;
+                    }
+                    Some(Some(_)) => {
+                        self.open.pop();
+                    }
+                    Some(None) => break,
+                }
+            }
+        }
+    }
+}
+
+impl Framing {
+    /// Splits the bytes of the next item off the front of `buf`, or returns
+    /// `None` while `buf` does not hold all of them. Concatenated items are
+    /// measured with `scan` and `head`, see [`Scan::measure`].
+    pub(super) fn split(
+        self,
+        buf: &mut BytesMut,
+        scan: &mut Scan,
+        head: impl Fn(&[u8]) -> Result<Option<Head>, String>,
+    ) -> Result<Option<BytesMut>, String> {
+        match self {
+            Framing::Concatenated => Ok(scan.measure(buf, head)?.map(|len| buf.split_to(len))),
+            Framing::LengthPrefixed => {
+                let Some(prefix) = buf.get(..PREFIX) else {
+                    return Ok(None);
+                };
+                let len = u32::from_be_bytes(prefix.try_into().unwrap()) as usize;
+                if buf.len() < PREFIX + len {
+                    return Ok(None);
+                }
+                buf.advance(PREFIX);
+                Ok(Some(buf.split_to(len)))
+            }
+        }
+    }
+
+    /// Appends the item `write` appends to `buf`, framed.
+    pub(super) fn write(
+        self,
+        buf: &mut Vec<u8>,
+        write: impl FnOnce(&mut Vec<u8>) -> Result<()>,
+    ) -> Result<()> {
+        match self {
+            Framing::Concatenated => write(buf),
+            Framing::LengthPrefixed => {
+                let start = buf.len();
+                buf.extend_from_slice(&[0; PREFIX]);
+                write(buf)?;
+                let len = u32::try_from(buf.len() - start - PREFIX)
+                    .map_err(|_| Error::codec("item too large for a 4-byte length prefix"))?;
+                buf[start..start + PREFIX].copy_from_slice(&len.to_be_bytes());
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The `n` bytes after the first byte of `bytes` as a big-endian number, or
+/// `None` if `bytes` is shorter.
+pub(super) fn argument(bytes: &[u8], n: usize) -> Option<u64> {
+    let bytes = bytes.get(1..1 + n)?;
+    Some(bytes.iter().fold(0, |value, &b| value << 8 | u64::from(b)))
+}
+
+/// Adds `len` bytes covered by a head of `head` bytes, failing when they do
+/// not fit in memory.
+pub(super) fn covering(head: usize, len: u64) -> Result<usize, String> {
+    usize::try_from(len)
+        .ok()
+        .and_then(|len| len.checked_add(head))
+        .ok_or_else(|| format!("value of {len} bytes too large"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    /// Reads the heads of values made of a length byte and that many bytes,
+    /// `[` and `]` enclosing values, `2` followed by two values, counting
+    /// every head read in `reads`.
+    fn head(reads: &Cell<usize>) -> impl Fn(&[u8]) -> Result<Option<Head>, String> + '_ {
+        move |bytes| {
+            let Some(&first) = bytes.first() else {
+                return Ok(None);
+            };
+            reads.set(reads.get() + 1);
+            let (len, nested) = match first {
+                b'[' => (1, Nested::UntilBreak),
+                b']' => (1, Nested::Break),
+                b'2' => (1, Nested::Values(2)),
+                0xff => return Err("bad value".to_string()),
+                len => (1 + usize::from(len), Nested::Values(0)),
+            };
+            Ok(Some(Head { len, nested }))
+        }
+    }
+
+    fn split(buf: &mut BytesMut, scan: &mut Scan) -> Result<Option<BytesMut>, String> {
+        Framing::Concatenated.split(buf, scan, head(&Cell::new(0)))
+    }
+
+    fn write(framing: Framing, values: &[&[u8]]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for value in values {
+            framing
+                .write(&mut buf, |buf| {
+                    buf.extend_from_slice(value);
+                    Ok(())
+                })
+                .unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn prefixes_items_with_their_big_endian_length() {
+        let buf = write(Framing::LengthPrefixed, &[b"ab", b""]);
+        assert_eq!(buf, [0, 0, 0, 2, b'a', b'b', 0, 0, 0, 0]);
+        assert_eq!(write(Framing::Concatenated, &[b"ab", b"c"]), b"abc");
+    }
+
+    #[test]
+    fn splits_length_prefixed_items_once_complete() {
+        let mut buf = BytesMut::new();
+        let mut scan = Scan::default();
+        let unused = |_: &[u8]| -> Result<Option<Head>, String> { unreachable!() };
+        for &b in &[0, 0, 0, 3, b'x', b'y'] {
+            buf.extend_from_slice(&[b]);
+            assert_eq!(
+                Framing::LengthPrefixed.split(&mut buf, &mut scan, unused),
+                Ok(None)
+            );
+        }
+        buf.extend_from_slice(b"zrest");
+        let item = Framing::LengthPrefixed
+            .split(&mut buf, &mut scan, unused)
+            .unwrap();
+        assert_eq!(item.as_deref(), Some(&b"xyz"[..]));
+        assert_eq!(&buf[..], b"rest");
+    }
+
+    #[test]
+    fn splits_concatenated_items_as_measured() {
+        let mut scan = Scan::default();
+        let mut buf = BytesMut::from(&[2, b'a', b'b', 1][..]);
+        let item = split(&mut buf, &mut scan).unwrap();
+        assert_eq!(item.as_deref(), Some(&[2, b'a', b'b'][..]));
+        assert_eq!(split(&mut buf, &mut scan), Ok(None));
+        buf.extend_from_slice(&[b'c', 0xff]);
+        let item = split(&mut buf, &mut scan).unwrap();
+        assert_eq!(item.as_deref(), Some(&[1, b'c'][..]));
+        assert_eq!(split(&mut buf, &mut scan), Err("bad value".to_string()));
+    }
+
+    #[test]
+    fn splits_nested_values_where_the_outermost_ends() {
+        let mut scan = Scan::default();
+        let mut buf = BytesMut::from(&b"2[\x01a]2\x00\x00\x01b"[..]);
+        let item = split(&mut buf, &mut scan).unwrap();
+        assert_eq!(item.as_deref(), Some(&b"2[\x01a]2\x00\x00"[..]));
+        assert_eq!(&buf[..], b"\x01b");
+        let mut buf = BytesMut::from(&b"\x01a]"[..]);
+        let item = split(&mut buf, &mut scan).unwrap();
+        assert_eq!(item.as_deref(), Some(&b"\x01a"[..]));
+        assert!(split(&mut buf, &mut scan).unwrap_err().contains("break"));
+    }
+
+    #[test]
+    fn reads_heads_only_as_their_bytes_arrive() {
+        let mut item = b"[".to_vec();
+        for _ in 0..100 {
+            item.extend_from_slice(b"\x01x");
+        }
+        item.push(b']');
+        let reads = Cell::new(0);
+        let mut scan = Scan::default();
+        let mut buf = BytesMut::new();
+        for (i, &b) in item.iter().enumerate() {
+            buf.extend_from_slice(&[b]);
+            let split = Framing::Concatenated
+                .split(&mut buf, &mut scan, head(&reads))
+                .unwrap();
+            assert_eq!(split.is_some(), i == item.len() - 1);
+        }
+        // the head of every string is read when it arrives and again once
+        // its byte does, the brackets once
+        assert_eq!(reads.get(), 2 * 100 + 2);
+    }
+}
@@ -0,0 +1,357 @@
+use std::fmt;
+
+use arrow::array::RecordBatch;
+use arrow::buffer::Buffer;
+use arrow::ipc::reader::StreamDecoder;
+use arrow::ipc::writer::{FileWriter, StreamWriter};
+use bytes::{Buf, BytesMut};
+use serde::Deserialize;
+
+use super::{Decoder, Encoder};
+use crate::{Error, Result};
+
+/// Starts and ends files in the IPC file format.
+const MAGIC: &[u8] = b"ARROW1";
+
+/// Marks the length prefix of a message in current IPC streams.
+const CONTINUATION: [u8; 4] = [0xff; 4];
+
+/// Which of the Arrow IPC formats [`ArrowIpc`] writes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IpcFormat {
+    /// The streaming format, which can be read as it arrives.
+    #[default]
+    Stream,
+    /// The file format, also known as Feather v2, which ends with an index
+    /// of its batches for random access.
+    File,
+}
+
+enum Writer {
+    Stream(StreamWriter<Vec<u8>>),
+    File(FileWriter<Vec<u8>>),
+}
+
+/// Arrow IPC: record batches in the stream or file format.
+///
+/// Decoding takes either format. Encoding writes `format`, with the schema
+/// of the first batch; nothing at all is written without any batches.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArrowIpc {
+    #[serde(default)]
+    format: IpcFormat,
+    #[serde(skip)]
+    decoder: StreamDecoder,
+    #[serde(skip)]
+    started: bool,
+    #[serde(skip)]
+    ended: bool,
+    #[serde(skip)]
+    writer: Option<Writer>,
+}
+
+impl ArrowIpc {
+    pub fn new(format: IpcFormat) -> Self {
+        Self {
+            format,
+            decoder: StreamDecoder::new(),
+            started: false,
+            ended: false,
+            writer: None,
+        }
+    }
+
+    /// The length of the next message at the front of `buf`, including its
+    /// prefix and body, or `None` while `buf` does not hold all of it.
+    /// A length of zero marks the end of the stream.
+    fn message_len(buf: &[u8]) -> Result<Option<(usize, usize)>> {
+        let Some(head) = buf.get(..4) else {
+            return Ok(None);
+        };
+        let (prefix, len) = if head == CONTINUATION {
+            match buf.get(4..8) {
+                Some(len) => (8, u32::from_le_bytes(len.try_into().unwrap())),
+                None => return Ok(None),
+            }
+        } else {
+            (4, u32::from_le_bytes(head.try_into().unwrap()))
+        };
+        let len = len as usize;
+        if len == 0 {
+            return Ok(Some((prefix, 0)));
+        }
+        let Some(message) = buf.get(prefix..prefix + len) else {
+            return Ok(None);
+        };
+        let message = arrow::ipc::root_as_message(message)
+            .map_err(|e| Error::codec(format!("invalid IPC message: {e}")))?;
+        let body = usize::try_from(message.bodyLength())
+            .map_err(|_| Error::codec("invalid IPC message: negative body length"))?;
+        if buf.len() < prefix + len + body {
+            return Ok(None);
+        }
+        Ok(Some((prefix, len + body)))
+    }
+}
+
+impl Default for ArrowIpc {
+    fn default() -> Self {
+        Self::new(IpcFormat::default())
+    }
+}
+
+/// Clones start over as for a new input or output.
+impl Clone for ArrowIpc {
+    fn clone(&self) -> Self {
+        Self::new(self.format)
+    }
+}
+
+impl fmt::Debug for ArrowIpc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArrowIpc")
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+impl Decoder for ArrowIpc {
+    type Item = RecordBatch;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<RecordBatch>> {
+        if !self.started {
+            // the file format is the stream format between a header and a footer
+            if buf.len() < 8 && MAGIC.starts_with(&buf[..buf.len().min(MAGIC.len())]) {
+                return Ok(None);
+            }
+            if buf.starts_with(MAGIC) {
+                // the header is padded with zeros up to the first message
+                let Some(start) = (8..buf.len().saturating_sub(3))
+                    .step_by(4)
+                    .find(|&i| buf[i..i + 4] != [0; 4])
+                else {
+                    return Ok(None);
+                };
+                buf.advance(start);
+            }
+            self.started = true;
+        }
+        while !self.ended {
+            let Some((prefix, len)) = Self::message_len(buf)? else {
+                return Ok(None);
+            };
+            if len == 0 {
+                buf.advance(prefix);
+                self.ended = true;
+                break;
+            }
+            let mut message = Buffer::from(buf.split_to(prefix + len).freeze());
+            if let Some(batch) = self.decoder.decode(&mut message).map_err(Error::codec)? {
+                return Ok(Some(batch));
+            }
+        }
+        // past the end of the stream lies the footer of a file, if anything
+        buf.clear();
+        Ok(None)
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<RecordBatch>> {
+        if let Some(batch) = self.decode(buf)? {
+            return Ok(Some(batch));
+        }
+        let left = buf.len();
+        *self = Self::new(self.format);
+        buf.clear();
+        if left > 0 {
+            return Err(Error::codec(format!(
+                "IPC stream cut off, {left} bytes left at the end of the input"
+            )));
+        }
+        Ok(None)
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        Err(Error::config(
+            "IPC streams start with the schema of their batches, so they cannot be resumed",
+        ))
+    }
+}
+
+impl Encoder<RecordBatch> for ArrowIpc {
+    fn encode(&mut self, batch: &RecordBatch, buf: &mut Vec<u8>) -> Result<()> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let schema = batch.schema();
+                let writer = match self.format {
+                    IpcFormat::Stream => {
+                        StreamWriter::try_new(Vec::new(), &schema).map(Writer::Stream)
+                    }
+                    IpcFormat::File => FileWriter::try_new(Vec::new(), &schema).map(Writer::File),
+                };
+                self.writer.insert(writer.map_err(Error::codec)?)
+            }
+        };
+        // the writers only ever append to their buffer, so it can be drained
+        // after every batch
+        let written = match writer {
+            Writer::Stream(writer) => writer.write(batch).map(|()| writer.get_mut()),
+            Writer::File(writer) => writer.write(batch).map(|()| writer.get_mut()),
+        };
+        buf.append(written.map_err(Error::codec)?);
+        Ok(())
+    }
+
+    fn finish(&mut self, buf: &mut Vec<u8>) -> Result<()> {
+        let finished = match &mut self.writer {
+            Some(Writer::Stream(writer)) => writer.finish().map(|()| writer.get_mut()),
+            Some(Writer::File(writer)) => writer.finish().map(|()| writer.get_mut()),
+            None => return Ok(()),
+        };
+        buf.append(finished.map_err(Error::codec)?);
+        self.writer = None;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        Err(Error::config(
+            "IPC streams start with the schema of their batches and end in a marker, \
+             so they cannot be appended to",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    use arrow::array::{Int64Array, StringArray};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::ipc::reader::{FileReader, StreamReader};
+
+    use super::*;
+    use crate::codec::decode_in_chunks;
+
+    fn batches() -> Vec<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+        ]));
+        (0..3)
+            .map(|i| {
+                RecordBatch::try_new(
+                    schema.clone(),
+                    vec![
+                        Arc::new(Int64Array::from(vec![i * 2, i * 2 + 1])),
+                        Arc::new(StringArray::from(vec![Some("a"), None])),
+                    ],
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    fn encode(format: IpcFormat, batches: &[RecordBatch]) -> Vec<u8> {
+        let mut ipc = ArrowIpc::new(format);
+        let mut buf = Vec::new();
+        for batch in batches {
+            ipc.encode(batch, &mut buf).unwrap();
+        }
+        ipc.finish(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn writes_streams_arrow_reads() {
+        let bytes = encode(IpcFormat::Stream, &batches());
+        let read: Vec<_> = StreamReader::try_new(Cursor::new(bytes), None)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, batches());
+    }
+
+    #[test]
+    fn writes_files_arrow_reads() {
+        let bytes = encode(IpcFormat::File, &batches());
+        assert!(bytes.starts_with(MAGIC) && bytes.ends_with(MAGIC));
+        let reader = FileReader::try_new(Cursor::new(bytes), None).unwrap();
+        assert_eq!(reader.num_batches(), 3);
+        let read: Vec<_> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(read, batches());
+    }
+
+    #[test]
+    fn reads_both_formats_in_any_chunks() {
+        for format in [IpcFormat::Stream, IpcFormat::File] {
+            let bytes = encode(format, &batches());
+            for chunk in [1, 7, bytes.len()] {
+                assert_eq!(
+                    decode_in_chunks(&mut ArrowIpc::default(), &bytes, chunk).unwrap(),
+                    batches(),
+                    "{format:?} by {chunk}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn writes_nothing_without_batches() {
+        assert!(encode(IpcFormat::File, &[]).is_empty());
+        assert!(
+            decode_in_chunks(&mut ArrowIpc::default(), &[], 1)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn fails_on_cut_off_streams() {
+        let bytes = encode(IpcFormat::Stream, &batches());
+        let e =
+            decode_in_chunks(&mut ArrowIpc::default(), &bytes[..bytes.len() - 20], 64).unwrap_err();
+        assert!(e.to_string().contains("cut off"), "{e}");
+    }
+
+    #[test]
+    fn refuses_to_resume() {
+        assert!(Decoder::resume(&mut ArrowIpc::default()).is_err());
+    }
+
+    #[tokio::test]
+    async fn refuses_to_append_to_files_that_hold_batches() {
+        use serde_json::Value;
+
+        use crate::Acks;
+        use crate::fio::{Output, WriteMode};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("batches.arrow");
+        let write = |batches: Vec<RecordBatch>| {
+            let path = path.clone();
+            async move {
+                Output::new(path)
+                    .mode(WriteMode::Append)
+                    .write(
+                        futures::stream::iter(batches),
+                        ArrowIpc::new(IpcFormat::Stream),
+                        |_| Ok(Value::Null),
+                        Acks::default(),
+                    )
+                    .await
+            }
+        };
+        write(batches()[..2].to_vec()).await.unwrap();
+        let written = std::fs::read(&path).unwrap();
+        let e = write(batches()[2..].to_vec()).await.unwrap_err();
+        assert!(e.to_string().contains("cannot append to"), "{e}");
+        assert_eq!(std::fs::read(&path).unwrap(), written);
+        assert_eq!(
+            decode_in_chunks(&mut ArrowIpc::default(), &written, 64).unwrap(),
+            batches()[..2]
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use std::fmt::Display;
+use std::io::Write;
+
+use bytes::BytesMut;
+use serde::Deserialize;
+
+use super::{Decoder, Encoder};
+use crate::{Error, Result};
+
+/// Newline-delimited text, one item per line.
+///
+/// Lines are decoded without their line ending, `\n` or `\r\n`; a last line
+/// without one is decoded all the same. Items are encoded with their
+/// [`Display`] form followed by `\n`.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Lines {}
+
+impl Lines {
+    fn line(mut bytes: BytesMut) -> Result<String> {
+        if bytes.ends_with(b"\n") {
+            bytes.truncate(bytes.len() - 1);
+            if bytes.ends_with(b"\r") {
+                bytes.truncate(bytes.len() - 1);
+            }
+        }
+        String::from_utf8(bytes.to_vec()).map_err(Error::codec)
+    }
+}
+
+impl Decoder for Lines {
+    type Item = String;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<String>> {
+        match buf.iter().position(|&b| b == b'\n') {
+            Some(end) => Self::line(buf.split_to(end + 1)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<String>> {
+        match self.decode(buf)? {
+            Some(line) => Ok(Some(line)),
+            None if buf.is_empty() => Ok(None),
+            None => Self::line(buf.split()).map(Some),
+        }
+    }
+
+    fn reads_lines(&self) -> bool {
+        true
+    }
+}
+
+impl<T: Display + ?Sized> Encoder<T> for Lines {
+    fn encode(&mut self, item: &T, buf: &mut Vec<u8>) -> Result<()> {
+        writeln!(buf, "{item}")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,532 @@
+//! Turning bytes into items and back.
+//!
+//! A [`Decoder`] splits the bytes read by an input into items, an [`Encoder`]
+//! renders items into the bytes written by an output. [`Decode`] and
+//! [`Encode`] put them in front of the byte-oriented components, e.g.
+//! `Decode::new(Stdin::new(), Lines {})`.
+//!
+//! Configured pipelines pick a codec by name with the `codec` option of such
+//! components, see [`Codec`].
+
+#[cfg(feature = "avro")]
+mod avro;
+mod cbor;
+mod csv;
+mod framing;
+#[cfg(feature = "arrow")]
+mod ipc;
+mod lines;
+mod msgpack;
+mod ndjson;
+#[cfg(feature = "protobuf")]
+mod protobuf;
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use futures::Stream;
+use serde::de::{DeserializeOwned, Error as _};
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{ComponentConfig, Error, FromRecord, Record, Result};
+
+#[cfg(feature = "avro")]
+pub use avro::{Avro, AvroCompression};
+pub use cbor::Cbor;
+pub use csv::{Csv, Header};
+pub use framing::Framing;
+#[cfg(feature = "arrow")]
+pub use ipc::{ArrowIpc, IpcFormat};
+pub use lines::Lines;
+pub use msgpack::MessagePack;
+pub use ndjson::{Malformed, Ndjson, ParseJson};
+#[cfg(feature = "protobuf")]
+pub use protobuf::Protobuf;
+
+/// How many bytes are read at once while decoding.
+const READ_SIZE: usize = 8 * 1024;
+
+/// Splits a byte stream into items.
+pub trait Decoder {
+    type Item;
+
+    /// Decodes the next item from the front of `buf`, removing the bytes it
+    /// took up, or returns `None` while `buf` does not hold a complete item.
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>>;
+
+    /// Like [`decode`](Self::decode), once the input has ended and `buf`
+    /// holds all that is left of it.
+    ///
+    /// Returns `None` when nothing is left, after which the decoder starts
+    /// over as for a new input. By default, leftover bytes are an error.
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>> {
+        match self.decode(buf)? {
+            Some(item) => Ok(Some(item)),
+            None if buf.is_empty() => Ok(None),
+            None => Err(Error::codec(format!(
+                "{} bytes left at the end of the input",
+                buf.len()
+            ))),
+        }
+    }
+
+    /// Prepares the decoder to pick up an input in the middle, past
+    /// whatever it would read first otherwise, such as a header. Inputs call
+    /// this when resuming from a checkpoint, and fail if the decoder cannot
+    /// make sense of the rest of the input on its own.
+    fn resume(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Reads whatever starts an input from the front of `buf`, such as a
+    /// header, for [`resume`](Self::resume) to make use of. Inputs that can
+    /// read their start again call this before resuming, with more bytes for
+    /// as long as it returns `false`; `eof` tells that `buf` holds all of the
+    /// input. By default, nothing needs to be read.
+    fn read_start(&mut self, _buf: &mut BytesMut, _eof: bool) -> Result<bool> {
+        Ok(true)
+    }
+
+    /// Whether the decoder reads items made of whole lines of text, and can
+    /// be fed a followed file line by line.
+    fn reads_lines(&self) -> bool {
+        false
+    }
+}
+
+/// Renders items into a byte stream.
+pub trait Encoder<T: ?Sized> {
+    /// Appends the encoding of `item` to `buf`.
+    fn encode(&mut self, item: &T, buf: &mut Vec<u8>) -> Result<()>;
+
+    /// Appends whatever ends the output, such as a closing bracket, after
+    /// which the encoder starts over as for a new output.
+    fn finish(&mut self, _buf: &mut Vec<u8>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Prepares the encoder to add to an output which already holds items it
+    /// encoded, so that it leaves out whatever starts an output, such as a
+    /// header. Outputs call this when appending to a file, and fail if the
+    /// encoder cannot add to what is there.
+    fn resume(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<D: Decoder + ?Sized> Decoder for Box<D> {
+    type Item = D::Item;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>> {
+        (**self).decode(buf)
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>> {
+        (**self).decode_eof(buf)
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        (**self).resume()
+    }
+
+    fn read_start(&mut self, buf: &mut BytesMut, eof: bool) -> Result<bool> {
+        (**self).read_start(buf, eof)
+    }
+
+    fn reads_lines(&self) -> bool {
+        (**self).reads_lines()
+    }
+}
+
+impl<T: ?Sized, E: Encoder<T> + ?Sized> Encoder<T> for Box<E> {
+    fn encode(&mut self, item: &T, buf: &mut Vec<u8>) -> Result<()> {
+        (**self).encode(item, buf)
+    }
+
+    fn finish(&mut self, buf: &mut Vec<u8>) -> Result<()> {
+        (**self).finish(buf)
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        (**self).resume()
+    }
+}
+
+/// An input decoding the bytes read by `I` with a [`Decoder`].
+#[derive(Debug, Clone)]
+pub struct Decode<I, D> {
+    pub(crate) input: I,
+    pub(crate) decoder: D,
+}
+
+impl<I, D> Decode<I, D> {
+    pub fn new(input: I, decoder: D) -> Self {
+        Self { input, decoder }
+    }
+}
+
+/// An output writing items through `O` rendered by an [`Encoder`].
+///
+/// The encoder is cloned for every stream, and for every file when the
+/// output writes more than one.
+#[derive(Debug, Clone)]
+pub struct Encode<O, E> {
+    pub(crate) output: O,
+    pub(crate) encoder: E,
+}
+
+impl<O, E> Encode<O, E> {
+    pub fn new(output: O, encoder: E) -> Self {
+        Self { output, encoder }
+    }
+}
+
+/// Decodes items from a reader, keeping track of the bytes they took up.
+pub(crate) struct FramedRead<R, D> {
+    reader: R,
+    decoder: D,
+    buf: BytesMut,
+    read: u64,
+    eof: bool,
+}
+
+impl<R: AsyncRead + Unpin, D: Decoder> FramedRead<R, D> {
+    pub(crate) fn new(reader: R, decoder: D) -> Self {
+        Self {
+            reader,
+            decoder,
+            buf: BytesMut::new(),
+            read: 0,
+            eof: false,
+        }
+    }
+
+    /// The next item, or `None` once the reader is exhausted.
+    pub(crate) async fn next(&mut self) -> Result<Option<D::Item>> {
+        loop {
+            if self.eof {
+                return self.decoder.decode_eof(&mut self.buf);
+            }
+            if let Some(item) = self.decoder.decode(&mut self.buf)? {
+                return Ok(Some(item));
+            }
+            self.buf.reserve(READ_SIZE);
+            match self.reader.read_buf(&mut self.buf).await? {
+                0 => self.eof = true,
+                n => self.read += n as u64,
+            }
+        }
+    }
+
+    /// How many bytes the items decoded so far took up.
+    pub(crate) fn position(&self) -> u64 {
+        self.read - self.buf.len() as u64
+    }
+
+    /// Has the decoder read the start of the input, see
+    /// [`Decoder::read_start`].
+    pub(crate) async fn read_start(mut self) -> Result<D> {
+        while !self.decoder.read_start(&mut self.buf, self.eof)? {
+            if self.eof {
+                return Err(Error::codec("the input ended before its start was read"));
+            }
+            self.buf.reserve(READ_SIZE);
+            if self.reader.read_buf(&mut self.buf).await? == 0 {
+                self.eof = true;
+            }
+        }
+        Ok(self.decoder)
+    }
+
+    pub(crate) fn into_decoder(self) -> D {
+        self.decoder
+    }
+}
+
+/// Decodes the items read from `reader` until it ends.
+pub fn decode<R, D>(reader: R, decoder: D) -> impl Stream<Item = Result<D::Item>> + Send
+where
+    R: AsyncRead + Unpin + Send,
+    D: Decoder + Send,
+    D::Item: Send,
+{
+    async_stream::try_stream! {
+        let mut framed = FramedRead::new(reader, decoder);
+        while let Some(item) = framed.next().await? {
+            yield item;
+        }
+    }
+}
+
+/// Decodes `bytes` fed `chunk` bytes at a time.
+#[cfg(test)]
+pub(crate) fn decode_in_chunks<D: Decoder>(
+    decoder: &mut D,
+    bytes: &[u8],
+    chunk: usize,
+) -> Result<Vec<D::Item>> {
+    let mut buf = BytesMut::new();
+    let mut items = Vec::new();
+    for part in bytes.chunks(chunk) {
+        buf.extend_from_slice(part);
+        while let Some(item) = decoder.decode(&mut buf)? {
+            items.push(item);
+        }
+    }
+    while let Some(item) = decoder.decode_eof(&mut buf)? {
+        items.push(item);
+    }
+    Ok(items)
+}
+
+type BoxDecoder = Box<dyn Decoder<Item = Record> + Send + Sync>;
+type BoxEncoder = Box<dyn Encoder<Record> + Send + Sync>;
+
+/// Creates fresh record decoders and encoders of one configured codec.
+trait RecordCodec: Send + Sync {
+    fn decoder(&self) -> BoxDecoder;
+
+    fn encoder(&self) -> BoxEncoder;
+}
+
+struct Records<C, T>(C, PhantomData<fn(T) -> T>);
+
+impl<C, T> RecordCodec for Records<C, T>
+where
+    C: Decoder<Item = T> + Encoder<T> + Clone + Send + Sync + 'static,
+    T: Into<Record> + FromRecord + 'static,
+{
+    fn decoder(&self) -> BoxDecoder {
+        Box::new(RecordDecoder(self.0.clone()))
+    }
+
+    fn encoder(&self) -> BoxEncoder {
+        Box::new(RecordEncoder(self.0.clone(), PhantomData))
+    }
+}
+
+struct RecordDecoder<D>(D);
+
+impl<D> Decoder for RecordDecoder<D>
+where
+    D: Decoder,
+    D::Item: Into<Record>,
+{
+    type Item = Record;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Record>> {
+        Ok(self.0.decode(buf)?.map(Into::into))
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Record>> {
+        Ok(self.0.decode_eof(buf)?.map(Into::into))
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.0.resume()
+    }
+
+    fn read_start(&mut self, buf: &mut BytesMut, eof: bool) -> Result<bool> {
+        self.0.read_start(buf, eof)
+    }
+
+    fn reads_lines(&self) -> bool {
+        self.0.reads_lines()
+    }
+}
+
+struct RecordEncoder<E, T>(E, PhantomData<fn(T)>);
+
+impl<E, T> Encoder<Record> for RecordEncoder<E, T>
+where
+    E: Encoder<T>,
+    T: FromRecord,
+{
+    fn encode(&mut self, item: &Record, buf: &mut Vec<u8>) -> Result<()> {
+        self.0.encode(&T::from_record(item.clone())?, buf)
+    }
+
+    fn finish(&mut self, buf: &mut Vec<u8>) -> Result<()> {
+        self.0.finish(buf)
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.0.resume()
+    }
+}
+
+/// Builds a codec from its options, or explains what is wrong with them.
+type Build = fn(Value) -> Result<Arc<dyn RecordCodec>, String>;
+
+fn build<C, T>(options: Value) -> Result<Arc<dyn RecordCodec>, String>
+where
+    C: DeserializeOwned + Decoder<Item = T> + Encoder<T> + Clone + Send + Sync + 'static,
+    T: Into<Record> + FromRecord + 'static,
+{
+    let codec: C = serde_json::from_value(options).map_err(|e| e.to_string())?;
+    Ok(Arc::new(Records(codec, PhantomData)))
+}
+
+/// The codecs configs can refer to, by name.
+const CODECS: &[(&str, Build)] = &[
+    #[cfg(feature = "avro")]
+    ("avro", build::<Avro, Value>),
+    ("cbor", build::<Cbor, Value>),
+    ("csv", build::<Csv, Value>),
+    ("lines", build::<Lines, String>),
+    ("msgpack", build::<MessagePack, Value>),
+    ("ndjson", build::<Ndjson, Value>),
+    #[cfg(feature = "protobuf")]
+    ("protobuf", build::<Protobuf, Value>),
+    ("tsv", build_tsv),
+];
+
+/// Builds a [`Csv`] codec delimited by tabs unless told otherwise.
+fn build_tsv(mut options: Value) -> Result<Arc<dyn RecordCodec>, String> {
+    if let Value::Object(options) = &mut options {
+        options.entry("delimiter").or_insert_with(|| "\t".into());
+    }
+    build::<Csv, Value>(options)
+}
+
+/// A record codec looked up by name.
+///
+/// In configs, a codec is given by its name alone, e.g. `codec: lines`, or
+/// by its name under `type` along with its options.
+pub struct Codec {
+    name: String,
+    codec: Arc<dyn RecordCodec>,
+    decoder: Option<BoxDecoder>,
+    encoder: Option<BoxEncoder>,
+}
+
+impl Codec {
+    pub fn new(name: &str) -> Result<Self> {
+        Self::with_options(name, Map::new())
+    }
+
+    pub fn with_options(name: &str, options: Map<String, Value>) -> Result<Self> {
+        let (_, build) = CODECS
+            .iter()
+            .find(|(known, _)| *known == name)
+            .ok_or_else(|| {
+                Error::config(format!(
+                    "unknown codec `{name}`, expected one of {}",
+                    Self::names().collect::<Vec<_>>().join(", ")
+                ))
+            })?;
+        let codec = build(Value::Object(options))
+            .map_err(|e| Error::config(format!("codec `{name}`: {e}")))?;
+        Ok(Self {
+            name: name.to_string(),
+            codec,
+            decoder: None,
+            encoder: None,
+        })
+    }
+
+    /// The names of all known codecs.
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        CODECS.iter().map(|(name, _)| *name)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Clones start over, as if nothing was decoded or encoded yet.
+impl Clone for Codec {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            codec: self.codec.clone(),
+            decoder: None,
+            encoder: None,
+        }
+    }
+}
+
+impl fmt::Debug for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Codec")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Decoder for Codec {
+    type Item = Record;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Record>> {
+        self.decoder
+            .get_or_insert_with(|| self.codec.decoder())
+            .decode(buf)
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Record>> {
+        self.decoder
+            .get_or_insert_with(|| self.codec.decoder())
+            .decode_eof(buf)
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.decoder
+            .get_or_insert_with(|| self.codec.decoder())
+            .resume()
+    }
+
+    fn read_start(&mut self, buf: &mut BytesMut, eof: bool) -> Result<bool> {
+        self.decoder
+            .get_or_insert_with(|| self.codec.decoder())
+            .read_start(buf, eof)
+    }
+
+    fn reads_lines(&self) -> bool {
+        match &self.decoder {
+            Some(decoder) => decoder.reads_lines(),
+            None => self.codec.decoder().reads_lines(),
+        }
+    }
+}
+
+impl Encoder<Record> for Codec {
+    fn encode(&mut self, item: &Record, buf: &mut Vec<u8>) -> Result<()> {
+        self.encoder
+            .get_or_insert_with(|| self.codec.encoder())
+            .encode(item, buf)
+    }
+
+    fn finish(&mut self, buf: &mut Vec<u8>) -> Result<()> {
+        self.encoder
+            .get_or_insert_with(|| self.codec.encoder())
+            .finish(buf)
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.encoder
+            .get_or_insert_with(|| self.codec.encoder())
+            .resume()
+    }
+}
+
+impl<'de> Deserialize<'de> for Codec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let codec = match Value::deserialize(deserializer)? {
+            Value::String(name) => Codec::new(&name),
+            config @ Value::Object(_) => {
+                let config = ComponentConfig::deserialize(config).map_err(D::Error::custom)?;
+                Codec::with_options(&config.kind, config.options)
+            }
+            other => return Err(D::Error::custom(format!("expected a codec, got {other}"))),
+        };
+        codec.map_err(|e| match e {
+            Error::Config(msg) => D::Error::custom(msg),
+            e => D::Error::custom(e),
+        })
+    }
+}
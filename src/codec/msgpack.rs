@@ -0,0 +1,321 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use bytes::BytesMut;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::framing::{Framing, Head, Nested, Scan, argument, covering};
+use super::{Decoder, Encoder};
+use crate::{Error, Result};
+
+/// MessagePack values, deserialized into a `T` (by default, any [`Value`]).
+///
+/// Items are framed according to `framing`. Structs are written as maps
+/// keyed by field name. Errors for malformed items name the item number
+/// within the current input, counting from 1.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, bound = "")]
+pub struct MessagePack<T = Value> {
+    #[serde(default)]
+    framing: Framing,
+    #[serde(skip)]
+    decoded: u64,
+    #[serde(skip)]
+    scan: Scan,
+    #[serde(skip)]
+    item: PhantomData<fn() -> T>,
+}
+
+impl<T> MessagePack<T> {
+    pub fn new() -> Self {
+        Self {
+            framing: Framing::default(),
+            decoded: 0,
+            scan: Scan::default(),
+            item: PhantomData,
+        }
+    }
+
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+}
+
+/// What follows the head of a MessagePack value, after the bytes holding a
+/// length or count.
+enum Then {
+    /// As many bytes as the length, after `extra` bytes, e.g. the type of an
+    /// extension.
+    Bytes { extra: usize },
+    /// `per` values for every one counted.
+    Values { per: u64 },
+}
+
+/// Reads the head of the MessagePack value at the front of `bytes`.
+fn head(bytes: &[u8]) -> Result<Option<Head>, String> {
+    let Some(&first) = bytes.first() else {
+        return Ok(None);
+    };
+    let scalar = |len| {
+        Ok(Some(Head {
+            len,
+            nested: Nested::Values(0),
+        }))
+    };
+    let fixed = |values| {
+        Ok(Some(Head {
+            len: 1,
+            nested: Nested::Values(values),
+        }))
+    };
+    let (size, then) = match first {
+        0x00..=0x7f | 0xc0 | 0xc2 | 0xc3 | 0xe0..=0xff => return scalar(1),
+        0x80..=0x8f => return fixed(2 * u64::from(first & 0x0f)),
+        0x90..=0x9f => return fixed(u64::from(first & 0x0f)),
+        0xa0..=0xbf => return scalar(1 + usize::from(first & 0x1f)),
+        0xc1 => return Err("invalid MessagePack marker 0xc1".to_string()),
+        0xcc | 0xd0 => return scalar(2),
+        0xcd | 0xd1 => return scalar(3),
+        0xca | 0xce | 0xd2 => return scalar(5),
+        0xcb | 0xcf | 0xd3 => return scalar(9),
+        // fixed-size extensions, with their type
+        0xd4 => return scalar(3),
+        0xd5 => return scalar(4),
+        0xd6 => return scalar(6),
+        0xd7 => return scalar(10),
+        0xd8 => return scalar(18),
+        0xc4 | 0xd9 => (1, Then::Bytes { extra: 0 }),
+        0xc5 | 0xda => (2, Then::Bytes { extra: 0 }),
+        0xc6 | 0xdb => (4, Then::Bytes { extra: 0 }),
+        0xc7 => (1, Then::Bytes { extra: 1 }),
+        0xc8 => (2, Then::Bytes { extra: 1 }),
+        0xc9 => (4, Then::Bytes { extra: 1 }),
+        0xdc => (2, Then::Values { per: 1 }),
+        0xdd => (4, Then::Values { per: 1 }),
+        0xde => (2, Then::Values { per: 2 }),
+        0xdf => (4, Then::Values { per: 2 }),
+    };
+    let Some(n) = argument(bytes, size) else {
+        return Ok(None);
+    };
+    let head = match then {
+        Then::Bytes { extra } => Head {
+            len: covering(1 + size + extra, n)?,
+            nested: Nested::Values(0),
+        },
+        Then::Values { per } => Head {
+            len: 1 + size,
+            nested: Nested::Values(per * n),
+        },
+    };
+    Ok(Some(head))
+}
+
+impl<T> Default for MessagePack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Clones start over at item 1.
+impl<T> Clone for MessagePack<T> {
+    fn clone(&self) -> Self {
+        Self::new().framing(self.framing)
+    }
+}
+
+impl<T> fmt::Debug for MessagePack<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MessagePack")
+            .field("framing", &self.framing)
+            .field("decoded", &self.decoded)
+            .finish()
+    }
+}
+
+impl<T: DeserializeOwned> Decoder for MessagePack<T> {
+    type Item = T;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<T>> {
+        let n = self.decoded + 1;
+        let Some(bytes) = self
+            .framing
+            .split(buf, &mut self.scan, head)
+            .map_err(|e| Error::codec(format!("item {n}: {e}")))?
+        else {
+            return Ok(None);
+        };
+        self.decoded = n;
+        rmp_serde::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| Error::codec(format!("item {n}: {e}")))
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<T>> {
+        if let Some(item) = self.decode(buf)? {
+            return Ok(Some(item));
+        }
+        let left = buf.len();
+        self.decoded = 0;
+        self.scan = Scan::default();
+        buf.clear();
+        if left > 0 {
+            return Err(Error::codec(format!(
+                "MessagePack item cut off, {left} bytes left at the end of the input"
+            )));
+        }
+        Ok(None)
+    }
+}
+
+impl<T, U: Serialize + ?Sized> Encoder<U> for MessagePack<T> {
+    fn encode(&mut self, item: &U, buf: &mut Vec<u8>) -> Result<()> {
+        self.framing.write(buf, |buf| {
+            rmp_serde::encode::write_named(buf, item).map_err(Error::codec)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::de::IgnoredAny;
+    use serde_json::json;
+
+    use super::*;
+    use crate::codec::decode_in_chunks;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Event {
+        id: u64,
+        tags: Vec<String>,
+        score: Option<f64>,
+    }
+
+    fn events() -> Vec<Event> {
+        (0..5)
+            .map(|id| Event {
+                id,
+                tags: vec!["a".repeat(id as usize); 2],
+                score: (id % 2 == 1).then_some(id as f64 / 2.0),
+            })
+            .collect()
+    }
+
+    fn encode(codec: &mut MessagePack, items: &[Event]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for item in items {
+            codec.encode(item, &mut buf).unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn round_trips_in_both_framings_and_any_chunks() {
+        for framing in [Framing::Concatenated, Framing::LengthPrefixed] {
+            let bytes = encode(&mut MessagePack::new().framing(framing), &events());
+            for chunk in [1, 5, bytes.len()] {
+                let read = decode_in_chunks(
+                    &mut MessagePack::<Event>::new().framing(framing),
+                    &bytes,
+                    chunk,
+                )
+                .unwrap();
+                assert_eq!(read, events(), "{framing:?} by {chunk}");
+            }
+        }
+    }
+
+    #[test]
+    fn writes_what_the_reference_library_reads() {
+        let bytes = encode(&mut MessagePack::new(), &events()[1..2]);
+        let read: Event = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(read, events()[1]);
+        let dynamic = decode_in_chunks(&mut MessagePack::<Value>::new(), &bytes, 3).unwrap();
+        assert_eq!(
+            dynamic,
+            [json!({"id": 1, "tags": ["a", "a"], "score": 0.5})]
+        );
+    }
+
+    #[test]
+    fn reads_what_the_reference_library_writes() {
+        let bytes: Vec<u8> = events()
+            .iter()
+            .flat_map(|item| rmp_serde::to_vec_named(item).unwrap())
+            .collect();
+        assert_eq!(
+            decode_in_chunks(&mut MessagePack::<Event>::new(), &bytes, 2).unwrap(),
+            events()
+        );
+    }
+
+    #[test]
+    fn names_the_malformed_item() {
+        let mut bytes = encode(&mut MessagePack::new(), &events()[..2]);
+        // the last item loses its last byte
+        bytes.truncate(bytes.len() - 1);
+        let e = decode_in_chunks(&mut MessagePack::<Event>::new(), &bytes, 4).unwrap_err();
+        assert!(e.to_string().contains("MessagePack item cut off"), "{e}");
+
+        let bytes = encode(
+            &mut MessagePack::new().framing(Framing::LengthPrefixed),
+            &events()[..2],
+        );
+        let e = decode_in_chunks(
+            &mut MessagePack::<Vec<u64>>::new().framing(Framing::LengthPrefixed),
+            &bytes,
+            64,
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains("item 1:"), "{e}");
+    }
+
+    fn values() -> Vec<Value> {
+        vec![
+            json!(null),
+            json!([
+                true,
+                false,
+                -1,
+                -100,
+                -1000,
+                -70000,
+                i64::MIN,
+                200,
+                70000,
+                u64::MAX
+            ]),
+            json!([1.5, 1e300]),
+            json!({"short": "a", "long": "b".repeat(300), "longer": "c".repeat(70_000)}),
+            json!((0..30).collect::<Vec<_>>()),
+            json!((0..70_000).map(|_| json!({})).collect::<Vec<_>>()),
+            json!([[], {}, [[[]]], {"a": {"b": []}}]),
+        ]
+    }
+
+    #[test]
+    fn tells_where_every_kind_of_value_ends() {
+        let mut bytes: Vec<u8> = values()
+            .iter()
+            .flat_map(|value| rmp_serde::to_vec(value).unwrap())
+            .collect();
+        for chunk in [1, 7, bytes.len()] {
+            let read = decode_in_chunks(&mut MessagePack::<Value>::new(), &bytes, chunk).unwrap();
+            assert_eq!(read, values(), "by {chunk}");
+        }
+
+        // binaries, extensions and 32-bit floats, which no JSON value becomes
+        bytes.clear();
+        bytes.extend_from_slice(&[0xc4, 2, 1, 2]);
+        bytes.extend_from_slice(&[0xc7, 3, 5, 1, 2, 3]);
+        bytes.extend_from_slice(&[0xd6, 5, 1, 2, 3, 4]);
+        bytes.extend_from_slice(&[0xca, 0x3f, 0x80, 0, 0]);
+        let read = decode_in_chunks(&mut MessagePack::<IgnoredAny>::new(), &bytes, 1).unwrap();
+        assert_eq!(read.len(), 4);
+        let e = decode_in_chunks(&mut MessagePack::<Value>::new(), &[0x01, 0xc1], 1).unwrap_err();
+        assert!(e.to_string().contains("item 2: invalid"), "{e}");
+    }
+}
@@ -0,0 +1,314 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use bytes::BytesMut;
+use futures::{Stream, StreamExt, future};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{Decoder, Encoder};
+use crate::{Error, Failed, Result, TryProcess};
+
+/// What [`Ndjson`] does with lines that do not hold a valid item.
+///
+/// To set malformed lines aside instead, read lines and parse them with a
+/// [`ParseJson`] stage behind a [`DeadLetter`](crate::DeadLetter).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Malformed {
+    /// Fail the input.
+    #[default]
+    Fail,
+    /// Log the line number and carry on.
+    Skip,
+}
+
+/// What serde_json says went wrong, without the position it adds.
+fn message(e: &serde_json::Error) -> String {
+    let position = format!(" at line {} column {}", e.line(), e.column());
+    let e = e.to_string();
+    e.strip_suffix(&position).unwrap_or(&e).to_string()
+}
+
+/// Newline-delimited JSON: every line holds one JSON value, deserialized
+/// into a `T` (by default, any [`Value`]).
+///
+/// Blank lines are skipped. Errors for malformed lines name the line number
+/// within the current input, counting from 1, or from the position resumed
+/// from.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, bound = "")]
+pub struct Ndjson<T = Value> {
+    #[serde(default)]
+    on_malformed: Malformed,
+    #[serde(skip)]
+    line: u64,
+    #[serde(skip)]
+    resumed: bool,
+    #[serde(skip)]
+    item: PhantomData<fn() -> T>,
+}
+
+impl<T> Ndjson<T> {
+    pub fn new() -> Self {
+        Self {
+            on_malformed: Malformed::default(),
+            line: 0,
+            resumed: false,
+            item: PhantomData,
+        }
+    }
+
+    pub fn on_malformed(mut self, on_malformed: Malformed) -> Self {
+        self.on_malformed = on_malformed;
+        self
+    }
+
+    /// Handles a line that did not parse according to `on_malformed`.
+    fn malformed(&mut self, e: serde_json::Error) -> Result<()> {
+        // lines hold no line breaks, so serde_json counts from 1 within them
+        let line = self.line + e.line() as u64 - 1;
+        let line = match self.resumed {
+            true => format!("{line} past the resumed position, column {}", e.column()),
+            false => format!("{line}, column {}", e.column()),
+        };
+        let e = message(&e);
+        match self.on_malformed {
+            Malformed::Fail => return Err(Error::codec(format!("line {line}: {e}"))),
+            Malformed::Skip => tracing::warn!("skipping malformed line {line}: {e}"),
+        }
+        Ok(())
+    }
+}
+
+impl<T: DeserializeOwned> Ndjson<T> {
+    /// Parses one line, without its line ending.
+    fn parse(&mut self, mut bytes: &[u8]) -> Result<Option<T>> {
+        self.line += 1;
+        if let Some(rest) = bytes.strip_suffix(b"\r") {
+            bytes = rest;
+        }
+        if bytes.iter().all(u8::is_ascii_whitespace) {
+            return Ok(None);
+        }
+        match serde_json::from_slice(bytes) {
+            Ok(item) => Ok(Some(item)),
+            Err(e) => self.malformed(e).map(|()| None),
+        }
+    }
+}
+
+impl<T> Default for Ndjson<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Clones start over at line 1.
+impl<T> Clone for Ndjson<T> {
+    fn clone(&self) -> Self {
+        Self::new().on_malformed(self.on_malformed)
+    }
+}
+
+impl<T> fmt::Debug for Ndjson<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ndjson")
+            .field("on_malformed", &self.on_malformed)
+            .field("line", &self.line)
+            .finish()
+    }
+}
+
+impl<T: DeserializeOwned> Decoder for Ndjson<T> {
+    type Item = T;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<T>> {
+        while let Some(end) = buf.iter().position(|&b| b == b'\n') {
+            let line = buf.split_to(end + 1);
+            if let Some(item) = self.parse(&line[..end])? {
+                return Ok(Some(item));
+            }
+        }
+        Ok(None)
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<T>> {
+        if let Some(item) = self.decode(buf)? {
+            return Ok(Some(item));
+        }
+        if !buf.is_empty() {
+            let line = buf.split();
+            if let Some(item) = self.parse(&line)? {
+                return Ok(Some(item));
+            }
+        }
+        self.line = 0;
+        self.resumed = false;
+        Ok(None)
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.resumed = true;
+        Ok(())
+    }
+
+    fn reads_lines(&self) -> bool {
+        true
+    }
+}
+
+impl<T, U: Serialize + ?Sized> Encoder<U> for Ndjson<T> {
+    fn encode(&mut self, item: &U, buf: &mut Vec<u8>) -> Result<()> {
+        serde_json::to_writer(&mut *buf, item).map_err(Error::codec)?;
+        buf.push(b'\n');
+        Ok(())
+    }
+}
+
+/// Parses lines of JSON into `T`s (by default, any [`Value`]), failing with
+/// the lines that do not hold one, so that a [`DeadLetter`](crate::DeadLetter)
+/// stage can set them aside. Blank lines are skipped.
+pub struct ParseJson<T = Value>(PhantomData<fn() -> T>);
+
+impl<T> ParseJson<T> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T> Default for ParseJson<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> fmt::Debug for ParseJson<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ParseJson")
+    }
+}
+
+impl<T: DeserializeOwned + Send> TryProcess<String, T, Error> for ParseJson<T> {
+    async fn try_process<S>(
+        &self,
+        stream: S,
+    ) -> impl Stream<Item = Result<T, Failed<String, Error>>> + Send
+    where
+        S: Stream<Item = String> + Send,
+    {
+        stream
+            .filter(|line| future::ready(!line.trim().is_empty()))
+            .map(|line| {
+                serde_json::from_str(&line).map_err(|e| Failed {
+                    error: Error::codec(format!("column {}: {}", e.column(), message(&e))),
+                    item: line,
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use futures::TryStreamExt;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{DeadLetter, Output, Process};
+
+    fn decode_all(decoder: &mut Ndjson, input: &str) -> Result<Vec<Value>> {
+        let mut buf = BytesMut::from(input);
+        let mut items = Vec::new();
+        while let Some(item) = decoder.decode_eof(&mut buf)? {
+            items.push(item);
+        }
+        Ok(items)
+    }
+
+    #[test]
+    fn decodes_lines_skipping_blank_ones() {
+        let items = decode_all(&mut Ndjson::new(), "{\"a\":1}\r\n\n  \n[2]\n3").unwrap();
+        assert_eq!(items, [json!({"a": 1}), json!([2]), json!(3)]);
+    }
+
+    #[test]
+    fn waits_for_complete_lines() {
+        let mut decoder = Ndjson::<Value>::new();
+        let mut buf = BytesMut::from("{\"a\":");
+        assert!(decoder.decode(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(b"1}\n");
+        assert_eq!(decoder.decode(&mut buf).unwrap(), Some(json!({"a": 1})));
+    }
+
+    #[test]
+    fn names_the_line_and_column_of_malformed_lines() {
+        let e = decode_all(&mut Ndjson::new(), "1\n\n{\"a\" 2}\n").unwrap_err();
+        assert_eq!(e.to_string(), "codec error: line 3, column 6: expected `:`");
+    }
+
+    #[test]
+    fn counts_lines_from_the_resumed_position() {
+        let mut decoder = Ndjson::new();
+        Decoder::resume(&mut decoder).unwrap();
+        let e = decode_all(&mut decoder, "1\nx\n").unwrap_err();
+        assert!(
+            e.to_string().contains("line 2 past the resumed position"),
+            "{e}"
+        );
+    }
+
+    #[test]
+    fn skips_malformed_lines_if_asked_to() {
+        let mut decoder = Ndjson::new().on_malformed(Malformed::Skip);
+        let items = decode_all(&mut decoder, "1\nnope\n2\n").unwrap();
+        assert_eq!(items, [json!(1), json!(2)]);
+    }
+
+    #[test]
+    fn encodes_one_value_per_line() {
+        let mut buf = Vec::new();
+        let mut encoder = Ndjson::<Value>::new();
+        encoder.encode(&json!({"a": "x\ny"}), &mut buf).unwrap();
+        encoder.encode(&json!(2), &mut buf).unwrap();
+        assert_eq!(buf, b"{\"a\":\"x\\ny\"}\n2\n");
+    }
+
+    #[derive(Default, Clone)]
+    struct Collect(Arc<Mutex<Vec<String>>>);
+
+    impl Output<Failed<String, Error>> for Collect {
+        async fn output<S>(&self, stream: S) -> Result<()>
+        where
+            S: Stream<Item = Failed<String, Error>> + Send,
+        {
+            futures::pin_mut!(stream);
+            while let Some(failed) = stream.next().await {
+                let entry = format!("{} <- {}", failed.item, failed.error);
+                self.0.lock().unwrap().push(entry);
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn parse_json_hands_malformed_lines_to_dead_letters() {
+        let dead = Collect::default();
+        let stage = DeadLetter::new(ParseJson::<Value>::new(), dead.clone());
+        let lines = ["1", "", "{oops", "[2]"].map(String::from);
+        let parsed: Vec<Value> = stage
+            .process(futures::stream::iter(lines))
+            .await
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(parsed, [json!(1), json!([2])]);
+        assert_eq!(
+            *dead.0.lock().unwrap(),
+            ["{oops <- codec error: column 2: key must be a string"]
+        );
+    }
+}
@@ -0,0 +1,459 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use bytes::{Buf, BytesMut};
+use prost_reflect::prost::Message;
+use prost_reflect::{
+    DescriptorPool, DeserializeOptions, DynamicMessage, MessageDescriptor, SerializeOptions,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{Decoder, Encoder};
+use crate::fio::with_path;
+use crate::{Error, Result};
+
+/// The longest length prefix, a varint of a `u64`.
+const MAX_PREFIX: usize = 10;
+
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+fn default_max_message_size() -> usize {
+    DEFAULT_MAX_MESSAGE_SIZE
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProtobufConfig {
+    descriptor_set: PathBuf,
+    message: String,
+    #[serde(default)]
+    proto_field_names: bool,
+    #[serde(default)]
+    emit_defaults: bool,
+    #[serde(default)]
+    enum_numbers: bool,
+    #[serde(default)]
+    ignore_unknown_fields: bool,
+    #[serde(default = "default_max_message_size")]
+    max_message_size: usize,
+}
+
+/// Length-delimited Protobuf messages of one type, each prefixed by its
+/// length as a varint, with items deserialized into a `T` (by default, any
+/// [`Value`]).
+///
+/// The message type is looked up by its full name, e.g. `events.v1.Click`,
+/// in `descriptor_set`, a file holding a serialized `FileDescriptorSet` as
+/// written by `protoc --include_imports --descriptor_set_out`. No code needs
+/// to be generated for it.
+///
+/// Messages map to items like in Protobuf's JSON mapping: fields go by their
+/// lowerCamelCase JSON names unless `proto_field_names` is set, fields left
+/// at their default are omitted unless `emit_defaults` is set, and enums go
+/// by name unless `enum_numbers` is set. Unlike in the JSON mapping, 64-bit
+/// integers are numbers rather than strings. Encoding fails for item fields
+/// the message does not have, unless `ignore_unknown_fields` is set.
+///
+/// Decoding fails for messages longer than `max_message_size` bytes, 64 MiB
+/// by default, as soon as their length prefix is read, rather than waiting
+/// for all of them when the prefix is corrupt.
+#[derive(Deserialize)]
+#[serde(try_from = "ProtobufConfig", bound = "")]
+pub struct Protobuf<T = Value> {
+    message: MessageDescriptor,
+    serialize: SerializeOptions,
+    deserialize: DeserializeOptions,
+    max_message_size: usize,
+    decoded: u64,
+    item: PhantomData<fn() -> T>,
+}
+
+impl<T> TryFrom<ProtobufConfig> for Protobuf<T> {
+    type Error = String;
+
+    fn try_from(config: ProtobufConfig) -> Result<Self, String> {
+        let protobuf = Self::from_file(&config.descriptor_set, &config.message)
+            .map_err(|e| match e {
+                Error::Config(msg) => msg,
+                e => e.to_string(),
+            })?
+            .proto_field_names(config.proto_field_names)
+            .emit_defaults(config.emit_defaults)
+            .enum_numbers(config.enum_numbers)
+            .ignore_unknown_fields(config.ignore_unknown_fields)
+            .max_message_size(config.max_message_size);
+        Ok(protobuf)
+    }
+}
+
+impl<T> Protobuf<T> {
+    pub fn new(message: MessageDescriptor) -> Self {
+        Self {
+            message,
+            serialize: SerializeOptions::new().stringify_64_bit_integers(false),
+            deserialize: DeserializeOptions::new(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            decoded: 0,
+            item: PhantomData,
+        }
+    }
+
+    /// Looks up `message` in the `FileDescriptorSet` stored at `path`.
+    pub fn from_file<P: AsRef<Path>>(path: P, message: &str) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(with_path(path))?;
+        let pool = DescriptorPool::decode(bytes.as_slice())
+            .map_err(|e| Error::config(format!("{}: {e}", path.display())))?;
+        let descriptor = pool.get_message_by_name(message).ok_or_else(|| {
+            Error::config(format!(
+                "{}: no message `{message}`, expected one of {}",
+                path.display(),
+                pool.all_messages()
+                    .map(|message| message.full_name().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        })?;
+        Ok(Self::new(descriptor))
+    }
+
+    pub fn proto_field_names(mut self, yes: bool) -> Self {
+        self.serialize = self.serialize.use_proto_field_name(yes);
+        self
+    }
+
+    pub fn emit_defaults(mut self, yes: bool) -> Self {
+        self.serialize = self.serialize.skip_default_fields(!yes);
+        self
+    }
+
+    pub fn enum_numbers(mut self, yes: bool) -> Self {
+        self.serialize = self.serialize.use_enum_numbers(yes);
+        self
+    }
+
+    pub fn ignore_unknown_fields(mut self, yes: bool) -> Self {
+        self.deserialize = self.deserialize.deny_unknown_fields(!yes);
+        self
+    }
+
+    /// The length of the longest message to decode, in bytes.
+    pub fn max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// The length of the message at the front of `buf` along with the length
+    /// of its prefix, or `None` while `buf` does not hold all of the prefix.
+    fn prefix(buf: &[u8]) -> Result<Option<(usize, usize)>> {
+        let mut len = 0u64;
+        for (i, &byte) in buf.iter().take(MAX_PREFIX).enumerate() {
+            len |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                let len =
+                    usize::try_from(len).map_err(|_| Error::codec("Protobuf message too large"))?;
+                return Ok(Some((i + 1, len)));
+            }
+        }
+        if buf.len() >= MAX_PREFIX {
+            return Err(Error::codec("invalid Protobuf length prefix"));
+        }
+        Ok(None)
+    }
+}
+
+/// Clones start over as for a new input or output.
+impl<T> Clone for Protobuf<T> {
+    fn clone(&self) -> Self {
+        Self {
+            message: self.message.clone(),
+            serialize: self.serialize.clone(),
+            deserialize: self.deserialize.clone(),
+            max_message_size: self.max_message_size,
+            decoded: 0,
+            item: PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for Protobuf<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Protobuf")
+            .field("message", &self.message.full_name())
+            .field("max_message_size", &self.max_message_size)
+            .field("decoded", &self.decoded)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: DeserializeOwned> Decoder for Protobuf<T> {
+    type Item = T;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<T>> {
+        let Some((prefix, len)) = Self::prefix(buf)? else {
+            return Ok(None);
+        };
+        if len > self.max_message_size {
+            return Err(Error::codec(format!(
+                "message {}: {len} bytes long, more than max_message_size of {}",
+                self.decoded + 1,
+                self.max_message_size
+            )));
+        }
+        if buf.len() < prefix + len {
+            return Ok(None);
+        }
+        buf.advance(prefix);
+        let bytes = buf.split_to(len);
+        self.decoded += 1;
+        let n = self.decoded;
+        let message = DynamicMessage::decode(self.message.clone(), bytes)
+            .map_err(|e| Error::codec(format!("message {n}: {e}")))?;
+        let value = message
+            .serialize_with_options(serde_json::value::Serializer, &self.serialize)
+            .map_err(|e| Error::codec(format!("message {n}: {e}")))?;
+        serde_json::from_value(value)
+            .map(Some)
+            .map_err(|e| Error::codec(format!("message {n}: {e}")))
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<T>> {
+        if let Some(item) = self.decode(buf)? {
+            return Ok(Some(item));
+        }
+        let left = buf.len();
+        self.decoded = 0;
+        buf.clear();
+        if left > 0 {
+            return Err(Error::codec(format!(
+                "Protobuf message cut off, {left} bytes left at the end of the input"
+            )));
+        }
+        Ok(None)
+    }
+}
+
+impl<T, U: Serialize + ?Sized> Encoder<U> for Protobuf<T> {
+    fn encode(&mut self, item: &U, buf: &mut Vec<u8>) -> Result<()> {
+        let value = serde_json::to_value(item).map_err(Error::codec)?;
+        let message = DynamicMessage::deserialize_with_options(
+            self.message.clone(),
+            value,
+            &self.deserialize,
+        )
+        .map_err(|e| Error::codec(format!("{}: {e}", self.message.full_name())))?;
+        message.encode_length_delimited(buf).map_err(Error::codec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_reflect::prost_types::field_descriptor_proto::{Label, Type};
+    use prost_reflect::prost_types::{
+        DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+        FileDescriptorProto, FileDescriptorSet,
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::codec::decode_in_chunks;
+
+    fn field(name: &str, number: i32, kind: Type) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            label: Some(Label::Optional as i32),
+            r#type: Some(kind as i32),
+            ..Default::default()
+        }
+    }
+
+    /// `events.v1.Click`, with an `id`, a `page_name`, a `kind` and `tags`.
+    fn descriptor_set() -> FileDescriptorSet {
+        let kind = FieldDescriptorProto {
+            type_name: Some(".events.v1.Kind".to_string()),
+            ..field("kind", 3, Type::Enum)
+        };
+        let tags = FieldDescriptorProto {
+            label: Some(Label::Repeated as i32),
+            ..field("tags", 4, Type::String)
+        };
+        let click = DescriptorProto {
+            name: Some("Click".to_string()),
+            field: vec![
+                field("id", 1, Type::Int64),
+                field("page_name", 2, Type::String),
+                kind,
+                tags,
+            ],
+            ..Default::default()
+        };
+        let kind = EnumDescriptorProto {
+            name: Some("Kind".to_string()),
+            value: ["KIND_UNSPECIFIED", "KIND_TAP"]
+                .into_iter()
+                .zip(0..)
+                .map(|(name, number)| EnumValueDescriptorProto {
+                    name: Some(name.to_string()),
+                    number: Some(number),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        FileDescriptorSet {
+            file: vec![FileDescriptorProto {
+                name: Some("events.proto".to_string()),
+                package: Some("events.v1".to_string()),
+                syntax: Some("proto3".to_string()),
+                message_type: vec![click],
+                enum_type: vec![kind],
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn click() -> MessageDescriptor {
+        DescriptorPool::from_file_descriptor_set(descriptor_set())
+            .unwrap()
+            .get_message_by_name("events.v1.Click")
+            .unwrap()
+    }
+
+    fn clicks() -> Vec<Value> {
+        vec![
+            json!({"id": 1, "pageName": "home", "kind": "KIND_TAP", "tags": ["a", "b"]}),
+            json!({}),
+            json!({"id": i64::MAX, "pageName": "p".repeat(300)}),
+        ]
+    }
+
+    fn encode(protobuf: &mut Protobuf, items: &[Value]) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        for item in items {
+            protobuf.encode(item, &mut buf)?;
+        }
+        Ok(buf)
+    }
+
+    #[test]
+    fn round_trips_in_any_chunks() {
+        let bytes = encode(&mut Protobuf::new(click()), &clicks()).unwrap();
+        for chunk in [1, 5, bytes.len()] {
+            let read = decode_in_chunks(&mut Protobuf::<Value>::new(click()), &bytes, chunk);
+            assert_eq!(read.unwrap(), clicks(), "by {chunk}");
+        }
+    }
+
+    #[test]
+    fn reads_what_prost_writes() {
+        let mut message = DynamicMessage::new(click());
+        message.set_field_by_name("id", prost_reflect::Value::I64(7));
+        message.set_field_by_name("kind", prost_reflect::Value::EnumNumber(1));
+        let mut bytes = Vec::new();
+        message.encode_length_delimited(&mut bytes).unwrap();
+        DynamicMessage::new(click())
+            .encode_length_delimited(&mut bytes)
+            .unwrap();
+        let read = decode_in_chunks(&mut Protobuf::<Value>::new(click()), &bytes, 2).unwrap();
+        assert_eq!(read, [json!({"id": 7, "kind": "KIND_TAP"}), json!({})]);
+    }
+
+    #[test]
+    fn maps_fields_as_configured() {
+        let bytes = encode(&mut Protobuf::new(click()), &clicks()[..2]).unwrap();
+        let mut protobuf = Protobuf::<Value>::new(click())
+            .proto_field_names(true)
+            .emit_defaults(true)
+            .enum_numbers(true);
+        let read = decode_in_chunks(&mut protobuf, &bytes, bytes.len()).unwrap();
+        assert_eq!(
+            read,
+            [
+                json!({"id": 1, "page_name": "home", "kind": 1, "tags": ["a", "b"]}),
+                json!({"id": 0, "page_name": "", "kind": 0, "tags": []}),
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_to_encode_unknown_fields_unless_told_to_ignore_them() {
+        let item = [json!({"id": 1, "referrer": "search"})];
+        let e = encode(&mut Protobuf::new(click()), &item).unwrap_err();
+        assert!(e.to_string().contains("events.v1.Click"), "{e}");
+        let mut protobuf = Protobuf::new(click()).ignore_unknown_fields(true);
+        let bytes = encode(&mut protobuf, &item).unwrap();
+        let read = decode_in_chunks(&mut Protobuf::<Value>::new(click()), &bytes, 1).unwrap();
+        assert_eq!(read, [json!({"id": 1})]);
+    }
+
+    #[test]
+    fn fails_on_messages_cut_off_at_the_end() {
+        let bytes = encode(&mut Protobuf::new(click()), &clicks()).unwrap();
+        let e = decode_in_chunks(
+            &mut Protobuf::<Value>::new(click()),
+            &bytes[..bytes.len() - 1],
+            16,
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains("message cut off"), "{e}");
+    }
+
+    #[test]
+    fn refuses_messages_longer_than_the_maximum_once_their_prefix_is_read() {
+        // a length of 4 GiB
+        let mut buf = BytesMut::from(&[0x80, 0x80, 0x80, 0x80, 0x10][..]);
+        let e = Protobuf::<Value>::new(click())
+            .decode(&mut buf)
+            .unwrap_err();
+        assert!(e.to_string().contains("message 1: 4294967296 bytes"), "{e}");
+
+        let bytes = encode(&mut Protobuf::new(click()), &clicks()).unwrap();
+        let mut protobuf = Protobuf::<Value>::new(click()).max_message_size(100);
+        let e = decode_in_chunks(&mut protobuf, &bytes, 1).unwrap_err();
+        assert!(e.to_string().contains("message 3:"), "{e}");
+        assert!(e.to_string().contains("max_message_size of 100"), "{e}");
+
+        let mut buf = BytesMut::from(&[0xff; MAX_PREFIX][..]);
+        let e = Protobuf::<Value>::new(click())
+            .decode(&mut buf)
+            .unwrap_err();
+        assert!(
+            e.to_string().contains("invalid Protobuf length prefix"),
+            "{e}"
+        );
+    }
+
+    #[test]
+    fn looks_messages_up_in_descriptor_set_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.pb");
+        std::fs::write(&path, descriptor_set().encode_to_vec()).unwrap();
+
+        let protobuf: Protobuf = serde_json::from_value(json!({
+            "descriptor_set": path,
+            "message": "events.v1.Click",
+            "max_message_size": 1000,
+        }))
+        .unwrap();
+        assert_eq!(protobuf.message.full_name(), "events.v1.Click");
+        assert_eq!(protobuf.max_message_size, 1000);
+
+        let e = Protobuf::<Value>::from_file(&path, "events.v1.Tap").unwrap_err();
+        assert!(matches!(e, Error::Config(_)), "{e}");
+        assert!(e.to_string().contains("no message `events.v1.Tap`"), "{e}");
+        assert!(
+            e.to_string().contains("expected one of events.v1.Click"),
+            "{e}"
+        );
+        let e = serde_json::from_value::<Protobuf>(json!({
+            "descriptor_set": path,
+            "message": "Click",
+        }))
+        .unwrap_err();
+        assert!(e.to_string().contains("no message `Click`"), "{e}");
+    }
+}
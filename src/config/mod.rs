@@ -0,0 +1,235 @@
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::pipeline::DEFAULT_CHECKPOINT_INTERVAL;
+use crate::{Error, Result};
+
+/// A pipeline described as data: one input, any number of process stages
+/// and one output, each selected by the name it is registered under.
+///
+/// ```yaml
+/// input:
+///   type: stdin
+/// output:
+///   type: http
+///   endpoint: http://localhost:9200/_bulk
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct PipelineConfig {
+    pub input: ComponentConfig,
+    #[serde(default)]
+    pub process: Vec<ComponentConfig>,
+    pub output: ComponentConfig,
+    /// How often checkpoints acknowledged by the output are committed while
+    /// the pipeline runs.
+    #[serde(default = "default_checkpoint_interval", with = "humantime_serde")]
+    pub checkpoint_interval: Duration,
+}
+
+fn default_checkpoint_interval() -> Duration {
+    DEFAULT_CHECKPOINT_INTERVAL
+}
+
+/// The registered `type` of a component and the options it is built from.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ComponentConfig {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(flatten)]
+    pub options: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    /// Picks the format from the file extension.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Ok(Format::Json),
+            Some("yaml" | "yml") => Ok(Format::Yaml),
+            Some("toml") => Ok(Format::Toml),
+            _ => Err(Error::config(format!(
+                "cannot tell the format of {} from its extension",
+                path.display()
+            ))),
+        }
+    }
+}
+
+impl PipelineConfig {
+    pub fn parse(s: &str, format: Format) -> Result<Self> {
+        match format {
+            Format::Json => serde_json::from_str(s).map_err(|e| Error::config(e.to_string())),
+            Format::Yaml => serde_yaml::from_str(s).map_err(|e| Error::config(e.to_string())),
+            Format::Toml => toml::from_str(s).map_err(|e| Error::config(e.to_string())),
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let format = Format::from_path(path)?;
+        Self::parse(&std::fs::read_to_string(path)?, format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const YAML: &str = r#"
+input:
+  type: file
+  path: in.jsonl
+process:
+  - type: filter
+    field: level
+  - type: batch
+output:
+  type: http
+  endpoint: http://localhost:9200/_bulk
+  batch_size: 500
+checkpoint_interval: 1m 30s
+"#;
+
+    const TOML: &str = r#"
+checkpoint_interval = "1m 30s"
+
+[input]
+type = "file"
+path = "in.jsonl"
+
+[[process]]
+type = "filter"
+field = "level"
+
+[[process]]
+type = "batch"
+
+[output]
+type = "http"
+endpoint = "http://localhost:9200/_bulk"
+batch_size = 500
+"#;
+
+    const JSON: &str = r#"{
+        "input": {"type": "file", "path": "in.jsonl"},
+        "process": [{"type": "filter", "field": "level"}, {"type": "batch"}],
+        "output": {"type": "http", "endpoint": "http://localhost:9200/_bulk", "batch_size": 500},
+        "checkpoint_interval": "1m 30s"
+    }"#;
+
+    fn assert_parsed(config: &PipelineConfig) {
+        assert_eq!(config.input.kind, "file");
+        assert_eq!(config.input.options["path"], "in.jsonl");
+        let kinds: Vec<_> = config.process.iter().map(|c| c.kind.as_str()).collect();
+        assert_eq!(kinds, ["filter", "batch"]);
+        assert_eq!(config.process[0].options["field"], "level");
+        assert!(config.process[1].options.is_empty());
+        assert_eq!(config.output.kind, "http");
+        assert_eq!(
+            Value::Object(config.output.options.clone()),
+            json!({"endpoint": "http://localhost:9200/_bulk", "batch_size": 500})
+        );
+        assert_eq!(config.checkpoint_interval, Duration::from_secs(90));
+    }
+
+    #[test]
+    fn tells_formats_from_extensions() {
+        for (path, format) in [
+            ("pipeline.json", Format::Json),
+            ("pipeline.yaml", Format::Yaml),
+            ("conf/pipeline.yml", Format::Yaml),
+            ("pipeline.toml", Format::Toml),
+        ] {
+            assert_eq!(Format::from_path(Path::new(path)).unwrap(), format);
+        }
+        for path in ["pipeline.ini", "pipeline", "pipeline.YAML"] {
+            let e = Format::from_path(Path::new(path)).unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                format!(
+                    "invalid configuration: cannot tell the format of {path} from its extension"
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn parses_every_format_alike() {
+        assert_parsed(&PipelineConfig::parse(YAML, Format::Yaml).unwrap());
+        assert_parsed(&PipelineConfig::parse(TOML, Format::Toml).unwrap());
+        assert_parsed(&PipelineConfig::parse(JSON, Format::Json).unwrap());
+    }
+
+    #[test]
+    fn defaults_stages_and_checkpoint_interval() {
+        let config =
+            PipelineConfig::parse("input: {type: stdin}\noutput: {type: stdout}", Format::Yaml)
+                .unwrap();
+        assert!(config.process.is_empty());
+        assert_eq!(config.checkpoint_interval, DEFAULT_CHECKPOINT_INTERVAL);
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        for (document, format, message) in [
+            (
+                "input: {type: stdin}",
+                Format::Yaml,
+                "missing field `output`",
+            ),
+            (
+                "input: {path: in.jsonl}\noutput: {type: stdout}",
+                Format::Yaml,
+                "missing field `type`",
+            ),
+            ("input: [stdin", Format::Yaml, ""),
+            (
+                r#"{"input": {"type": "stdin"}, "output": {"type": "stdout"}, "process": {}}"#,
+                Format::Json,
+                "invalid type",
+            ),
+            (
+                "checkpoint_interval = \"soon\"\ninput = {type = \"stdin\"}\noutput = {type = \"stdout\"}",
+                Format::Toml,
+                "soon",
+            ),
+            ("[input\ntype = \"stdin\"", Format::Toml, ""),
+        ] {
+            let e = PipelineConfig::parse(document, format).unwrap_err();
+            assert!(matches!(e, Error::Config(_)), "{document}: {e}");
+            assert!(e.to_string().contains(message), "{document}: {e}");
+        }
+    }
+
+    #[test]
+    fn reads_files_in_the_format_of_their_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, document) in [("p.yml", YAML), ("p.toml", TOML), ("p.json", JSON)] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, document).unwrap();
+            assert_parsed(&PipelineConfig::from_path(&path).unwrap());
+        }
+
+        // the extension decides, not the contents
+        let path = dir.path().join("p.json");
+        std::fs::write(&path, YAML).unwrap();
+        assert!(matches!(
+            PipelineConfig::from_path(&path),
+            Err(Error::Config(_))
+        ));
+
+        let e = PipelineConfig::from_path(dir.path().join("missing.yaml")).unwrap_err();
+        assert!(matches!(e, Error::Io(_)), "{e}");
+    }
+}
@@ -1,38 +1,128 @@
-use crate::{Input, Output};
+use crate::codec::{Decode, Decoder, Encode, Encoder, Lines};
+use crate::fio::{Compression, compress, decompress};
+use crate::{Input, Output, Result};
 use futures::stream::StreamExt;
 use serde::Deserialize;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
-use tokio_stream::wrappers::LinesStream;
+use tokio::io::AsyncWriteExt;
 
-#[derive(Debug, Deserialize)]
-pub struct Stdin {}
+/// Reads standard input, line by line.
+///
+/// Input compressed with gzip, zstd, bzip2, xz or lz4 is decompressed, told
+/// apart by its first bytes; `compression` names the compression instead.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stdin {
+    #[serde(default)]
+    compression: Option<Compression>,
+}
+
+impl Stdin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+}
 
 impl Input<String> for Stdin {
-    fn into_stream(self) -> impl futures::Stream<Item = String> {
-        LinesStream::new(tokio::io::BufReader::new(tokio::io::stdin()).lines())
-            .filter_map(|line| async move { line.ok() })
+    fn into_stream(self) -> impl futures::Stream<Item = Result<String>> + Send {
+        Decode::new(self, Lines {}).into_stream()
+    }
+}
+
+impl<D> Input<D::Item> for Decode<Stdin, D>
+where
+    D: Decoder + Send,
+    D::Item: Send,
+{
+    fn into_stream(self) -> impl futures::Stream<Item = Result<D::Item>> + Send {
+        async_stream::try_stream! {
+            let reader = decompress(tokio::io::stdin(), self.input.compression).await?;
+            for await item in crate::codec::decode(reader, self.decoder) {
+                yield item?;
+            }
+        }
     }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Stdout {}
+/// Writes to standard output, one item per line.
+///
+/// With `compression` set, the output is compressed as it is written, at
+/// `compression_level` if given.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stdout {
+    #[serde(default)]
+    compression: Option<Compression>,
+    #[serde(default)]
+    compression_level: Option<i32>,
+}
+
+impl Stdout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    pub fn compression_level(mut self, level: i32) -> Self {
+        self.compression_level = Some(level);
+        self
+    }
+}
 
 impl<T: std::fmt::Display + Send> Output<T> for Stdout {
-    fn output<S>(
-        &self,
-        stream: S,
-    ) -> impl std::future::Future<Output = Result<(), std::io::Error>> + Send
+    async fn output<S>(&self, stream: S) -> Result<()>
     where
         S: futures::Stream<Item = T> + Send,
     {
-        async move {
-            let mut stdout = tokio::io::stdout();
-            futures::pin_mut!(stream);
-            while let Some(o) = stream.next().await {
-                let s = format!("{o}\n");
-                stdout.write(s.as_bytes()).await.unwrap();
-            }
-            Ok(())
+        let mut stdout = compress(
+            tokio::io::stdout(),
+            self.compression,
+            self.compression_level,
+        );
+        futures::pin_mut!(stream);
+        while let Some(o) = stream.next().await {
+            let s = format!("{o}\n");
+            stdout.write_all(s.as_bytes()).await?;
+        }
+        stdout.shutdown().await?;
+        Ok(())
+    }
+}
+
+impl<T, E> Output<T> for Encode<Stdout, E>
+where
+    T: Send,
+    E: Encoder<T> + Clone + Send + Sync,
+{
+    async fn output<S>(&self, stream: S) -> Result<()>
+    where
+        S: futures::Stream<Item = T> + Send,
+    {
+        let mut encoder = self.encoder.clone();
+        let mut stdout = compress(
+            tokio::io::stdout(),
+            self.output.compression,
+            self.output.compression_level,
+        );
+        let mut buf = Vec::new();
+        futures::pin_mut!(stream);
+        while let Some(item) = stream.next().await {
+            buf.clear();
+            encoder.encode(&item, &mut buf)?;
+            stdout.write_all(&buf).await?;
         }
+        buf.clear();
+        encoder.finish(&mut buf)?;
+        stdout.write_all(&buf).await?;
+        stdout.shutdown().await?;
+        Ok(())
     }
 }
@@ -0,0 +1,310 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use futures::channel::mpsc;
+use futures::{SinkExt, Stream, StreamExt, future, stream};
+
+use crate::pipeline::Failure;
+use crate::{Error, Output, Process, Result, TryProcess};
+
+/// Number of failed items buffered before the dead-letter output applies
+/// backpressure to the process stage.
+const DEAD_LETTER_BUFFER: usize = 64;
+
+/// An item a [`TryProcess`] stage could not handle, together with the reason.
+#[derive(Debug, Clone)]
+pub struct Failed<T, E> {
+    pub item: T,
+    pub error: E,
+}
+
+impl<T: fmt::Debug, E: fmt::Display> fmt::Display for Failed<T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:?}", self.error, self.item)
+    }
+}
+
+/// What a [`DeadLetter`] stage does after routing a failed item.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Keep going; failed items only end up in the dead-letter output.
+    #[default]
+    Skip,
+    /// Stop the pipeline at the first failed item.
+    Stop,
+    /// Stop the pipeline once this many items have failed.
+    StopAfter(u64),
+}
+
+impl ErrorPolicy {
+    fn should_stop(self, failures: u64) -> bool {
+        match self {
+            ErrorPolicy::Skip => false,
+            ErrorPolicy::Stop => true,
+            ErrorPolicy::StopAfter(n) => failures >= n,
+        }
+    }
+}
+
+/// Turns a [`TryProcess`] into a [`Process`] by sending failed items to a
+/// dead-letter output.
+///
+/// Failed items are always delivered to the dead-letter output before the
+/// stage stops according to its [`ErrorPolicy`]. An error from the
+/// dead-letter output itself stops the pipeline.
+pub struct DeadLetter<P, D, E> {
+    process: P,
+    sink: D,
+    policy: ErrorPolicy,
+    _marker: PhantomData<fn() -> E>,
+}
+
+impl<P, D, E> DeadLetter<P, D, E> {
+    pub fn new(process: P, sink: D) -> Self {
+        Self {
+            process,
+            sink,
+            policy: ErrorPolicy::default(),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn policy(mut self, policy: ErrorPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+}
+
+impl<T, U, E, P, D> Process<T, U> for DeadLetter<P, D, E>
+where
+    P: TryProcess<T, U, E> + Sync,
+    D: Output<Failed<T, E>> + Sync,
+    T: Send,
+    U: Send,
+    E: fmt::Display + Send,
+{
+    async fn process<S>(&self, stream: S) -> impl Stream<Item = Result<U>> + Send
+    where
+        S: Stream<Item = T> + Send,
+    {
+        let (mut tx, rx) = mpsc::channel(DEAD_LETTER_BUFFER);
+        let failure = Failure::default();
+        let policy = self.policy;
+        let results = self.process.try_process(stream).await;
+
+        let routed = {
+            let failure = failure.clone();
+            async_stream::stream! {
+                futures::pin_mut!(results);
+                let mut failures = 0;
+                while let Some(result) = results.next().await {
+                    match result {
+                        Ok(item) => yield Ok(item),
+                        Err(failed) => {
+                            failures += 1;
+                            let reason = failed.error.to_string();
+                            if tx.send(failed).await.is_err() {
+                                // the dead-letter output stopped early and reports why
+                                break;
+                            }
+                            if policy.should_stop(failures) {
+                                failure.set(Error::user(format!(
+                                    "stopped after {failures} failed item(s), last: {reason}"
+                                )));
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        };
+        let drain = stream::once(self.sink.output(rx))
+            .filter_map(|result| future::ready(result.err().map(Err)));
+
+        stream::select(routed, drain)
+            .chain(stream::once(async move { failure.take().map(Err) }).filter_map(future::ready))
+    }
+
+    /// Failed items wait in a buffer, and then in the dead-letter output,
+    /// while the items after them are passed on.
+    fn holds_items(&self) -> bool {
+        true
+    }
+}
+
+/// A dead-letter output writing the items of failures through `O` as they
+/// were, logging their errors, so that they can be fed in again later.
+#[derive(Debug, Clone)]
+pub struct FailedItems<O>(pub O);
+
+impl<T, E, O> Output<Failed<T, E>> for FailedItems<O>
+where
+    O: Output<T> + Sync,
+    T: Send,
+    E: fmt::Display + Send,
+{
+    async fn output<S>(&self, stream: S) -> Result<()>
+    where
+        S: Stream<Item = Failed<T, E>> + Send,
+    {
+        let items = stream.map(|failed| {
+            tracing::warn!("setting aside failed item: {}", failed.error);
+            failed.item
+        });
+        self.0.output(items).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::ParseIntError;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Parses numbers, failing on anything else.
+    struct ParseNumbers;
+
+    impl TryProcess<&'static str, i64, String> for ParseNumbers {
+        async fn try_process<S>(
+            &self,
+            stream: S,
+        ) -> impl Stream<Item = std::result::Result<i64, Failed<&'static str, String>>> + Send
+        where
+            S: Stream<Item = &'static str> + Send,
+        {
+            stream.map(|item| {
+                item.parse().map_err(|e: ParseIntError| Failed {
+                    item,
+                    error: e.to_string(),
+                })
+            })
+        }
+    }
+
+    /// Collects the items it is given.
+    #[derive(Debug, Default, Clone)]
+    struct Collect(Arc<Mutex<Vec<&'static str>>>);
+
+    impl Output<&'static str> for Collect {
+        async fn output<S>(&self, stream: S) -> Result<()>
+        where
+            S: Stream<Item = &'static str> + Send,
+        {
+            stream
+                .for_each(|item| {
+                    self.0.lock().unwrap().push(item);
+                    future::ready(())
+                })
+                .await;
+            Ok(())
+        }
+    }
+
+    /// Fails without taking any item.
+    struct Broken;
+
+    impl Output<Failed<&'static str, String>> for Broken {
+        async fn output<S>(&self, _stream: S) -> Result<()>
+        where
+            S: Stream<Item = Failed<&'static str, String>> + Send,
+        {
+            Err(Error::user("disk full"))
+        }
+    }
+
+    /// Runs `items` through a [`DeadLetter`] stage with `policy`, returning
+    /// what it passed on and the items it set aside.
+    async fn run(
+        policy: ErrorPolicy,
+        items: &[&'static str],
+    ) -> (Vec<i64>, Option<Error>, Vec<&'static str>) {
+        let set_aside = Collect::default();
+        let stage = DeadLetter::new(ParseNumbers, FailedItems(set_aside.clone())).policy(policy);
+        let results: Vec<_> = stage
+            .process(stream::iter(items.to_vec()))
+            .await
+            .collect()
+            .await;
+        let mut passed = Vec::new();
+        let mut error = None;
+        for result in results {
+            match result {
+                Ok(n) => passed.push(n),
+                Err(e) => {
+                    assert!(error.is_none(), "a second error: {e}");
+                    error = Some(e);
+                }
+            }
+        }
+        let set_aside = set_aside.0.lock().unwrap().clone();
+        (passed, error, set_aside)
+    }
+
+    #[tokio::test]
+    async fn skips_failed_items_after_setting_them_aside() {
+        let (passed, error, set_aside) = run(ErrorPolicy::Skip, &["1", "x", "2", "", "3"]).await;
+        assert_eq!(passed, [1, 2, 3]);
+        assert!(error.is_none());
+        assert_eq!(set_aside, ["x", ""]);
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_first_failed_item() {
+        let (passed, error, set_aside) = run(ErrorPolicy::Stop, &["1", "x", "2", "y"]).await;
+        assert_eq!(passed, [1]);
+        let error = error.unwrap();
+        assert!(matches!(error, Error::User(_)), "{error}");
+        assert_eq!(
+            error.to_string(),
+            "stopped after 1 failed item(s), last: invalid digit found in string"
+        );
+        assert_eq!(set_aside, ["x"]);
+    }
+
+    #[tokio::test]
+    async fn stops_once_as_many_items_failed_as_allowed() {
+        let items = ["1", "x", "2", "", "3", "z", "4"];
+        let (passed, error, set_aside) = run(ErrorPolicy::StopAfter(2), &items).await;
+        assert_eq!(passed, [1, 2]);
+        assert_eq!(
+            error.unwrap().to_string(),
+            "stopped after 2 failed item(s), last: cannot parse integer from empty string"
+        );
+        assert_eq!(set_aside, ["x", ""]);
+
+        // fewer failures than allowed
+        let (passed, error, set_aside) = run(ErrorPolicy::StopAfter(3), &items).await;
+        assert_eq!(passed, [1, 2, 3]);
+        assert!(error.unwrap().to_string().starts_with("stopped after 3"));
+        assert_eq!(set_aside, ["x", "", "z"]);
+        let (passed, error, _) = run(ErrorPolicy::StopAfter(4), &items).await;
+        assert_eq!(passed, [1, 2, 3, 4]);
+        assert!(error.is_none());
+    }
+
+    #[tokio::test]
+    async fn fails_with_the_error_of_the_dead_letter_output() {
+        let stage = DeadLetter::new(ParseNumbers, Broken);
+        let results: Vec<_> = stage
+            .process(stream::iter(["1", "x", "2", "y"]))
+            .await
+            .collect()
+            .await;
+        let errors: Vec<_> = results
+            .iter()
+            .filter_map(|result| result.as_ref().err())
+            .collect();
+        assert_eq!(errors.len(), 1, "{results:?}");
+        assert_eq!(errors[0].to_string(), "disk full");
+    }
+
+    #[test]
+    fn shows_failures_with_their_item() {
+        let failed = Failed {
+            item: "x",
+            error: "not a number",
+        };
+        assert_eq!(failed.to_string(), r#"not a number: "x""#);
+    }
+}
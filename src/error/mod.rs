@@ -0,0 +1,120 @@
+use http::StatusCode;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors surfaced by inputs, process stages and outputs.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Reading or writing a local resource failed.
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// Items could not be decoded from or encoded to their wire format.
+    #[error("codec error: {0}")]
+    Codec(#[source] BoxError),
+
+    /// A remote endpoint answered with a non-success status.
+    #[error("unexpected status [{status}]: {body}")]
+    Status { status: StatusCode, body: String },
+
+    /// A remote endpoint accepted a batch of items but rejected some of them.
+    #[error("{rejected} of {items} items rejected: {error}")]
+    Rejected {
+        rejected: usize,
+        items: usize,
+        error: String,
+    },
+
+    /// A request could not be delivered to a remote endpoint.
+    #[error("transport error: {0}")]
+    Transport(#[from] reqwest::Error),
+
+    /// A component was configured with invalid settings.
+    #[error("invalid configuration: {0}")]
+    Config(String),
+
+    /// An error raised by user-provided code.
+    #[error(transparent)]
+    User(BoxError),
+}
+
+impl Error {
+    pub fn codec<E: Into<BoxError>>(e: E) -> Self {
+        Self::Codec(e.into())
+    }
+
+    pub fn config<S: Into<String>>(msg: S) -> Self {
+        Self::Config(msg.into())
+    }
+
+    pub fn user<E: Into<BoxError>>(e: E) -> Self {
+        Self::User(e.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+    use std::io;
+
+    use super::*;
+
+    #[test]
+    fn displays_what_went_wrong() {
+        let io = Error::from(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+        assert_eq!(io.to_string(), "i/o error: no such file");
+        assert_eq!(
+            Error::codec("expected value at line 1").to_string(),
+            "codec error: expected value at line 1"
+        );
+        let status = Error::Status {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            body: "slow down".into(),
+        };
+        assert_eq!(
+            status.to_string(),
+            "unexpected status [429 Too Many Requests]: slow down"
+        );
+        let rejected = Error::Rejected {
+            rejected: 2,
+            items: 10,
+            error: "mapper_parsing_exception".into(),
+        };
+        assert_eq!(
+            rejected.to_string(),
+            "2 of 10 items rejected: mapper_parsing_exception"
+        );
+        assert_eq!(
+            Error::config("batch_size must be at least 1").to_string(),
+            "invalid configuration: batch_size must be at least 1"
+        );
+    }
+
+    #[tokio::test]
+    async fn displays_transport_errors() {
+        let e = reqwest::Client::new()
+            .get("not a url")
+            .send()
+            .await
+            .unwrap_err();
+        let message = e.to_string();
+        let e = Error::from(e);
+        assert_eq!(e.to_string(), format!("transport error: {message}"));
+        assert!(e.source().is_some());
+    }
+
+    #[test]
+    fn user_errors_are_transparent() {
+        let parse = "x".parse::<u8>().unwrap_err();
+        let e = Error::user(parse.clone());
+        assert_eq!(e.to_string(), parse.to_string());
+        // the source is that of the user's error, which has none
+        assert!(e.source().is_none());
+
+        let io = Error::from(io::Error::other("disk full"));
+        assert_eq!(io.source().unwrap().to_string(), "disk full");
+        assert!(Error::codec("bad").source().is_some());
+    }
+}
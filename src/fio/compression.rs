@@ -0,0 +1,350 @@
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use async_compression::Level;
+use async_compression::tokio::bufread::{
+    BzDecoder, GzipDecoder, Lz4Decoder, XzDecoder, ZstdDecoder,
+};
+use async_compression::tokio::write::{BzEncoder, GzipEncoder, Lz4Encoder, XzEncoder, ZstdEncoder};
+use serde::Deserialize;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+
+use super::{WriteMode, with_path};
+use crate::Result;
+
+/// The bytes every stream of a compression starts with.
+const MAGIC: &[(&[u8], Compression)] = &[
+    (b"\x1f\x8b", Compression::Gzip),
+    (b"\x28\xb5\x2f\xfd", Compression::Zstd),
+    (b"BZh", Compression::Bzip2),
+    (b"\xfd7zXZ\x00", Compression::Xz),
+    (b"\x04\x22\x4d\x18", Compression::Lz4),
+];
+
+pub(crate) type BoxRead = Box<dyn AsyncRead + Send + Unpin>;
+
+pub(crate) type BoxWrite = Box<dyn AsyncWrite + Send + Sync + Unpin>;
+
+/// Compression formats of files and standard streams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+    Lz4,
+}
+
+impl Compression {
+    /// The compression a file name ends in, such as `.gz`, if any.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" | "gzip" => Some(Compression::Gzip),
+            "zst" | "zstd" => Some(Compression::Zstd),
+            "bz2" => Some(Compression::Bzip2),
+            "xz" => Some(Compression::Xz),
+            "lz4" => Some(Compression::Lz4),
+            _ => None,
+        }
+    }
+
+    /// The compression whose magic bytes start `bytes`, if any.
+    pub fn from_magic(bytes: &[u8]) -> Option<Self> {
+        MAGIC
+            .iter()
+            .find(|(magic, _)| bytes.starts_with(magic))
+            .map(|&(_, compression)| compression)
+    }
+
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+            Compression::Bzip2 => "bz2",
+            Compression::Xz => "xz",
+            Compression::Lz4 => "lz4",
+        }
+    }
+
+    /// Decompresses what `reader` reads. Concatenated streams, as written by
+    /// appending to a compressed file, are read one after the other.
+    pub(crate) fn decoder<R: AsyncRead + Send + Unpin + 'static>(self, reader: R) -> BoxRead {
+        let reader = BufReader::new(reader);
+        macro_rules! decoder {
+            ($decoder:ident) => {{
+                let mut decoder = $decoder::new(reader);
+                decoder.multiple_members(true);
+                Box::new(decoder)
+            }};
+        }
+        match self {
+            Compression::Gzip => decoder!(GzipDecoder),
+            Compression::Zstd => decoder!(ZstdDecoder),
+            Compression::Bzip2 => decoder!(BzDecoder),
+            Compression::Xz => decoder!(XzDecoder),
+            Compression::Lz4 => decoder!(Lz4Decoder),
+        }
+    }
+
+    /// Compresses what is written to `writer` at `level`, or at the format's
+    /// default level without one.
+    fn encoder<W: AsyncWrite + Send + Sync + Unpin + 'static>(
+        self,
+        writer: W,
+        level: Option<i32>,
+    ) -> BoxWrite {
+        let level = level.map_or(Level::Default, Level::Precise);
+        match self {
+            Compression::Gzip => Box::new(GzipEncoder::with_quality(writer, level)),
+            Compression::Zstd => Box::new(ZstdEncoder::with_quality(writer, level)),
+            Compression::Bzip2 => Box::new(BzEncoder::with_quality(writer, level)),
+            Compression::Xz => Box::new(XzEncoder::with_quality(writer, level)),
+            Compression::Lz4 => Box::new(Lz4Encoder::with_quality(writer, level)),
+        }
+    }
+
+    /// Replaces `path` with a compressed copy at `target`.
+    pub(crate) async fn compress_file(self, path: &Path, target: &Path) -> Result<()> {
+        let mut source = File::open(path).await.map_err(with_path(path))?;
+        let mut writer = FileWriter::create(target, WriteMode::Truncate, Some(self), None).await?;
+        tokio::io::copy(&mut source, &mut writer.inner).await?;
+        writer.close(true).await?;
+        tokio::fs::remove_file(path)
+            .await
+            .map_err(with_path(path))?;
+        Ok(())
+    }
+}
+
+/// Decompresses what `reader` reads with `compression`, or with the
+/// compression its first bytes call for without one. Reads that do not start
+/// with the magic bytes of a known compression are passed through.
+pub(crate) async fn decompress<R>(
+    mut reader: R,
+    compression: Option<Compression>,
+) -> Result<BoxRead>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    if let Some(compression) = compression {
+        return Ok(compression.decoder(reader));
+    }
+    let magic = read_magic(&mut reader).await?;
+    let compression = Compression::from_magic(&magic);
+    let reader = std::io::Cursor::new(magic).chain(reader);
+    Ok(match compression {
+        Some(compression) => compression.decoder(reader),
+        None => Box::new(reader),
+    })
+}
+
+/// Reads from the start of `reader` until it is clear whether it starts with
+/// the magic bytes of a compression, returning what was read.
+pub(crate) async fn read_magic<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut magic = Vec::new();
+    loop {
+        let pending = MAGIC
+            .iter()
+            .filter(|(bytes, _)| bytes.len() > magic.len() && bytes.starts_with(&magic))
+            .map(|(bytes, _)| bytes.len())
+            .max();
+        let Some(len) = pending else {
+            return Ok(magic);
+        };
+        if reader
+            .take((len - magic.len()) as u64)
+            .read_buf(&mut magic)
+            .await?
+            == 0
+        {
+            return Ok(magic);
+        }
+    }
+}
+
+/// A file being written, compressed on the way if asked to.
+pub(crate) struct FileWriter {
+    inner: BoxWrite,
+    /// The file itself, kept to sync it.
+    file: File,
+}
+
+impl FileWriter {
+    /// Opens `path` according to `mode`, compressing at `level` with
+    /// `compression`, if any.
+    pub(crate) async fn create(
+        path: &Path,
+        mode: WriteMode,
+        compression: Option<Compression>,
+        level: Option<i32>,
+    ) -> Result<Self> {
+        let file = mode.open(path).await.map_err(with_path(path))?;
+        let writer = BufWriter::new(file.try_clone().await?);
+        let inner = match compression {
+            Some(compression) => compression.encoder(writer, level),
+            None => Box::new(writer),
+        };
+        Ok(Self { inner, file })
+    }
+
+    /// The length of the file, before anything is written through this
+    /// writer.
+    pub(crate) async fn len(&self) -> Result<u64> {
+        Ok(self.file.metadata().await?.len())
+    }
+
+    pub(crate) async fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        Ok(self.inner.write_all(buf).await?)
+    }
+
+    /// Flushes everything written so far, and syncs the file to disk if
+    /// `sync` is set.
+    pub(crate) async fn flush(&mut self, sync: bool) -> Result<()> {
+        self.inner.flush().await?;
+        if sync {
+            self.file.sync_data().await?;
+        }
+        Ok(())
+    }
+
+    /// Ends the compressed stream, if any, flushes everything, and syncs the
+    /// file to disk if `sync` is set.
+    pub(crate) async fn close(mut self, sync: bool) -> Result<()> {
+        self.inner.shutdown().await?;
+        if sync {
+            self.file.sync_all().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes to a standard stream, compressed if asked to. Shutting the writer
+/// down flushes everything written.
+pub(crate) fn compress<W>(
+    writer: W,
+    compression: Option<Compression>,
+    level: Option<i32>,
+) -> BoxWrite
+where
+    W: AsyncWrite + Send + Sync + Unpin + 'static,
+{
+    let writer = FlushOnShutdown(writer);
+    match compression {
+        Some(compression) => compression.encoder(writer, level),
+        None => Box::new(writer),
+    }
+}
+
+/// Flushes a writer when it is shut down, which the standard streams of
+/// tokio leave undone.
+struct FlushOnShutdown<W>(W);
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for FlushOnShutdown<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(Pin::new(&mut self.0).poll_flush(cx))?;
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Compression; 5] = [
+        Compression::Gzip,
+        Compression::Zstd,
+        Compression::Bzip2,
+        Compression::Xz,
+        Compression::Lz4,
+    ];
+
+    async fn compressed(compression: Compression, text: &str) -> Vec<u8> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data");
+        let mut file = FileWriter::create(&path, WriteMode::Truncate, Some(compression), None)
+            .await
+            .unwrap();
+        file.write_all(text.as_bytes()).await.unwrap();
+        file.close(false).await.unwrap();
+        std::fs::read(&path).unwrap()
+    }
+
+    async fn read_all(mut reader: BoxRead) -> String {
+        let mut text = String::new();
+        reader.read_to_string(&mut text).await.unwrap();
+        text
+    }
+
+    #[test]
+    fn tells_compressions_by_extension() {
+        assert_eq!(
+            Compression::from_path(Path::new("a.ndjson.gz")),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::from_path(Path::new("a.zst")),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(
+            Compression::from_path(Path::new("a.tar.bz2")),
+            Some(Compression::Bzip2)
+        );
+        assert_eq!(Compression::from_path(Path::new("a.gz.log")), None);
+        assert_eq!(Compression::from_path(Path::new("gz")), None);
+        for compression in ALL {
+            let path = format!("a.{}", compression.extension());
+            assert_eq!(Compression::from_path(Path::new(&path)), Some(compression));
+        }
+    }
+
+    #[tokio::test]
+    async fn tells_compressions_by_their_first_bytes() {
+        for compression in ALL {
+            let bytes = compressed(compression, "hello\n").await;
+            assert_eq!(Compression::from_magic(&bytes), Some(compression));
+            let reader = decompress(std::io::Cursor::new(bytes), None).await.unwrap();
+            assert_eq!(read_all(reader).await, "hello\n", "{compression:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn passes_through_what_is_not_compressed() {
+        for text in ["", "B", "BZ", "plain text\n", "\x1f"] {
+            let reader = decompress(std::io::Cursor::new(text.as_bytes().to_vec()), None)
+                .await
+                .unwrap();
+            assert_eq!(read_all(reader).await, text);
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_appended_streams_one_after_the_other() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.gz");
+        for text in ["a\n", "b\n"] {
+            let mut file =
+                FileWriter::create(&path, WriteMode::Append, Some(Compression::Gzip), None)
+                    .await
+                    .unwrap();
+            file.write_all(text.as_bytes()).await.unwrap();
+            file.close(false).await.unwrap();
+        }
+        let file = File::open(&path).await.unwrap();
+        assert_eq!(read_all(Compression::Gzip.decoder(file)).await, "a\nb\n");
+    }
+}
@@ -0,0 +1,335 @@
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use futures::Stream;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Deserialize;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncSeekExt, BufReader};
+use tokio::sync::mpsc;
+
+use super::{take_line, with_path};
+use crate::{Checkpoint, Checkpoints, FileId, Result};
+
+/// Where a followed file is first read from. Files that replace it after a
+/// rotation, or show up only after the input started, are always read from
+/// the beginning.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartAt {
+    #[default]
+    Beginning,
+    End,
+    /// A byte offset, e.g. one saved by a previous run. Offsets past the end
+    /// of the file mean it was truncated since, and read from the beginning.
+    Offset(u64),
+}
+
+/// What happened to a followed file since it was last read to its end.
+enum Change {
+    None,
+    /// The file shrank below what was read, as with `copytruncate`.
+    Truncated,
+    /// The path now names another file, or nothing at all.
+    Rotated,
+}
+
+struct Tail {
+    reader: BufReader<File>,
+    id: FileId,
+    offset: u64,
+}
+
+impl Tail {
+    /// Opens `path`, or returns `None` if it does not exist (yet).
+    ///
+    /// Reading resumes from `resume` if it was taken on this very file, and
+    /// starts at `start` otherwise.
+    async fn open(path: &Path, start: StartAt, resume: Option<Checkpoint>) -> Result<Option<Self>> {
+        let file = match File::open(path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(with_path(path)(e).into()),
+        };
+        let metadata = file.metadata().await?;
+        let id = FileId::of(&metadata);
+        let start = match resume.and_then(|resume| resume.resume(id, metadata.len())) {
+            Some(offset) => StartAt::Offset(offset),
+            None => start,
+        };
+        let offset = match start {
+            StartAt::Beginning => 0,
+            StartAt::End => metadata.len(),
+            StartAt::Offset(offset) if offset <= metadata.len() => offset,
+            StartAt::Offset(offset) => {
+                tracing::warn!(
+                    "{} is shorter than offset {offset}, reading from the beginning",
+                    path.display()
+                );
+                0
+            }
+        };
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::Start(offset)).await?;
+        Ok(Some(Self { reader, id, offset }))
+    }
+
+    async fn check(&self, path: &Path) -> Result<Change> {
+        match tokio::fs::metadata(path).await {
+            Ok(metadata) if FileId::of(&metadata) != self.id => return Ok(Change::Rotated),
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Change::Rotated),
+            Err(e) => return Err(with_path(path)(e).into()),
+        }
+        if self.reader.get_ref().metadata().await?.len() < self.offset {
+            return Ok(Change::Truncated);
+        }
+        Ok(Change::None)
+    }
+}
+
+/// Watches the directory of `path`, signalling on every change in it.
+///
+/// Returns `None` when no watcher can be set up, in which case the caller
+/// relies on polling alone.
+fn watch(path: &Path) -> Option<(RecommendedWatcher, mpsc::Receiver<()>)> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let (tx, rx) = mpsc::channel(1);
+    let watcher = notify::recommended_watcher(move |_| {
+        // a pending signal already covers this change
+        let _ = tx.try_send(());
+    })
+    .and_then(|mut watcher| {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        Ok(watcher)
+    });
+    match watcher {
+        Ok(watcher) => Some((watcher, rx)),
+        Err(e) => {
+            tracing::warn!("cannot watch {}, polling instead: {e}", dir.display());
+            None
+        }
+    }
+}
+
+/// Reads lines from `path` as it grows, like `tail -F`.
+///
+/// Rename-based rotation is detected by the path naming another file, and
+/// `copytruncate` by the file shrinking. Either way, the lines already
+/// written are read before switching over.
+///
+/// The position after every line is staged in `checkpoints` under `path`,
+/// together with the identity of the file it was read from.
+pub(crate) fn follow(
+    path: PathBuf,
+    start: StartAt,
+    poll_interval: Duration,
+    checkpoints: Option<Checkpoints>,
+) -> impl Stream<Item = Result<String>> + Send {
+    async_stream::try_stream! {
+        let key = path.to_string_lossy().into_owned();
+        let mut resume = match &checkpoints {
+            Some(checkpoints) => checkpoints.load(&key).await?,
+            None => None,
+        };
+        let mut watcher = watch(&path);
+        let mut start = Some(start);
+        let mut tail: Option<Tail> = None;
+        let mut line = String::new();
+        loop {
+            if tail.is_none() {
+                // only the file there at the start is read from `start`
+                tail = Tail::open(&path, start.take().unwrap_or_default(), resume.take()).await?;
+            }
+            if let Some(current) = tail.as_mut() {
+                loop {
+                    let n = current.reader.read_line(&mut line).await?;
+                    if n == 0 {
+                        break;
+                    }
+                    current.offset += n as u64;
+                    if line.ends_with('\n') {
+                        if let Some(checkpoints) = &checkpoints {
+                            checkpoints.stage(&key, Checkpoint { file: current.id, offset: current.offset });
+                        }
+                        yield take_line(&mut line);
+                    }
+                }
+                match current.check(&path).await? {
+                    Change::None => {}
+                    Change::Truncated => {
+                        tracing::info!("{} was truncated", path.display());
+                        current.reader.seek(SeekFrom::Start(0)).await?;
+                        current.offset = 0;
+                        line.clear();
+                    }
+                    Change::Rotated => {
+                        tracing::info!("{} was rotated", path.display());
+                        if !line.is_empty() {
+                            if let Some(checkpoints) = &checkpoints {
+                                checkpoints.stage(&key, Checkpoint { file: current.id, offset: current.offset });
+                            }
+                            yield take_line(&mut line);
+                        }
+                        tail = None;
+                        continue;
+                    }
+                }
+            }
+            match watcher.as_mut() {
+                Some((_, changes)) => {
+                    let _ = tokio::time::timeout(poll_interval, changes.recv()).await;
+                }
+                None => tokio::time::sleep(poll_interval).await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::pin::Pin;
+
+    use futures::StreamExt;
+
+    use super::*;
+
+    type Lines = Pin<Box<dyn Stream<Item = Result<String>> + Send>>;
+
+    fn tail(path: &Path, start: StartAt) -> Lines {
+        Box::pin(follow(
+            path.to_path_buf(),
+            start,
+            Duration::from_millis(20),
+            None,
+        ))
+    }
+
+    async fn next(lines: &mut Lines) -> String {
+        tokio::time::timeout(Duration::from_secs(5), lines.next())
+            .await
+            .expect("no line within 5s")
+            .unwrap()
+            .unwrap()
+    }
+
+    /// Checks that no line comes up for a while, by which time the file is
+    /// open.
+    async fn nothing(lines: &mut Lines) {
+        let next = tokio::time::timeout(Duration::from_millis(100), lines.next()).await;
+        assert!(next.is_err(), "unexpected {next:?}");
+    }
+
+    fn append(path: &Path, text: &str) {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reads_lines_as_they_are_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        std::fs::write(&path, "a\nb").unwrap();
+        let mut lines = tail(&path, StartAt::Beginning);
+        assert_eq!(next(&mut lines).await, "a");
+        // a line is only read once it ends
+        nothing(&mut lines).await;
+        append(&path, "c\r\nd\n");
+        assert_eq!(next(&mut lines).await, "bc");
+        assert_eq!(next(&mut lines).await, "d");
+    }
+
+    #[tokio::test]
+    async fn switches_over_to_the_file_renamed_into_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        std::fs::write(&path, "a\n").unwrap();
+        let mut lines = tail(&path, StartAt::End);
+        nothing(&mut lines).await;
+        // the last lines written to the old file, the very last one unended
+        append(&path, "b\nc");
+        std::fs::rename(&path, dir.path().join("app.log.1")).unwrap();
+        std::fs::write(&path, "d\n").unwrap();
+        assert_eq!(next(&mut lines).await, "b");
+        assert_eq!(next(&mut lines).await, "c");
+        // the new file is read from its beginning, whatever `start` says
+        assert_eq!(next(&mut lines).await, "d");
+        append(&path, "e\n");
+        assert_eq!(next(&mut lines).await, "e");
+    }
+
+    #[tokio::test]
+    async fn waits_for_a_file_removed_until_it_comes_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        std::fs::write(&path, "a\n").unwrap();
+        let mut lines = tail(&path, StartAt::Beginning);
+        assert_eq!(next(&mut lines).await, "a");
+        std::fs::remove_file(&path).unwrap();
+        nothing(&mut lines).await;
+        std::fs::write(&path, "b\n").unwrap();
+        assert_eq!(next(&mut lines).await, "b");
+    }
+
+    #[tokio::test]
+    async fn starts_over_when_the_file_is_truncated_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+        let mut lines = tail(&path, StartAt::Beginning);
+        assert_eq!(next(&mut lines).await, "first");
+        assert_eq!(next(&mut lines).await, "second");
+        // as with copytruncate, the file stays the same
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(0).unwrap();
+        nothing(&mut lines).await;
+        append(&path, "third\n");
+        assert_eq!(next(&mut lines).await, "third");
+    }
+
+    #[tokio::test]
+    async fn starts_at_the_end_or_an_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        std::fs::write(&path, "abc\ndef\n").unwrap();
+
+        let mut lines = tail(&path, StartAt::End);
+        nothing(&mut lines).await;
+        append(&path, "ghi\n");
+        assert_eq!(next(&mut lines).await, "ghi");
+
+        let mut lines = tail(&path, StartAt::Offset(4));
+        assert_eq!(next(&mut lines).await, "def");
+        assert_eq!(next(&mut lines).await, "ghi");
+
+        // past the end, so the file was truncated since
+        let mut lines = tail(&path, StartAt::Offset(100));
+        assert_eq!(next(&mut lines).await, "abc");
+    }
+
+    #[tokio::test]
+    async fn polls_when_the_directory_cannot_be_watched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("later").join("app.log");
+        assert!(watch(&path).is_none());
+
+        let mut lines = tail(&path, StartAt::End);
+        nothing(&mut lines).await;
+        std::fs::create_dir(path.parent().unwrap()).unwrap();
+        // a file showing up after the start is read from its beginning
+        std::fs::write(&path, "a\n").unwrap();
+        assert_eq!(next(&mut lines).await, "a");
+        append(&path, "b\n");
+        assert_eq!(next(&mut lines).await, "b");
+    }
+}
@@ -1,9 +1,786 @@
-use std::path::PathBuf;
+mod compression;
+mod follow;
+mod partition;
+mod rotate;
 
-struct Input {
+use std::fmt::Display;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bytes::BytesMut;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, BufReader};
+use tokio::time::Instant;
+
+use crate::codec::{Decode, Decoder, Encode, Encoder, FramedRead, Lines};
+use crate::{Acks, Checkpoint, Checkpoints, Error, FileId, Result};
+
+pub use compression::Compression;
+pub use follow::StartAt;
+pub use rotate::{Interval, Rotation};
+
+pub(crate) use compression::{BoxRead, compress, decompress};
+use compression::{FileWriter, read_magic};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long written items may wait to be flushed and acknowledged.
+const ACK_INTERVAL: Duration = Duration::from_secs(1);
+
+fn default_poll_interval() -> Duration {
+    DEFAULT_POLL_INTERVAL
+}
+
+/// Adds the offending path to an I/O error.
+pub(crate) fn with_path(path: &Path) -> impl FnOnce(std::io::Error) -> std::io::Error + '_ {
+    move |e| std::io::Error::new(e.kind(), format!("{}: {e}", path.display()))
+}
+
+/// Takes a line read by `read_line` out of `line`, without its line ending.
+pub(crate) fn take_line(line: &mut String) -> String {
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    std::mem::take(line)
+}
+
+/// Reads a file line by line.
+///
+/// `path` may name a single file, a directory, or a glob pattern such as
+/// `logs/*.log`; directories and patterns are read file after file in sorted
+/// path order.
+///
+/// With `follow` set, a single file is read like `tail -F`: the input keeps
+/// waiting for new lines and survives the file being rotated or truncated.
+/// Changes are picked up through file system notifications where available,
+/// and by checking the file every `poll_interval` regardless.
+///
+/// With `checkpoint` set to the path of a checkpoint store, the position in
+/// each file is saved under the file's path, and a restarted input resumes
+/// from there as long as the path still names the same file.
+///
+/// Files compressed with gzip, zstd, bzip2, xz or lz4 are decompressed as
+/// they are read, told apart by their extension or else by their first
+/// bytes; `compression` names the compression of all files instead.
+/// Positions in compressed files count decompressed bytes, and resuming
+/// decompresses everything before the position again. Followed files are
+/// read as they are.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Input {
     path: PathBuf,
+    #[serde(default)]
+    follow: bool,
+    #[serde(default)]
+    start: StartAt,
+    #[serde(default = "default_poll_interval", with = "humantime_serde")]
+    poll_interval: Duration,
+    #[serde(default, rename = "checkpoint")]
+    checkpoints: Option<Checkpoints>,
+    #[serde(default)]
+    compression: Option<Compression>,
+}
+
+impl Input {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            follow: false,
+            start: StartAt::default(),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            checkpoints: None,
+            compression: None,
+        }
+    }
+
+    pub fn follow(mut self, follow: bool) -> Self {
+        self.follow = follow;
+        self
+    }
+
+    /// Where a followed file is first read from.
+    pub fn start(mut self, start: StartAt) -> Self {
+        self.start = start;
+        self
+    }
+
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Resumes from and stages read positions in `checkpoints`.
+    pub fn checkpoints(mut self, checkpoints: Checkpoints) -> Self {
+        self.checkpoints = Some(checkpoints);
+        self
+    }
+
+    /// Reads every file as compressed with `compression`, rather than
+    /// telling compressed files apart.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// The compression of `file`, found at `path`, looking at its first bytes
+    /// if need be. `file` is left at its start.
+    async fn compression_of(&self, path: &Path, file: &mut File) -> Result<Option<Compression>> {
+        if let Some(compression) = self.compression.or_else(|| Compression::from_path(path)) {
+            return Ok(Some(compression));
+        }
+        let magic = read_magic(file).await?;
+        file.seek(SeekFrom::Start(0)).await?;
+        Ok(Compression::from_magic(&magic))
+    }
+
+    /// Opens `path` at its start, decompressing it as need be.
+    async fn open_start(&self, path: &Path) -> Result<BoxRead> {
+        let mut file = File::open(path).await.map_err(with_path(path))?;
+        Ok(match self.compression_of(path, &mut file).await? {
+            Some(compression) => compression.decoder(file),
+            None => Box::new(file),
+        })
+    }
+
+    /// Opens `path` and moves to the committed checkpoint for it, if any.
+    async fn open(&self, path: &Path) -> Result<(BoxRead, Checkpoint)> {
+        let mut file = File::open(path).await.map_err(with_path(path))?;
+        let metadata = file.metadata().await?;
+        let id = FileId::of(&metadata);
+        let compression = self.compression_of(path, &mut file).await?;
+        // positions in compressed files may well lie past their length
+        let len = match compression {
+            Some(_) => u64::MAX,
+            None => metadata.len(),
+        };
+        let offset = match &self.checkpoints {
+            Some(checkpoints) => checkpoints
+                .load(&path.to_string_lossy())
+                .await?
+                .and_then(|checkpoint| checkpoint.resume(id, len))
+                .unwrap_or(0),
+            None => 0,
+        };
+        let reader = match compression {
+            Some(compression) => {
+                let mut reader = compression.decoder(file);
+                let mut skipped = (&mut reader).take(offset);
+                tokio::io::copy(&mut skipped, &mut tokio::io::sink()).await?;
+                reader
+            }
+            None => {
+                file.seek(SeekFrom::Start(offset)).await?;
+                Box::new(file)
+            }
+        };
+        Ok((reader, Checkpoint { file: id, offset }))
+    }
+
+    /// Reads raw chunks of at most `size` bytes, and at least one, instead
+    /// of lines.
+    pub fn chunks(self, size: usize) -> Chunks {
+        Chunks {
+            input: self,
+            size: size.max(1),
+        }
+    }
+
+    /// Whether `path` is a glob pattern rather than the name of an existing
+    /// file, which may contain `[` as well.
+    fn is_pattern(&self) -> bool {
+        !self.path.exists() && self.path.to_string_lossy().contains(['*', '?', '['])
+    }
+
+    /// Resolves `path` into the files to read, in order.
+    pub(crate) fn paths(&self) -> Result<Vec<PathBuf>> {
+        let pattern = self.path.to_string_lossy();
+        let mut paths = if self.is_pattern() {
+            glob::glob(&pattern)
+                .map_err(|e| Error::config(format!("pattern `{pattern}`: {e}")))?
+                .map(|entry| entry.map_err(|e| Error::Io(e.into())))
+                .filter(|entry| !matches!(entry, Ok(path) if path.is_dir()))
+                .collect::<Result<Vec<_>>>()?
+        } else if self.path.is_dir() {
+            std::fs::read_dir(&self.path)
+                .map_err(with_path(&self.path))?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .filter(|entry| !matches!(entry, Ok(path) if path.is_dir()))
+                .collect::<std::io::Result<Vec<_>>>()?
+        } else {
+            return Ok(vec![self.path.clone()]);
+        };
+        paths.sort();
+        if paths.is_empty() {
+            tracing::warn!("no files match {}", self.path.display());
+        }
+        Ok(paths)
+    }
+}
+
+impl crate::Input<String> for Input {
+    fn into_stream(self) -> impl Stream<Item = Result<String>> + Send {
+        Decode::new(self, Lines {}).into_stream()
+    }
+
+    fn checkpoints(&self) -> Option<Checkpoints> {
+        self.checkpoints.clone()
+    }
+}
+
+/// Every file is decoded on its own, and checkpoints are staged after every
+/// item. A followed file is fed to the decoder line by line, so it can only
+/// be read with codecs that [read lines](Decoder::reads_lines).
+impl<D> crate::Input<D::Item> for Decode<Input, D>
+where
+    D: Decoder + Send,
+    D::Item: Send,
+{
+    fn into_stream(self) -> impl Stream<Item = Result<D::Item>> + Send {
+        async_stream::try_stream! {
+            let Decode { input, mut decoder } = self;
+            if input.follow {
+                if input.path.is_dir() || input.is_pattern() {
+                    Err(Error::config(format!(
+                        "cannot follow {}, only single files can be followed",
+                        input.path.display()
+                    )))?;
+                }
+                if !decoder.reads_lines() {
+                    Err(Error::config(format!(
+                        "cannot follow {} with a codec that does not read lines",
+                        input.path.display()
+                    )))?;
+                }
+                let lines = follow::follow(input.path, input.start, input.poll_interval, input.checkpoints);
+                let mut buf = BytesMut::new();
+                for await line in lines {
+                    buf.extend_from_slice(line?.as_bytes());
+                    buf.extend_from_slice(b"\n");
+                    while let Some(item) = decoder.decode(&mut buf)? {
+                        yield item;
+                    }
+                }
+                return;
+            }
+            for path in input.paths()? {
+                let key = path.to_string_lossy().into_owned();
+                let (reader, mut position) = input.open(&path).await?;
+                let start = position.offset;
+                let mut reader = BufReader::new(reader);
+                if start > 0 {
+                    // a file read to its end has nothing to resume
+                    if reader.fill_buf().await?.is_empty() {
+                        continue;
+                    }
+                    let start = input.open_start(&path).await?;
+                    decoder = FramedRead::new(start, decoder).read_start().await?;
+                    decoder.resume().map_err(|e| match e {
+                        Error::Config(message) => {
+                            Error::config(format!("cannot resume {}: {message}", path.display()))
+                        }
+                        e => e,
+                    })?;
+                }
+                let mut framed = FramedRead::new(reader, decoder);
+                while let Some(item) = framed.next().await? {
+                    position.offset = start + framed.position();
+                    if let Some(checkpoints) = &input.checkpoints {
+                        checkpoints.stage(&key, position);
+                    }
+                    yield item;
+                }
+                decoder = framed.into_decoder();
+            }
+        }
+    }
+
+    fn checkpoints(&self) -> Option<Checkpoints> {
+        self.input.checkpoints.clone()
+    }
 }
 
-struct Output {
+/// Reads the files of an [`Input`] as raw byte chunks, decompressed like
+/// lines are; see [`Input::chunks`].
+#[derive(Debug, Clone)]
+pub struct Chunks {
+    input: Input,
+    size: usize,
+}
+
+impl crate::Input<Vec<u8>> for Chunks {
+    fn into_stream(self) -> impl Stream<Item = Result<Vec<u8>>> + Send {
+        async_stream::try_stream! {
+            for path in self.input.paths()? {
+                let mut reader = self.input.open_start(&path).await?;
+                loop {
+                    let mut chunk = vec![0; self.size];
+                    let n = reader.read(&mut chunk).await?;
+                    if n == 0 {
+                        break;
+                    }
+                    chunk.truncate(n);
+                    yield chunk;
+                }
+            }
+        }
+    }
+}
+
+/// How [`Output`] treats a file that already exists.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteMode {
+    /// Create the file, or empty it if it exists.
+    #[default]
+    Truncate,
+    /// Create the file, or write after its current end if it exists.
+    Append,
+    /// Create the file, failing if it already exists.
+    CreateNew,
+}
+
+impl WriteMode {
+    pub(crate) async fn open(self, path: &Path) -> std::io::Result<File> {
+        let mut options = OpenOptions::new();
+        match self {
+            WriteMode::Truncate => options.write(true).create(true).truncate(true),
+            WriteMode::Append => options.append(true).create(true),
+            WriteMode::CreateNew => options.write(true).create_new(true),
+        };
+        options.open(path).await
+    }
+}
+
+/// Writes one item per line to a file.
+///
+/// The file is opened when the pipeline starts writing, and flushed once the
+/// stream ends. With `sync` set, the data is also synced to disk before the
+/// output reports success, and before moving on to a new file when rotating.
+///
+/// With `rotate` set, `path` is a template for a series of files; see
+/// [`Rotation`]. `mode` then has no effect as every file is a new one.
+///
+/// With `partition_by` set, every line must hold a JSON object, or every
+/// item serialize to one when written through [`Encode`], and is written
+/// below the directory of `path` into a Hive-style partition named after
+/// the values of the listed fields, e.g.
+/// `out/dt=2026-10-17/region=eu/part-{seq}.ndjson` for a `path` of
+/// `out/part-{seq}.ndjson`. At most `max_open_files` partitions are kept
+/// open at once; the least recently written one is closed to make room, and
+/// appended to should it be written again.
+///
+/// Codecs whose files start with a header they cannot repeat, such as Avro
+/// and Arrow IPC, fail rather than append to a file that is not empty, and
+/// so do partitions written with them once they would be reopened.
+///
+/// Items are acknowledged to a pipeline once they are flushed, which happens
+/// at the latest a second after they are written, and synced with `sync`.
+///
+/// With `compression` set, or a `path` ending in the extension of a
+/// compression such as `.gz` or `.zst`, files are compressed as they are
+/// written, at `compression_level` if given. Appending to a compressed file
+/// adds a compressed stream after the ones already in it, which inputs read
+/// one after the other.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Output {
     path: PathBuf,
+    #[serde(default)]
+    mode: WriteMode,
+    #[serde(default)]
+    sync: bool,
+    #[serde(default)]
+    rotate: Option<Rotation>,
+    #[serde(default)]
+    partition_by: Vec<String>,
+    #[serde(default = "default_max_open_files")]
+    max_open_files: usize,
+    #[serde(default)]
+    compression: Option<Compression>,
+    #[serde(default)]
+    compression_level: Option<i32>,
+}
+
+const DEFAULT_MAX_OPEN_FILES: usize = 64;
+
+fn default_max_open_files() -> usize {
+    DEFAULT_MAX_OPEN_FILES
+}
+
+impl Output {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            mode: WriteMode::default(),
+            sync: false,
+            rotate: None,
+            partition_by: Vec::new(),
+            max_open_files: DEFAULT_MAX_OPEN_FILES,
+            compression: None,
+            compression_level: None,
+        }
+    }
+
+    pub fn mode(mut self, mode: WriteMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    pub fn rotate(mut self, rotation: Rotation) -> Self {
+        self.rotate = Some(rotation);
+        self
+    }
+
+    pub fn partition_by<I, S>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.partition_by = fields.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn max_open_files(mut self, max_open_files: usize) -> Self {
+        self.max_open_files = max_open_files;
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// The level to compress at, whose range depends on the compression,
+    /// e.g. 1 to 9 for gzip and 1 to 22 for zstd.
+    pub fn compression_level(mut self, level: i32) -> Self {
+        self.compression_level = Some(level);
+        self
+    }
+
+    /// Opens a writer for `path`, which is a template when rotating.
+    async fn writer<T, E: Encoder<T>>(
+        &self,
+        path: &Path,
+        mode: WriteMode,
+        mut encoder: E,
+    ) -> Result<Writer<E>> {
+        let compression = self.compression.or_else(|| Compression::from_path(path));
+        if let Some(rotation) = &self.rotate {
+            return Ok(Writer::Rotating(rotate::RotatingWriter::new(
+                path,
+                rotation.clone(),
+                self.sync,
+                compression,
+                self.compression_level,
+                encoder,
+            )?));
+        }
+        let file = FileWriter::create(path, mode, compression, self.compression_level).await?;
+        if mode == WriteMode::Append && file.len().await? > 0 {
+            Encoder::<T>::resume(&mut encoder).map_err(|e| match e {
+                Error::Config(message) => {
+                    Error::config(format!("cannot append to {}: {message}", path.display()))
+                }
+                e => e,
+            })?;
+        }
+        Ok(Writer::Plain {
+            file,
+            encoder,
+            sync: self.sync,
+        })
+    }
+
+    /// Writes `stream` with `encoder`, looking up partition values in what
+    /// `fields` makes of every item, and acknowledges items in `acks` as
+    /// they are flushed.
+    pub(crate) async fn write<T, S, E, F>(
+        &self,
+        stream: S,
+        encoder: E,
+        fields: F,
+        acks: Acks,
+    ) -> Result<()>
+    where
+        T: Send + Sync,
+        S: Stream<Item = T> + Send,
+        E: Encoder<T> + Clone + Send + Sync,
+        F: Fn(&T) -> Result<Value> + Send + Sync,
+    {
+        futures::pin_mut!(stream);
+        let mut sink = if self.partition_by.is_empty() {
+            Sink::Single(self.writer::<T, _>(&self.path, self.mode, encoder).await?)
+        } else {
+            Sink::Partitioned(partition::Partitions::new(self, encoder)?)
+        };
+        let mut written = 0;
+        // when the first item not yet acknowledged is to be flushed
+        let mut flush_at = None;
+        loop {
+            if flush_at.is_some_and(|at| Instant::now() >= at) {
+                sink.flush().await?;
+                acks.acknowledge(written);
+                flush_at = None;
+            }
+            let item = match flush_at {
+                Some(at) => tokio::select! {
+                    item = stream.next() => item,
+                    () = tokio::time::sleep_until(at) => continue,
+                },
+                None => stream.next().await,
+            };
+            let Some(item) = item else { break };
+            match &mut sink {
+                Sink::Single(writer) => writer.write(&item).await?,
+                Sink::Partitioned(partitions) => {
+                    let path = partitions.path(&fields(&item)?)?;
+                    partitions.write(path, &item).await?;
+                }
+            }
+            written += 1;
+            flush_at.get_or_insert_with(|| Instant::now() + ACK_INTERVAL);
+        }
+        match sink {
+            Sink::Single(writer) => writer.finish::<T>().await?,
+            Sink::Partitioned(partitions) => partitions.finish::<T>().await?,
+        }
+        acks.acknowledge(written);
+        Ok(())
+    }
+}
+
+/// The writers of an [`Output`], partitioned or not.
+enum Sink<'a, E> {
+    Single(Writer<E>),
+    Partitioned(partition::Partitions<'a, E>),
+}
+
+impl<E> Sink<'_, E> {
+    async fn flush(&mut self) -> Result<()> {
+        match self {
+            Sink::Single(writer) => writer.flush().await,
+            Sink::Partitioned(partitions) => partitions.flush().await,
+        }
+    }
+}
+
+/// Where an [`Output`] writes items to.
+enum Writer<E> {
+    Plain {
+        file: FileWriter,
+        encoder: E,
+        sync: bool,
+    },
+    Rotating(rotate::RotatingWriter<E>),
+}
+
+impl<E> Writer<E> {
+    async fn write<T: Sync>(&mut self, item: &T) -> Result<()>
+    where
+        E: Encoder<T> + Clone,
+    {
+        match self {
+            Writer::Plain { file, encoder, .. } => {
+                let mut buf = Vec::new();
+                encoder.encode(item, &mut buf)?;
+                file.write_all(&buf).await
+            }
+            Writer::Rotating(writer) => writer.write(item).await,
+        }
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        match self {
+            Writer::Plain { file, sync, .. } => file.flush(*sync).await,
+            Writer::Rotating(writer) => writer.flush().await,
+        }
+    }
+
+    async fn finish<T>(self) -> Result<()>
+    where
+        E: Encoder<T>,
+    {
+        match self {
+            Writer::Plain {
+                mut file,
+                mut encoder,
+                sync,
+            } => {
+                let mut buf = Vec::new();
+                encoder.finish(&mut buf)?;
+                file.write_all(&buf).await?;
+                file.close(sync).await
+            }
+            Writer::Rotating(writer) => writer.finish::<T>().await,
+        }
+    }
+}
+
+impl<T: Display + Send> crate::Output<T> for Output {
+    async fn output<S>(&self, stream: S) -> Result<()>
+    where
+        S: Stream<Item = T> + Send,
+    {
+        self.output_acked(stream, Acks::default()).await
+    }
+
+    async fn output_acked<S>(&self, stream: S, acks: Acks) -> Result<()>
+    where
+        S: Stream<Item = T> + Send,
+    {
+        let lines = stream.map(|item| item.to_string());
+        self.write(
+            lines,
+            Lines {},
+            |line: &String| serde_json::from_str(line).map_err(Error::codec),
+            acks,
+        )
+        .await
+    }
+}
+
+/// Partition values are looked up in the serialized items.
+impl<T, E> crate::Output<T> for Encode<Output, E>
+where
+    T: Serialize + Send + Sync,
+    E: Encoder<T> + Clone + Send + Sync,
+{
+    async fn output<S>(&self, stream: S) -> Result<()>
+    where
+        S: Stream<Item = T> + Send,
+    {
+        self.output_acked(stream, Acks::default()).await
+    }
+
+    async fn output_acked<S>(&self, stream: S, acks: Acks) -> Result<()>
+    where
+        S: Stream<Item = T> + Send,
+    {
+        self.output
+            .write(
+                stream,
+                self.encoder.clone(),
+                |item: &T| serde_json::to_value(item).map_err(Error::codec),
+                acks,
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::Input as _;
+    use crate::codec::Csv;
+
+    async fn lines(input: Input) -> Vec<String> {
+        input.into_stream().try_collect().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn reads_directories_and_patterns_in_path_order() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("b.log"), "b1\nb2\n").unwrap();
+        std::fs::write(dir.path().join("a.log"), "a1\n").unwrap();
+        std::fs::write(dir.path().join("c.txt"), "c1\n").unwrap();
+
+        assert_eq!(
+            lines(Input::new(dir.path())).await,
+            ["a1", "b1", "b2", "c1"]
+        );
+        let pattern = dir.path().join("*.log");
+        assert_eq!(lines(Input::new(pattern)).await, ["a1", "b1", "b2"]);
+    }
+
+    #[tokio::test]
+    async fn reads_existing_paths_with_brackets_as_they_are() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app[1].log");
+        std::fs::write(&path, "one\n").unwrap();
+        std::fs::write(dir.path().join("app1.log"), "other\n").unwrap();
+
+        assert_eq!(Input::new(&path).paths().unwrap(), vec![path.clone()]);
+        assert_eq!(lines(Input::new(&path)).await, ["one"]);
+        let pattern = dir.path().join("app[0-9].log");
+        assert_eq!(lines(Input::new(pattern)).await, ["other"]);
+    }
+
+    #[tokio::test]
+    async fn reads_chunks_of_at_least_one_byte() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        std::fs::write(&path, b"abcde").unwrap();
+
+        let chunks: Vec<_> = Input::new(&path)
+            .chunks(2)
+            .into_stream()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(chunks, [b"ab".to_vec(), b"cd".to_vec(), b"e".to_vec()]);
+        let chunks: Vec<_> = Input::new(&path)
+            .chunks(0)
+            .into_stream()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(chunks.concat(), b"abcde");
+        assert_eq!(chunks.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn follows_files_with_line_codecs_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        std::fs::write(&path, b"").unwrap();
+
+        let input = Decode::new(
+            Input::new(&path).follow(true),
+            crate::codec::MessagePack::new(),
+        );
+        let stream = crate::Input::<Value>::into_stream(input);
+        futures::pin_mut!(stream);
+        let e = stream.next().await.unwrap().unwrap_err();
+        assert!(matches!(e, Error::Config(_)), "{e}");
+    }
+
+    #[tokio::test]
+    async fn resumes_only_files_with_something_left() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.csv");
+        let store = dir.path().join("checkpoints.json");
+        let read = || async {
+            let input = Input::new(&path).checkpoints(Checkpoints::open(&store));
+            let checkpoints = input.checkpoints.clone().unwrap();
+            let stream = crate::Input::<Value>::into_stream(Decode::new(input, Csv::new()));
+            let items: Result<Vec<_>> = stream.try_collect().await;
+            checkpoints.commit().await.unwrap();
+            items
+        };
+
+        std::fs::write(&path, "a,b\n1,2\n").unwrap();
+        assert_eq!(read().await.unwrap().len(), 1);
+        // read to its end, so the header is not needed again
+        assert!(read().await.unwrap().is_empty());
+        // the header is read again from the start
+        std::fs::write(&path, "a,b\n1,2\n3,4\n").unwrap();
+        assert_eq!(read().await.unwrap(), [serde_json::json!({"a": 3, "b": 4})]);
+        std::fs::write(&path, "a,b\n1,2\n3,4\n5\n").unwrap();
+        let e = read().await.unwrap_err();
+        assert!(e.to_string().contains("expected 2 fields"), "{e}");
+    }
 }
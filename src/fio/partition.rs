@@ -0,0 +1,279 @@
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+use lru::LruCache;
+use serde_json::Value;
+
+use super::{Output, WriteMode, Writer, with_path};
+use crate::codec::Encoder;
+use crate::{Error, Result};
+
+/// The partition value Hive uses for missing, null and empty fields.
+const DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// Escapes the characters Hive does not allow in partition values.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c.is_control() || "\"#%'*/:=?\\{}[]^".contains(c) {
+            escaped.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+fn partition_value(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => DEFAULT_PARTITION.to_string(),
+        Some(Value::String(s)) if s.is_empty() => DEFAULT_PARTITION.to_string(),
+        Some(Value::String(s)) => escape(s),
+        Some(other) => escape(&other.to_string()),
+    }
+}
+
+/// The open writers of a partitioned [`Output`], at most `max_open_files` of
+/// them, each with its own clone of `encoder`.
+pub(crate) struct Partitions<'a, E> {
+    output: &'a Output,
+    encoder: E,
+    writers: LruCache<PathBuf, Writer<E>>,
+    /// Partitions written before, which are appended to when reopened.
+    opened: HashSet<PathBuf>,
+}
+
+impl<'a, E: Clone> Partitions<'a, E> {
+    pub(crate) fn new(output: &'a Output, encoder: E) -> Result<Self> {
+        let capacity = NonZeroUsize::new(output.max_open_files)
+            .ok_or_else(|| Error::config("max_open_files must be at least 1"))?;
+        Ok(Self {
+            output,
+            encoder,
+            writers: LruCache::new(capacity),
+            opened: HashSet::new(),
+        })
+    }
+
+    /// The path a record is written to, e.g. `out/dt=2026-10-17/part.ndjson`.
+    pub(crate) fn path(&self, record: &Value) -> Result<PathBuf> {
+        let Value::Object(fields) = record else {
+            return Err(Error::codec(format!(
+                "cannot partition {record}, records must be JSON objects"
+            )));
+        };
+        let mut path = self
+            .output
+            .path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        for field in &self.output.partition_by {
+            path.push(format!(
+                "{}={}",
+                escape(field),
+                partition_value(fields.get(field))
+            ));
+        }
+        if let Some(name) = self.output.path.file_name() {
+            path.push(name);
+        }
+        Ok(path)
+    }
+
+    pub(crate) async fn write<T: Sync>(&mut self, path: PathBuf, item: &T) -> Result<()>
+    where
+        E: Encoder<T>,
+    {
+        if !self.writers.contains(&path) {
+            if self.writers.len() == self.writers.cap().get()
+                && let Some((evicted, writer)) = self.writers.pop_lru()
+            {
+                tracing::debug!("closing partition {}", evicted.display());
+                writer.finish::<T>().await?;
+            }
+            if let Some(dir) = path.parent() {
+                tokio::fs::create_dir_all(dir)
+                    .await
+                    .map_err(with_path(dir))?;
+            }
+            let reopened = self.opened.contains(&path);
+            let mode = if reopened {
+                WriteMode::Append
+            } else {
+                self.output.mode
+            };
+            let writer = self
+                .output
+                .writer::<T, _>(&path, mode, self.encoder.clone())
+                .await
+                .map_err(|e| match e {
+                    Error::Config(message) if reopened => Error::config(format!(
+                        "{message}; the partition was closed to keep at most \
+                         {} open, see max_open_files",
+                        self.writers.cap()
+                    )),
+                    e => e,
+                })?;
+            self.opened.insert(path.clone());
+            self.writers.put(path.clone(), writer);
+        }
+        let writer = self
+            .writers
+            .get_mut(&path)
+            .expect("the partition was just opened");
+        writer.write(item).await
+    }
+
+    /// Finishes every writer still open.
+    pub(crate) async fn finish<T>(mut self) -> Result<()>
+    where
+        E: Encoder<T>,
+    {
+        while let Some((_, writer)) = self.writers.pop_lru() {
+            writer.finish::<T>().await?;
+        }
+        Ok(())
+    }
+}
+
+impl<E> Partitions<'_, E> {
+    /// Flushes every writer still open.
+    pub(crate) async fn flush(&mut self) -> Result<()> {
+        for (_, writer) in self.writers.iter_mut() {
+            writer.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::Output as _;
+    use crate::codec::Lines;
+
+    #[test]
+    fn escapes_what_hive_does_not_allow_in_values() {
+        assert_eq!(escape("eu-west"), "eu-west");
+        assert_eq!(escape("a/b=c%d"), "a%2Fb%3Dc%25d");
+        assert_eq!(escape("x:y?\n"), "x%3Ay%3F%0A");
+        assert_eq!(escape("zürich"), "zürich");
+    }
+
+    #[test]
+    fn puts_missing_null_and_empty_values_in_the_default_partition() {
+        assert_eq!(partition_value(None), DEFAULT_PARTITION);
+        assert_eq!(partition_value(Some(&Value::Null)), DEFAULT_PARTITION);
+        assert_eq!(partition_value(Some(&json!(""))), DEFAULT_PARTITION);
+        assert_eq!(partition_value(Some(&json!("a b"))), "a b");
+        assert_eq!(partition_value(Some(&json!(12))), "12");
+        assert_eq!(partition_value(Some(&json!(true))), "true");
+        assert_eq!(partition_value(Some(&json!(["a"]))), "%5B%22a%22%5D");
+    }
+
+    #[test]
+    fn names_partitions_below_the_directory_of_the_path() {
+        let output = Output::new("out/part.ndjson").partition_by(["dt", "region"]);
+        let partitions = Partitions::new(&output, Lines {}).unwrap();
+        let path = partitions
+            .path(&json!({"dt": "2026-10-17", "region": "eu/west"}))
+            .unwrap();
+        assert_eq!(
+            path,
+            Path::new("out/dt=2026-10-17/region=eu%2Fwest/part.ndjson")
+        );
+        let path = partitions.path(&json!({"region": null})).unwrap();
+        assert_eq!(
+            path,
+            Path::new(
+                "out/dt=__HIVE_DEFAULT_PARTITION__/region=__HIVE_DEFAULT_PARTITION__/part.ndjson"
+            )
+        );
+        let e = partitions.path(&json!([1])).unwrap_err();
+        assert!(e.to_string().contains("must be JSON objects"), "{e}");
+    }
+
+    #[test]
+    fn needs_room_for_an_open_file() {
+        let output = Output::new("part.ndjson")
+            .partition_by(["k"])
+            .max_open_files(0);
+        let e = Partitions::new(&output, Lines {}).err().unwrap();
+        assert!(matches!(e, Error::Config(_)), "{e}");
+    }
+
+    #[tokio::test]
+    async fn closes_the_least_recently_written_partition_and_appends_when_reopened() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = Output::new(dir.path().join("part.ndjson"))
+            .partition_by(["k"])
+            .max_open_files(2);
+        // left over from an earlier run, and replaced
+        let stale = dir.path().join("k=a/part.ndjson");
+        std::fs::create_dir_all(stale.parent().unwrap()).unwrap();
+        std::fs::write(&stale, "stale\n").unwrap();
+
+        let mut partitions = Partitions::new(&output, Lines {}).unwrap();
+        for (n, k) in ["a", "b", "c", "a", "b", "a"].into_iter().enumerate() {
+            let line = json!({"k": k, "n": n}).to_string();
+            let path = partitions.path(&json!({"k": k})).unwrap();
+            partitions.write(path, &line).await.unwrap();
+            assert!(partitions.writers.len() <= 2);
+        }
+        assert_eq!(partitions.opened.len(), 3);
+        partitions.finish::<String>().await.unwrap();
+
+        let read = |k: &str| std::fs::read_to_string(dir.path().join(format!("k={k}/part.ndjson")));
+        let ns = |k: &str| -> Vec<u64> {
+            read(k)
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    serde_json::from_str::<Value>(line).unwrap()["n"]
+                        .as_u64()
+                        .unwrap()
+                })
+                .collect()
+        };
+        assert_eq!(ns("a"), [0, 3, 5]);
+        assert_eq!(ns("b"), [1, 4]);
+        assert_eq!(ns("c"), [2]);
+    }
+
+    #[tokio::test]
+    async fn writes_every_line_into_its_partition() {
+        let dir = tempfile::tempdir().unwrap();
+        let lines = [
+            r#"{"region": "eu", "n": 1}"#,
+            r#"{"n": 2}"#,
+            r#"{"region": "us/east", "n": 3}"#,
+            r#"{"region": "eu", "n": 4}"#,
+        ];
+        Output::new(dir.path().join("out.ndjson"))
+            .partition_by(["region"])
+            .max_open_files(1)
+            .output(futures::stream::iter(lines))
+            .await
+            .unwrap();
+        let read = |dir_name: &str| {
+            std::fs::read_to_string(dir.path().join(dir_name).join("out.ndjson")).unwrap()
+        };
+        assert_eq!(read("region=eu"), format!("{}\n{}\n", lines[0], lines[3]));
+        assert_eq!(
+            read("region=__HIVE_DEFAULT_PARTITION__"),
+            format!("{}\n", lines[1])
+        );
+        assert_eq!(read("region=us%2Feast"), format!("{}\n", lines[2]));
+
+        let e = Output::new(dir.path().join("out.ndjson"))
+            .partition_by(["region"])
+            .output(futures::stream::iter(["not json"]))
+            .await
+            .unwrap_err();
+        assert!(matches!(e, Error::Codec(_)), "{e}");
+    }
+}
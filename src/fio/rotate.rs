@@ -0,0 +1,470 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use super::compression::FileWriter;
+use super::{Compression, WriteMode, with_path};
+use crate::codec::Encoder;
+use crate::{Error, Result};
+
+/// Fixed time periods a rotating [`Output`](super::Output) starts a new file
+/// for, aligned to UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interval {
+    Hourly,
+    Daily,
+}
+
+impl Interval {
+    fn period(self, now: DateTime<Utc>) -> String {
+        match self {
+            Interval::Hourly => now.format("%Y-%m-%dT%H").to_string(),
+            Interval::Daily => now.format("%Y-%m-%d").to_string(),
+        }
+    }
+}
+
+fn append_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    path.into()
+}
+
+/// When a file [`Output`](super::Output) moves on to a new file, and what
+/// happens to the files it is done with.
+///
+/// The output path then is a template: `{date}` and `{hour}` are replaced
+/// with the UTC date and hour the file was opened at, and `{seq}` with a
+/// sequence number counting up within every interval, e.g.
+/// `events-{date}-{seq}.ndjson`. Every file is a new one: sequence numbers
+/// continue after the highest one found on disk.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rotation {
+    /// Start a new file once one has grown to this many bytes, which the
+    /// record written last may take it past.
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// Start a new file once one holds this many records.
+    #[serde(default)]
+    pub max_records: Option<u64>,
+    /// Start a new file with every interval. The switch happens when the
+    /// first record of the new interval is written.
+    #[serde(default)]
+    pub interval: Option<Interval>,
+    /// Compress files once they are rotated away from, instead of as they
+    /// are written. Not allowed for outputs that compress as they write.
+    #[serde(default)]
+    pub compress: Option<Compression>,
+    /// Keep at most this many closed files, deleting the oldest.
+    #[serde(default)]
+    pub keep: Option<usize>,
+    /// Delete closed files last modified longer ago than this.
+    #[serde(default, with = "humantime_serde")]
+    pub max_age: Option<Duration>,
+}
+
+struct Current<E> {
+    path: PathBuf,
+    file: FileWriter,
+    encoder: E,
+    bytes: u64,
+    records: u64,
+}
+
+/// Writes records to a series of files named after a template, with a fresh
+/// clone of `encoder` for every file, compressed with `compression` at
+/// `level` as they are written, if at all.
+pub(crate) struct RotatingWriter<E> {
+    template: String,
+    rotation: Rotation,
+    sync: bool,
+    compression: Option<Compression>,
+    level: Option<i32>,
+    encoder: E,
+    current: Option<Current<E>>,
+    /// The interval of the current file, if one was opened.
+    period: Option<String>,
+    seq: u64,
+}
+
+impl<E> RotatingWriter<E> {
+    pub(crate) fn new(
+        template: &Path,
+        rotation: Rotation,
+        sync: bool,
+        compression: Option<Compression>,
+        level: Option<i32>,
+        encoder: E,
+    ) -> Result<Self> {
+        let template = template.to_string_lossy().into_owned();
+        if !template.contains("{seq}") {
+            return Err(Error::config(format!(
+                "rotated file name `{template}` needs a `{{seq}}` placeholder"
+            )));
+        }
+        if let (Some(compression), Some(_)) = (compression, rotation.compress) {
+            return Err(Error::config(format!(
+                "rotated files are already written {compression:?}-compressed, \
+                 `rotate.compress` would compress them again"
+            )));
+        }
+        Ok(Self {
+            template,
+            rotation,
+            sync,
+            compression,
+            level,
+            encoder,
+            current: None,
+            period: None,
+            seq: 0,
+        })
+    }
+
+    pub(crate) async fn write<T: Sync>(&mut self, record: &T) -> Result<()>
+    where
+        E: Encoder<T> + Clone,
+    {
+        let now = Utc::now();
+        let period = self
+            .rotation
+            .interval
+            .map(|interval| interval.period(now))
+            .unwrap_or_default();
+        let new_period = self.period.as_ref() != Some(&period);
+        let rotate = match &self.current {
+            None => true,
+            Some(current) => {
+                new_period
+                    || self
+                        .rotation
+                        .max_bytes
+                        .is_some_and(|max| current.bytes >= max)
+                    || self
+                        .rotation
+                        .max_records
+                        .is_some_and(|max| current.records >= max)
+            }
+        };
+        if rotate {
+            let rotated = self.close::<T>(true).await?;
+            if new_period {
+                self.period = Some(period);
+                self.seq = self.next_seq(now)?;
+            } else if rotated {
+                self.seq += 1;
+            }
+            self.open(now).await?;
+        }
+        let current = self
+            .current
+            .as_mut()
+            .expect("a file is open after rotating");
+        let mut buf = Vec::new();
+        current.encoder.encode(record, &mut buf)?;
+        current.file.write_all(&buf).await?;
+        current.bytes += buf.len() as u64;
+        current.records += 1;
+        Ok(())
+    }
+
+    /// Flushes the file being written, if any.
+    pub(crate) async fn flush(&mut self) -> Result<()> {
+        match &mut self.current {
+            Some(current) => current.file.flush(self.sync).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Flushes the file written last. It stays uncompressed and counts
+    /// towards retention only once a later file replaces it.
+    pub(crate) async fn finish<T>(mut self) -> Result<()>
+    where
+        E: Encoder<T>,
+    {
+        self.close::<T>(false).await?;
+        Ok(())
+    }
+
+    fn render(&self, now: DateTime<Utc>, seq: &str) -> String {
+        self.template
+            .replace("{date}", &now.format("%Y-%m-%d").to_string())
+            .replace("{hour}", &now.format("%H").to_string())
+            .replace("{seq}", seq)
+    }
+
+    /// The sequence number after the highest one already used for `now`, so
+    /// that names keep increasing even after retention removed older files.
+    fn next_seq(&self, now: DateTime<Utc>) -> Result<u64> {
+        let rendered = self.render(now, "{seq}");
+        let (prefix, suffix) = rendered
+            .split_once("{seq}")
+            .expect("templates contain `{seq}`");
+        let pattern = format!(
+            "{}*{}*",
+            glob::Pattern::escape(prefix),
+            glob::Pattern::escape(suffix)
+        );
+        let mut next = 0;
+        for path in glob::glob(&pattern).map_err(|e| Error::config(e.to_string()))? {
+            let path = path
+                .map_err(|e| Error::Io(e.into()))?
+                .to_string_lossy()
+                .into_owned();
+            let seq = path
+                .strip_prefix(prefix)
+                .and_then(|rest| rest.split_once(suffix))
+                .and_then(|(seq, _)| seq.parse::<u64>().ok());
+            if let Some(seq) = seq {
+                next = next.max(seq + 1);
+            }
+        }
+        Ok(next)
+    }
+
+    async fn open(&mut self, now: DateTime<Utc>) -> Result<()>
+    where
+        E: Clone,
+    {
+        let path = loop {
+            let path = PathBuf::from(self.render(now, &format!("{:04}", self.seq)));
+            let taken = match self.rotation.compress {
+                Some(compression) => append_extension(&path, compression.extension()).exists(),
+                None => false,
+            };
+            if !taken && !path.exists() {
+                break path;
+            }
+            self.seq += 1;
+        };
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(with_path(dir))?;
+        }
+        let file =
+            FileWriter::create(&path, WriteMode::CreateNew, self.compression, self.level).await?;
+        self.current = Some(Current {
+            path,
+            file,
+            encoder: self.encoder.clone(),
+            bytes: 0,
+            records: 0,
+        });
+        Ok(())
+    }
+
+    /// Closes the current file, if any, and reports whether there was one.
+    async fn close<T>(&mut self, rotated: bool) -> Result<bool>
+    where
+        E: Encoder<T>,
+    {
+        let Some(mut current) = self.current.take() else {
+            return Ok(false);
+        };
+        let mut buf = Vec::new();
+        current.encoder.finish(&mut buf)?;
+        current.file.write_all(&buf).await?;
+        current.file.close(self.sync).await?;
+        if rotated {
+            if let Some(compression) = self.rotation.compress {
+                let target = append_extension(&current.path, compression.extension());
+                compression.compress_file(&current.path, &target).await?;
+            }
+            self.apply_retention()?;
+        }
+        Ok(true)
+    }
+
+    /// Deletes closed files beyond `keep` or older than `max_age`.
+    fn apply_retention(&self) -> Result<()> {
+        if self.rotation.keep.is_none() && self.rotation.max_age.is_none() {
+            return Ok(());
+        }
+        let mut pattern = glob::Pattern::escape(&self.template);
+        for placeholder in ["{date}", "{hour}", "{seq}"] {
+            pattern = pattern.replace(&glob::Pattern::escape(placeholder), "*");
+        }
+        let mut patterns = vec![pattern.clone()];
+        if let Some(compression) = self.rotation.compress {
+            patterns.push(format!("{pattern}.{}", compression.extension()));
+        }
+
+        let mut files = Vec::new();
+        for pattern in patterns {
+            for path in glob::glob(&pattern).map_err(|e| Error::config(e.to_string()))? {
+                let path = path.map_err(|e| Error::Io(e.into()))?;
+                let modified = std::fs::metadata(&path)?.modified()?;
+                files.push((modified, path));
+            }
+        }
+        files.sort_by(|a, b| b.cmp(a));
+
+        let now = SystemTime::now();
+        for (i, (modified, path)) in files.into_iter().enumerate() {
+            let too_many = self.rotation.keep.is_some_and(|keep| i >= keep);
+            let too_old = self
+                .rotation
+                .max_age
+                .is_some_and(|max_age| now.duration_since(modified).is_ok_and(|age| age > max_age));
+            if too_many || too_old {
+                tracing::debug!("removing {}", path.display());
+                std::fs::remove_file(&path).map_err(with_path(&path))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::codec::{Csv, Lines};
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn read(dir: &Path, name: &str) -> String {
+        std::fs::read_to_string(dir.join(name)).unwrap()
+    }
+
+    async fn write_all<E: Encoder<String> + Clone>(
+        dir: &Path,
+        rotation: Rotation,
+        encoder: E,
+        records: &[&str],
+    ) {
+        let template = dir.join("out-{seq}.log");
+        let mut writer =
+            RotatingWriter::new(&template, rotation, false, None, None, encoder).unwrap();
+        for record in records {
+            writer.write(&record.to_string()).await.unwrap();
+        }
+        writer.finish::<String>().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rotates_after_max_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let rotation = Rotation {
+            max_records: Some(2),
+            ..Rotation::default()
+        };
+        write_all(dir.path(), rotation, Lines {}, &["a", "b", "c", "d", "e"]).await;
+
+        assert_eq!(
+            files(dir.path()),
+            ["out-0000.log", "out-0001.log", "out-0002.log"]
+        );
+        assert_eq!(read(dir.path(), "out-0000.log"), "a\nb\n");
+        assert_eq!(read(dir.path(), "out-0002.log"), "e\n");
+    }
+
+    #[tokio::test]
+    async fn rotates_once_files_reach_max_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let rotation = Rotation {
+            max_bytes: Some(4),
+            ..Rotation::default()
+        };
+        write_all(dir.path(), rotation, Lines {}, &["aa", "bb", "cc", "d"]).await;
+
+        assert_eq!(read(dir.path(), "out-0000.log"), "aa\nbb\n");
+        assert_eq!(read(dir.path(), "out-0001.log"), "cc\nd\n");
+    }
+
+    #[tokio::test]
+    async fn starts_every_file_with_a_fresh_encoder() {
+        let dir = tempfile::tempdir().unwrap();
+        let rotation = Rotation {
+            max_records: Some(1),
+            ..Rotation::default()
+        };
+        let template = dir.path().join("out-{seq}.csv");
+        let csv: Csv<Value> = serde_json::from_value(serde_json::json!({})).unwrap();
+        let mut writer = RotatingWriter::new(&template, rotation, false, None, None, csv).unwrap();
+        for n in [1, 2] {
+            writer.write(&serde_json::json!({"n": n})).await.unwrap();
+        }
+        writer.finish::<Value>().await.unwrap();
+
+        assert_eq!(read(dir.path(), "out-0000.csv"), "n\n1\n");
+        assert_eq!(read(dir.path(), "out-0001.csv"), "n\n2\n");
+    }
+
+    #[tokio::test]
+    async fn continues_after_the_highest_sequence_number_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("out-0007.log"), "old\n").unwrap();
+        write_all(dir.path(), Rotation::default(), Lines {}, &["new"]).await;
+
+        assert_eq!(files(dir.path()), ["out-0007.log", "out-0008.log"]);
+        assert_eq!(read(dir.path(), "out-0008.log"), "new\n");
+    }
+
+    #[tokio::test]
+    async fn keeps_the_newest_closed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let rotation = Rotation {
+            max_records: Some(1),
+            keep: Some(1),
+            ..Rotation::default()
+        };
+        write_all(dir.path(), rotation, Lines {}, &["a", "b", "c"]).await;
+
+        assert_eq!(files(dir.path()), ["out-0001.log", "out-0002.log"]);
+    }
+
+    #[tokio::test]
+    async fn compresses_files_rotated_away_from() {
+        let dir = tempfile::tempdir().unwrap();
+        let rotation = Rotation {
+            max_records: Some(1),
+            compress: Some(Compression::Gzip),
+            ..Rotation::default()
+        };
+        write_all(dir.path(), rotation, Lines {}, &["a", "b"]).await;
+
+        assert_eq!(files(dir.path()), ["out-0000.log.gz", "out-0001.log"]);
+        let file = tokio::fs::File::open(dir.path().join("out-0000.log.gz"))
+            .await
+            .unwrap();
+        let mut text = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut Compression::Gzip.decoder(file), &mut text)
+            .await
+            .unwrap();
+        assert_eq!(text, "a\n");
+    }
+
+    #[test]
+    fn refuses_to_compress_compressed_files_again() {
+        let rotation = Rotation {
+            compress: Some(Compression::Gzip),
+            ..Rotation::default()
+        };
+        let template = Path::new("out-{seq}.log.zst");
+        let result = RotatingWriter::new(
+            template,
+            rotation,
+            false,
+            Some(Compression::Zstd),
+            None,
+            Lines {},
+        );
+        let e = result.err().unwrap();
+        assert!(e.to_string().contains("compress them again"), "{e}");
+    }
+}
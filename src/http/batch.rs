@@ -0,0 +1,504 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt, pin_mut};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::time::Instant;
+
+use super::template::find;
+use crate::codec::Encoder;
+use crate::{Error, Result};
+
+/// How [`HttpOutput`](super::HttpOutput) groups items into request bodies.
+///
+/// A batch is sent once it holds `max_items` items, once the next item would
+/// take its body past `max_bytes`, or `linger` after its first item arrived,
+/// whichever comes first. A single item larger than `max_bytes` is sent in a
+/// batch of its own.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Batching {
+    /// Send a batch once it holds this many items.
+    pub max_items: usize,
+    /// Keep the body of a batch within this many bytes. With codecs which
+    /// hold on to items before writing them, such as Avro, only what they
+    /// wrote counts.
+    pub max_bytes: Option<usize>,
+    /// Send a batch this long after its first item arrived, even if it is
+    /// not full.
+    #[serde(with = "humantime_serde")]
+    pub linger: Option<Duration>,
+    /// How items are laid out in the body.
+    pub body: BatchBody,
+    /// Where the response reports items it rejected, if it does.
+    pub item_errors: Option<ItemErrors>,
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Self {
+            max_items: 100,
+            max_bytes: None,
+            linger: Some(Duration::from_secs(1)),
+            body: BatchBody::default(),
+            item_errors: None,
+        }
+    }
+}
+
+/// How the items of a batch are laid out in its body.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchBody {
+    /// As written by the output's codec, as one document for the whole
+    /// batch. Without a codec, as for `lines`.
+    #[default]
+    Codec,
+    /// As the elements of a JSON array.
+    JsonArray,
+    /// As JSON, one item per line.
+    Ndjson,
+    /// As text, one item per line: strings as they are, anything else as
+    /// JSON.
+    Lines,
+}
+
+/// Where a response lists how every item of a batch fared, as with the bulk
+/// API of Elasticsearch: `{results: items, error: "*.error"}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ItemErrors {
+    /// The dotted path to an array in the response with one result per item,
+    /// in order; the response itself without one.
+    #[serde(default)]
+    pub results: Option<String>,
+    /// The dotted path to the error within a result, which is absent or null
+    /// for items that went through. `*` stands for any field.
+    pub error: String,
+}
+
+impl ItemErrors {
+    /// The positions of the items `response` rejects among `items`, with
+    /// their errors.
+    pub(crate) fn rejected(&self, response: &[u8], items: usize) -> Result<Vec<(usize, String)>> {
+        let response: Value = serde_json::from_slice(response)
+            .map_err(|e| Error::codec(format!("item results: response is not JSON: {e}")))?;
+        let results = match &self.results {
+            Some(path) => find(&response, path),
+            None => Some(&response),
+        };
+        let results = results.and_then(Value::as_array).ok_or_else(|| {
+            Error::codec(format!(
+                "item results: no array at `{}`",
+                self.results.as_deref().unwrap_or_default()
+            ))
+        })?;
+        if results.len() != items {
+            return Err(Error::codec(format!(
+                "item results: {} results for {items} items",
+                results.len()
+            )));
+        }
+        Ok(results
+            .iter()
+            .enumerate()
+            .filter_map(|(i, result)| {
+                find(result, &self.error).map(|e| match e {
+                    Value::String(e) => (i, e.clone()),
+                    e => (i, e.to_string()),
+                })
+            })
+            .collect())
+    }
+}
+
+/// The body of a batch being put together, with every item on its own.
+pub(crate) struct Batch {
+    layout: BatchBody,
+    max_bytes: Option<usize>,
+    pub(crate) body: Vec<u8>,
+    /// Every item as JSON, or as it is, to set aside the ones that fail.
+    pub(crate) items: Vec<Bytes>,
+}
+
+impl Batch {
+    fn new(batching: &Batching) -> Self {
+        Self {
+            layout: batching.body,
+            max_bytes: batching.max_bytes,
+            body: Vec::new(),
+            items: Vec::new(),
+        }
+    }
+
+    /// Whether `len` more bytes keep the body within `max_bytes`, which an
+    /// empty batch always does.
+    fn has_room(&self, len: usize) -> bool {
+        self.items.is_empty()
+            || self
+                .max_bytes
+                .is_none_or(|max| self.body.len() + len <= max)
+    }
+
+    /// Lays out `item` in the body, if there is room for it.
+    fn add(&mut self, item: Bytes) -> Added<Bytes> {
+        let framing = match self.layout {
+            // the separator and the closing bracket
+            BatchBody::JsonArray => 2,
+            _ => usize::from(!item.ends_with(b"\n")),
+        };
+        if !self.has_room(item.len() + framing) {
+            return Added::NoRoom(item);
+        }
+        self.push(item);
+        Added::Fits
+    }
+
+    /// Lays out `item` in the body.
+    fn push(&mut self, item: Bytes) {
+        match self.layout {
+            BatchBody::JsonArray => {
+                self.body
+                    .push(if self.items.is_empty() { b'[' } else { b',' });
+                self.body.extend_from_slice(&item);
+            }
+            BatchBody::Codec | BatchBody::Ndjson | BatchBody::Lines => {
+                self.body.extend_from_slice(&item);
+                if !item.ends_with(b"\n") {
+                    self.body.push(b'\n');
+                }
+            }
+        }
+        self.items.push(item);
+    }
+
+    fn close(&mut self) {
+        if self.layout == BatchBody::JsonArray {
+            self.body.push(b']');
+        }
+    }
+}
+
+/// Whether an item went into a batch.
+pub(crate) enum Added<T> {
+    Fits,
+    /// The batch has no room for the item, which is handed back to start the
+    /// next batch with.
+    NoRoom(T),
+}
+
+/// Writes items of type `T` into batches.
+pub(crate) trait Framer<T> {
+    /// Adds `item` to `batch`, unless it would take the body past
+    /// `max_bytes`, leaving `batch` as it was.
+    fn add(&mut self, batch: &mut Batch, item: T) -> Result<Added<T>>;
+
+    fn finish(&mut self, batch: &mut Batch) -> Result<()>;
+}
+
+/// Frames items written without a codec as they are.
+pub(crate) struct Raw;
+
+impl Framer<Bytes> for Raw {
+    fn add(&mut self, batch: &mut Batch, item: Bytes) -> Result<Added<Bytes>> {
+        Ok(batch.add(item))
+    }
+
+    fn finish(&mut self, batch: &mut Batch) -> Result<()> {
+        batch.close();
+        Ok(())
+    }
+}
+
+/// Frames serializable items, through `encoder` for [`BatchBody::Codec`].
+pub(crate) struct Serialized<E, T> {
+    encoder: E,
+    /// The items of the batch, with [`BatchBody::Codec`], to encode them
+    /// again without the last one should it not fit.
+    items: Vec<T>,
+}
+
+impl<E, T> Serialized<E, T> {
+    pub(crate) fn new(encoder: E) -> Self {
+        Self {
+            encoder,
+            items: Vec::new(),
+        }
+    }
+}
+
+impl<T: Serialize, E: Encoder<T>> Framer<T> for Serialized<E, T> {
+    fn add(&mut self, batch: &mut Batch, item: T) -> Result<Added<T>> {
+        let json = match batch.layout {
+            BatchBody::Codec => {
+                let json = serde_json::to_vec(&item).map_err(Error::codec)?;
+                let mut encoded = Vec::new();
+                self.encoder.encode(&item, &mut encoded)?;
+                if !batch.has_room(encoded.len()) {
+                    // the encoder may hold on to some of what it encoded, so
+                    // it starts over with the items that fit
+                    self.encoder.finish(&mut Vec::new())?;
+                    batch.body.clear();
+                    for item in &self.items {
+                        self.encoder.encode(item, &mut batch.body)?;
+                    }
+                    return Ok(Added::NoRoom(item));
+                }
+                batch.body.append(&mut encoded);
+                self.items.push(item);
+                batch.items.push(json.into());
+                return Ok(Added::Fits);
+            }
+            BatchBody::JsonArray | BatchBody::Ndjson => {
+                serde_json::to_vec(&item).map_err(Error::codec)?.into()
+            }
+            BatchBody::Lines => match serde_json::to_value(&item).map_err(Error::codec)? {
+                Value::String(text) => text.into(),
+                value => value.to_string().into(),
+            },
+        };
+        Ok(match batch.add(json) {
+            Added::Fits => Added::Fits,
+            Added::NoRoom(_) => Added::NoRoom(item),
+        })
+    }
+
+    fn finish(&mut self, batch: &mut Batch) -> Result<()> {
+        match batch.layout {
+            BatchBody::Codec => {
+                self.items.clear();
+                self.encoder.finish(&mut batch.body)
+            }
+            _ => {
+                batch.close();
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Batching {
+    pub(crate) fn check(&self) -> Result<(), String> {
+        if self.max_items == 0 {
+            return Err("max_items must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    fn is_full(&self, batch: &Batch) -> bool {
+        batch.items.len() >= self.max_items
+            || self.max_bytes.is_some_and(|max| batch.body.len() >= max)
+    }
+
+    /// Groups `items` into batches, written by `framer`.
+    pub(crate) fn batches<S, T, F>(
+        &self,
+        items: S,
+        mut framer: F,
+    ) -> impl Stream<Item = Result<Batch>> + Send
+    where
+        S: Stream<Item = T> + Send,
+        T: Send,
+        F: Framer<T> + Send,
+    {
+        let batching = self.clone();
+        async_stream::try_stream! {
+            pin_mut!(items);
+            let mut batch = Batch::new(&batching);
+            let mut deadline = None;
+            // an item that did not fit into the last batch
+            let mut carried = None;
+            loop {
+                let item = match (carried.take(), deadline) {
+                    (Some(item), _) => Some(Some(item)),
+                    (None, Some(deadline)) => tokio::select! {
+                        item = items.next() => Some(item),
+                        () = tokio::time::sleep_until(deadline) => None,
+                    },
+                    (None, None) => Some(items.next().await),
+                };
+                match item {
+                    // lingered long enough
+                    None => {}
+                    Some(None) => break,
+                    Some(Some(item)) => {
+                        if batch.items.is_empty() {
+                            deadline = batching.linger.map(|linger| Instant::now() + linger);
+                        }
+                        match framer.add(&mut batch, item)? {
+                            Added::Fits if !batching.is_full(&batch) => continue,
+                            Added::Fits => {}
+                            Added::NoRoom(item) => carried = Some(item),
+                        }
+                    }
+                }
+                framer.finish(&mut batch)?;
+                yield std::mem::replace(&mut batch, Batch::new(&batching));
+                deadline = None;
+            }
+            if !batch.items.is_empty() {
+                framer.finish(&mut batch)?;
+                yield batch;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{TryStreamExt, stream};
+    use serde_json::json;
+
+    use super::*;
+    use crate::codec::Csv;
+
+    fn without_linger(body: BatchBody) -> Batching {
+        Batching {
+            body,
+            linger: None,
+            ..Batching::default()
+        }
+    }
+
+    async fn batch_bodies<T, F>(batching: &Batching, items: Vec<T>, framer: F) -> Vec<String>
+    where
+        T: Send,
+        F: Framer<T> + Send,
+    {
+        let batches: Vec<_> = batching
+            .batches(stream::iter(items), framer)
+            .try_collect()
+            .await
+            .unwrap();
+        batches
+            .into_iter()
+            .map(|batch| String::from_utf8(batch.body).unwrap())
+            .collect()
+    }
+
+    fn raw(items: &[&'static str]) -> Vec<Bytes> {
+        items
+            .iter()
+            .map(|item| Bytes::from_static(item.as_bytes()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn sends_batches_of_up_to_max_items() {
+        let batching = Batching {
+            max_items: 2,
+            ..without_linger(BatchBody::Lines)
+        };
+        let bodies = batch_bodies(&batching, raw(&["a", "b\n", "c"]), Raw).await;
+        assert_eq!(bodies, ["a\nb\n", "c\n"]);
+    }
+
+    #[tokio::test]
+    async fn keeps_batches_within_max_bytes() {
+        let batching = Batching {
+            max_bytes: Some(8),
+            ..without_linger(BatchBody::Lines)
+        };
+        let bodies =
+            batch_bodies(&batching, raw(&["aaa", "bbb", "c", "long item", "d"]), Raw).await;
+        assert_eq!(bodies, ["aaa\nbbb\n", "c\n", "long item\n", "d\n"]);
+
+        let batching = Batching {
+            max_bytes: Some(14),
+            ..without_linger(BatchBody::JsonArray)
+        };
+        let items = vec![json!(1), json!("two"), json!([3]), json!({"n": 4})];
+        let bodies = batch_bodies(&batching, items, Serialized::new(Csv::<Value>::new())).await;
+        assert_eq!(bodies, [r#"[1,"two",[3]]"#, r#"[{"n":4}]"#]);
+        for body in &bodies {
+            assert!(body.len() <= 14);
+            serde_json::from_str::<Value>(body).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn encodes_every_batch_as_a_document_of_its_own() {
+        let batching = Batching {
+            max_bytes: Some(18),
+            ..without_linger(BatchBody::Codec)
+        };
+        let items: Vec<_> = (1..=4).map(|n| json!({"id": n, "name": "x"})).collect();
+        let bodies = batch_bodies(&batching, items, Serialized::new(Csv::<Value>::new())).await;
+        assert_eq!(bodies, ["id,name\n1,x\n2,x\n", "id,name\n3,x\n4,x\n"]);
+    }
+
+    #[tokio::test]
+    async fn keeps_every_item_on_its_own() {
+        let batching = without_linger(BatchBody::Codec);
+        let items = vec![json!({"id": 1}), json!({"id": 2})];
+        let batches: Vec<_> = batching
+            .batches(stream::iter(items), Serialized::new(Csv::<Value>::new()))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].items, [r#"{"id":1}"#, r#"{"id":2}"#]);
+    }
+
+    #[tokio::test]
+    async fn sends_batches_that_lingered_long_enough() {
+        let batching = Batching {
+            linger: Some(Duration::from_millis(20)),
+            ..without_linger(BatchBody::Ndjson)
+        };
+        let items = stream::iter([json!(1), json!(2)]).chain(stream::pending());
+        let batches = batching.batches(items, Serialized::new(Csv::<Value>::new()));
+        pin_mut!(batches);
+        let batch = tokio::time::timeout(Duration::from_secs(5), batches.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(batch.body, b"1\n2\n");
+    }
+
+    #[test]
+    fn finds_the_items_a_response_rejects() {
+        let item_errors = ItemErrors {
+            results: Some("items".to_string()),
+            error: "*.error.reason".to_string(),
+        };
+        let response = json!({"items": [
+            {"index": {"status": 201}},
+            {"index": {"error": {"reason": "mapping"}}},
+            {"create": {"error": {"reason": {"code": 7}}}},
+        ]});
+        let rejected = item_errors
+            .rejected(response.to_string().as_bytes(), 3)
+            .unwrap();
+        assert_eq!(
+            rejected,
+            [(1, "mapping".to_string()), (2, r#"{"code":7}"#.to_string())]
+        );
+
+        let error = item_errors.rejected(response.to_string().as_bytes(), 2);
+        assert!(
+            error
+                .unwrap_err()
+                .to_string()
+                .contains("3 results for 2 items")
+        );
+        let error = item_errors.rejected(b"{}", 1);
+        assert!(
+            error
+                .unwrap_err()
+                .to_string()
+                .contains("no array at `items`")
+        );
+        let error = item_errors.rejected(b"<html>", 1);
+        assert!(error.unwrap_err().to_string().contains("not JSON"));
+    }
+
+    #[test]
+    fn rejects_empty_batches() {
+        let batching: Batching = serde_json::from_value(json!({"max_items": 0})).unwrap();
+        assert!(batching.check().unwrap_err().contains("max_items"));
+        without_linger(BatchBody::Lines).check().unwrap();
+    }
+}
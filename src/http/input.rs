@@ -0,0 +1,700 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use http::HeaderMap;
+use http::header::{HeaderName, HeaderValue, LINK};
+use reqwest::{RequestBuilder, Url};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::time::Instant;
+
+use super::RetryPolicy;
+use super::template::find;
+use crate::codec::{Decode, Decoder, Lines};
+use crate::{Error, Input, Result};
+
+/// Fetches items from an HTTP endpoint, once or every `poll_interval`.
+///
+/// Response bodies are decoded with the input's codec, or split into lines
+/// without one. With `records`, a body is read as JSON instead, and the
+/// elements of the array at that dotted path are decoded as one JSON item
+/// per line, e.g. with the `ndjson` codec.
+///
+/// With `pagination`, further pages are fetched until none are left. Polls
+/// start `poll_interval` after the last page of the previous one, from where
+/// [`Repoll`] says. Failed requests are retried according to `retry`, and
+/// `rate_limit` spaces all requests out, retries included.
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "HttpInputConfig")]
+pub struct HttpInput {
+    endpoint: Url,
+    method: http::method::Method,
+    body: Option<String>,
+    records: Option<String>,
+    pagination: Option<Pagination>,
+    poll: Option<(Duration, Repoll)>,
+    rate_limit: Option<RateLimit>,
+    retry: RetryPolicy,
+    client: reqwest::Client,
+}
+
+/// Where an [`HttpInput`] starts every poll after the first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Repoll {
+    /// Start over from the first page, fetching every item again.
+    Restart,
+    /// Fetch the last page of the previous poll again and carry on from
+    /// there, skipping as many items of it as were fetched before, for
+    /// endpoints which only ever add items to their last page.
+    Continue,
+}
+
+/// How an [`HttpInput`] finds the page after the one it fetched last.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Pagination {
+    /// Follow the `next` link of the `Link` header.
+    Link,
+    /// Pass the cursor at the dotted path `cursor` of every body, read as
+    /// JSON, as the `param` query parameter, until a body holds none.
+    Cursor { cursor: String, param: String },
+    /// Count the `offset_param` query parameter up from 0 by the items of
+    /// every page, asking for `limit` items at a time with the `limit_param`
+    /// parameter, until a page has fewer.
+    Offset {
+        #[serde(default = "default_offset_param")]
+        offset_param: String,
+        #[serde(default = "default_limit_param")]
+        limit_param: String,
+        limit: u64,
+    },
+    /// Count the `param` query parameter up from `start`, until a page has
+    /// no items.
+    Page {
+        #[serde(default = "default_page_param")]
+        param: String,
+        #[serde(default = "default_page_start")]
+        start: u64,
+    },
+}
+
+fn default_offset_param() -> String {
+    "offset".to_string()
+}
+
+fn default_limit_param() -> String {
+    "limit".to_string()
+}
+
+fn default_page_param() -> String {
+    "page".to_string()
+}
+
+fn default_page_start() -> u64 {
+    1
+}
+
+/// At most `requests` requests every `per`, evenly spaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub requests: u32,
+    #[serde(default = "default_rate_period", with = "humantime_serde")]
+    pub per: Duration,
+}
+
+fn default_rate_period() -> Duration {
+    Duration::from_secs(1)
+}
+
+impl RateLimit {
+    pub(crate) fn check(&self) -> Result<(), String> {
+        if self.requests == 0 {
+            return Err("requests must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Spaces requests out according to a rate limit, if any.
+#[derive(Debug, Default)]
+pub(crate) struct Pacer {
+    rate_limit: Option<RateLimit>,
+    /// When the last request was sent.
+    last: Option<Instant>,
+}
+
+impl Pacer {
+    fn new(rate_limit: Option<RateLimit>) -> Self {
+        Self {
+            rate_limit,
+            last: None,
+        }
+    }
+
+    /// Waits until the rate limit allows another request.
+    pub(crate) async fn wait(&mut self) {
+        if let (Some(limit), Some(last)) = (self.rate_limit, self.last) {
+            tokio::time::sleep_until(last + limit.per / limit.requests).await;
+        }
+        self.last = Some(Instant::now());
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HttpInputConfig {
+    endpoint: String,
+    #[serde(default = "default_method")]
+    method: String,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    records: Option<String>,
+    #[serde(default)]
+    pagination: Option<Pagination>,
+    #[serde(default, with = "humantime_serde")]
+    poll_interval: Option<Duration>,
+    #[serde(default)]
+    repoll: Option<Repoll>,
+    #[serde(default)]
+    rate_limit: Option<RateLimit>,
+    #[serde(default)]
+    retry: RetryPolicy,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+impl TryFrom<HttpInputConfig> for HttpInput {
+    type Error = String;
+
+    fn try_from(config: HttpInputConfig) -> Result<Self, String> {
+        Self::from_config(config).map_err(|e| match e {
+            Error::Config(msg) => msg,
+            e => e.to_string(),
+        })
+    }
+}
+
+impl HttpInput {
+    fn from_config(config: HttpInputConfig) -> Result<Self> {
+        let endpoint = Url::parse(&config.endpoint)
+            .map_err(|e| Error::config(format!("endpoint `{}`: {e}", config.endpoint)))?;
+        let method = config
+            .method
+            .parse()
+            .map_err(|e| Error::config(format!("method `{}`: {e}", config.method)))?;
+        let mut headers = HeaderMap::new();
+        for (name, value) in &config.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| Error::config(format!("header `{name}`: {e}")))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| Error::config(format!("header `{name}`: {e}")))?;
+            headers.insert(name, value);
+        }
+        config
+            .retry
+            .check()
+            .map_err(|e| Error::config(format!("retry: {e}")))?;
+        let mut input = Self::new(endpoint, method, Some(headers))?.retry(config.retry);
+        input.body = config.body;
+        input.records = config.records;
+        input.pagination = config.pagination;
+        match (config.poll_interval, config.repoll) {
+            (Some(interval), Some(repoll)) => input = input.poll_interval(interval, repoll),
+            (Some(_), None) => {
+                return Err(Error::config(
+                    "poll_interval needs `repoll`: `restart` to fetch every item again on \
+                     every poll, or `continue` to carry on from the last page",
+                ));
+            }
+            (None, Some(_)) => return Err(Error::config("repoll needs a poll_interval")),
+            (None, None) => {}
+        }
+        if let Some(rate_limit) = config.rate_limit {
+            input = input.rate_limit(rate_limit)?;
+        }
+        Ok(input)
+    }
+
+    pub fn new<T: Into<Url>>(
+        endpoint: T,
+        method: http::method::Method,
+        default_headers: Option<HeaderMap>,
+    ) -> Result<Self> {
+        let mut client_builder = reqwest::ClientBuilder::new();
+        if let Some(headers) = default_headers {
+            client_builder = client_builder.default_headers(headers);
+        }
+        Ok(Self {
+            endpoint: endpoint.into(),
+            method,
+            body: None,
+            records: None,
+            pagination: None,
+            poll: None,
+            rate_limit: None,
+            retry: RetryPolicy::default(),
+            client: client_builder.build()?,
+        })
+    }
+
+    /// Sends `body` with every request.
+    pub fn body<B: Into<String>>(mut self, body: B) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// Takes the items from the array at the dotted path `records` of every
+    /// body, read as JSON.
+    pub fn records<P: Into<String>>(mut self, records: P) -> Self {
+        self.records = Some(records.into());
+        self
+    }
+
+    pub fn pagination(mut self, pagination: Pagination) -> Self {
+        self.pagination = Some(pagination);
+        self
+    }
+
+    /// Fetches items again `poll_interval` after the last page, from where
+    /// `repoll` says.
+    pub fn poll_interval(mut self, poll_interval: Duration, repoll: Repoll) -> Self {
+        self.poll = Some((poll_interval, repoll));
+        self
+    }
+
+    /// Spaces requests out to stay within `rate_limit`, which allows at least
+    /// one request.
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Result<Self> {
+        rate_limit
+            .check()
+            .map_err(|e| Error::config(format!("rate_limit: {e}")))?;
+        self.rate_limit = Some(rate_limit);
+        Ok(self)
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    fn request(&self, url: Url) -> RequestBuilder {
+        let request = self.client.request(self.method.clone(), url);
+        match &self.body {
+            Some(body) => request.body(body.clone()),
+            None => request,
+        }
+    }
+
+    /// Fetches the page at `url`, with every attempt paced by `pacer`,
+    /// returning its headers, its body, and the body read as JSON if it is
+    /// needed.
+    async fn fetch(
+        &self,
+        url: &Url,
+        pacer: &mut Pacer,
+    ) -> Result<(HeaderMap, Bytes, Option<Value>)> {
+        let request = self.request(url.clone());
+        let response = self.retry.send_paced(request, pacer).await?;
+        let headers = response.headers().clone();
+        let body = response.bytes().await?;
+        let is_cursor = matches!(self.pagination, Some(Pagination::Cursor { .. }));
+        if self.records.is_none() && !is_cursor {
+            return Ok((headers, body, None));
+        }
+        let json = serde_json::from_slice(&body)
+            .map_err(|e| Error::codec(format!("{url}: body is not JSON: {e}")))?;
+        let body = match &self.records {
+            Some(path) => records(&json, path).map_err(|e| Error::codec(format!("{url}: {e}")))?,
+            None => body,
+        };
+        Ok((headers, body, Some(json)))
+    }
+
+    /// The URL of the first page.
+    fn first_page(&self) -> Url {
+        let url = &self.endpoint;
+        match &self.pagination {
+            None | Some(Pagination::Link | Pagination::Cursor { .. }) => url.clone(),
+            Some(Pagination::Offset {
+                offset_param,
+                limit_param,
+                limit,
+            }) => {
+                let offset = param(url, offset_param).unwrap_or(0);
+                let url = with_param(url, limit_param, &limit.to_string());
+                with_param(&url, offset_param, &offset.to_string())
+            }
+            Some(Pagination::Page { param: name, start }) => {
+                let page = param(url, name).unwrap_or(*start);
+                with_param(url, name, &page.to_string())
+            }
+        }
+    }
+
+    /// The URL of the page after the one at `url`, which held `items` items,
+    /// if there is one.
+    fn next_page(
+        &self,
+        url: &Url,
+        headers: &HeaderMap,
+        json: Option<&Value>,
+        items: u64,
+    ) -> Result<Option<Url>> {
+        let next = match &self.pagination {
+            None => None,
+            Some(Pagination::Link) => next_link(url, headers)?,
+            Some(Pagination::Cursor { cursor, param }) => {
+                match json.and_then(|json| find(json, cursor)) {
+                    Some(Value::String(cursor)) if cursor.is_empty() => None,
+                    Some(Value::String(cursor)) => Some(with_param(url, param, cursor)),
+                    Some(cursor) => Some(with_param(url, param, &cursor.to_string())),
+                    None => None,
+                }
+            }
+            Some(Pagination::Offset {
+                offset_param,
+                limit,
+                ..
+            }) => (items >= *limit && items > 0).then(|| {
+                let offset = param(url, offset_param).unwrap_or(0) + items;
+                with_param(url, offset_param, &offset.to_string())
+            }),
+            Some(Pagination::Page { param: name, start }) => (items > 0).then(|| {
+                let page = param(url, name).unwrap_or(*start) + 1;
+                with_param(url, name, &page.to_string())
+            }),
+        };
+        // a page pointing back to itself would be fetched forever
+        Ok(next.filter(|next| next != url))
+    }
+}
+
+/// The elements of the array at the dotted `path` of `json`, one per line.
+fn records(json: &Value, path: &str) -> Result<Bytes, String> {
+    let records = find(json, path)
+        .and_then(Value::as_array)
+        .ok_or_else(|| format!("no array at `{path}`"))?;
+    let mut body = Vec::new();
+    for record in records {
+        serde_json::to_writer(&mut body, record).map_err(|e| e.to_string())?;
+        body.push(b'\n');
+    }
+    Ok(body.into())
+}
+
+/// The `rel="next"` link of a `Link` header, resolved against `url`.
+fn next_link(url: &Url, headers: &HeaderMap) -> Result<Option<Url>> {
+    for value in headers.get_all(LINK) {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+        for link in value.split(',') {
+            let mut parts = link.split(';');
+            let Some(target) = parts.next().map(str::trim) else {
+                continue;
+            };
+            let is_next = parts.any(|part| {
+                part.trim()
+                    .strip_prefix("rel=")
+                    .is_some_and(|rel| rel.trim_matches('"').split(' ').any(|rel| rel == "next"))
+            });
+            let Some(target) = target.strip_prefix('<').and_then(|t| t.strip_suffix('>')) else {
+                continue;
+            };
+            if is_next {
+                return url
+                    .join(target)
+                    .map(Some)
+                    .map_err(|e| Error::codec(format!("next link `{target}`: {e}")));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// The number in query parameter `name` of `url`, if any.
+fn param(url: &Url, name: &str) -> Option<u64> {
+    url.query_pairs()
+        .find(|(key, _)| key == name)
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+/// `url` with query parameter `name` set to `value`.
+fn with_param(url: &Url, name: &str, value: &str) -> Url {
+    let pairs: Vec<_> = url
+        .query_pairs()
+        .filter(|(key, _)| key != name)
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    let mut url = url.clone();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .append_pair(name, value);
+    url
+}
+
+/// Decodes all items of a page.
+fn decode_page<D: Decoder>(decoder: &mut D, body: &[u8]) -> Result<Vec<D::Item>> {
+    let mut buf = BytesMut::from(body);
+    let mut items = Vec::new();
+    while let Some(item) = decoder.decode_eof(&mut buf)? {
+        items.push(item);
+    }
+    Ok(items)
+}
+
+impl Input<String> for HttpInput {
+    fn into_stream(self) -> impl futures::Stream<Item = Result<String>> + Send {
+        Decode::new(self, Lines {}).into_stream()
+    }
+}
+
+impl<D> Input<D::Item> for Decode<HttpInput, D>
+where
+    D: Decoder + Send,
+    D::Item: Send,
+{
+    fn into_stream(self) -> impl futures::Stream<Item = Result<D::Item>> + Send {
+        let Decode { input, mut decoder } = self;
+        async_stream::try_stream! {
+            let mut pacer = Pacer::new(input.rate_limit);
+            // the last page fetched, with the number of items it held
+            let mut last = None;
+            loop {
+                let mut page = match (input.poll, last.take()) {
+                    (Some((_, Repoll::Continue)), Some(last)) => Some(last),
+                    _ => Some((input.first_page(), 0)),
+                };
+                while let Some((url, seen)) = page.take() {
+                    let (headers, body, json) = input.fetch(&url, &mut pacer).await?;
+                    let items = decode_page(&mut decoder, &body)?;
+                    let held = items.len();
+                    page = input
+                        .next_page(&url, &headers, json.as_ref(), held as u64)?
+                        .map(|next| (next, 0));
+                    if page.is_none() {
+                        last = Some((url, held));
+                    }
+                    for item in items.into_iter().skip(seen) {
+                        yield item;
+                    }
+                }
+                let Some((interval, _)) = input.poll else {
+                    break;
+                };
+                tokio::time::sleep(interval).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{StreamExt, TryStreamExt};
+    use serde_json::json;
+
+    use super::super::mock::{Reply, Server};
+    use super::*;
+    use crate::codec::Ndjson;
+
+    fn input(server: &Server, path: &str) -> HttpInput {
+        let url = Url::parse(&server.url(path)).unwrap();
+        HttpInput::new(url, http::Method::GET, None)
+            .unwrap()
+            .retry(RetryPolicy::none())
+    }
+
+    async fn lines(input: HttpInput, n: usize) -> Vec<String> {
+        input.into_stream().take(n).try_collect().await.unwrap()
+    }
+
+    fn paths(server: &Server) -> Vec<String> {
+        server.requests().into_iter().map(|r| r.path).collect()
+    }
+
+    #[tokio::test]
+    async fn follows_next_links() {
+        let server = Server::start(|_, request| match request.path.as_str() {
+            "/" => Reply::ok("a\nb\n").header("link", r#"</p2>; rel="prev", </p2>; rel="next""#),
+            "/p2" => Reply::ok("c").header("link", r#"</>; rel="first""#),
+            _ => Reply::status(404),
+        })
+        .await;
+        let input = input(&server, "/").pagination(Pagination::Link);
+        assert_eq!(lines(input, 10).await, ["a", "b", "c"]);
+        assert_eq!(paths(&server), ["/", "/p2"]);
+    }
+
+    #[tokio::test]
+    async fn stops_at_pages_pointing_back_to_themselves() {
+        let server =
+            Server::start(|_, _| Reply::ok("a").header("link", r#"</>; rel="next""#)).await;
+        let input = input(&server, "/").pagination(Pagination::Link);
+        assert_eq!(lines(input, 10).await, ["a"]);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn passes_cursors_on() {
+        let server = Server::start(|_, request| match request.path.as_str() {
+            "/items" => Reply::ok(r#"{"data": [1, 2], "meta": {"next": "x y"}}"#),
+            "/items?after=x+y" => Reply::ok(r#"{"data": [{"n": 3}], "meta": {"next": ""}}"#),
+            _ => Reply::status(404),
+        })
+        .await;
+        let input = input(&server, "/items")
+            .records("data")
+            .pagination(Pagination::Cursor {
+                cursor: "meta.next".to_string(),
+                param: "after".to_string(),
+            });
+        let items: Vec<Value> = Decode::new(input, Ndjson::new())
+            .into_stream()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(items, [json!(1), json!(2), json!({"n": 3})]);
+    }
+
+    /// A server listing `items` by the `offset` and `limit` parameters, or
+    /// by `page`, two at a time.
+    async fn listing(items: impl Fn(usize) -> Vec<&'static str> + Send + Sync + 'static) -> Server {
+        Server::start(move |n, request| {
+            let url = Url::parse(&format!("http://localhost{}", request.path)).unwrap();
+            let items = items(n);
+            let (offset, limit) = match param(&url, "page") {
+                Some(page) => ((page as usize - 1) * 2, 2),
+                None => (
+                    param(&url, "offset").unwrap() as usize,
+                    param(&url, "limit").unwrap() as usize,
+                ),
+            };
+            let page = items.iter().skip(offset).take(limit);
+            Reply::ok(page.map(|item| format!("{item}\n")).collect::<String>())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn counts_offsets_up_until_a_page_is_short() {
+        let server = listing(|_| vec!["a", "b", "c"]).await;
+        let input = input(&server, "/?sort=id").pagination(Pagination::Offset {
+            offset_param: "offset".to_string(),
+            limit_param: "limit".to_string(),
+            limit: 2,
+        });
+        assert_eq!(lines(input, 10).await, ["a", "b", "c"]);
+        assert_eq!(
+            paths(&server),
+            ["/?sort=id&limit=2&offset=0", "/?sort=id&limit=2&offset=2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn counts_pages_up_until_one_is_empty() {
+        let server = listing(|_| vec!["a", "b", "c", "d"]).await;
+        let input = input(&server, "/").pagination(Pagination::Page {
+            param: "page".to_string(),
+            start: 1,
+        });
+        assert_eq!(lines(input, 10).await, ["a", "b", "c", "d"]);
+        assert_eq!(paths(&server), ["/?page=1", "/?page=2", "/?page=3"]);
+    }
+
+    #[tokio::test]
+    async fn restarts_polls_from_the_first_page() {
+        let server =
+            Server::start(|n, _| Reply::ok((0..=n).map(|i| format!("{i}\n")).collect::<String>()))
+                .await;
+        let input = input(&server, "/").poll_interval(Duration::from_millis(10), Repoll::Restart);
+        assert_eq!(lines(input, 6).await, ["0", "0", "1", "0", "1", "2"]);
+    }
+
+    #[tokio::test]
+    async fn continues_polls_from_the_last_page() {
+        let server = listing(|n| match n {
+            0 | 1 => vec!["a", "b", "c"],
+            _ => vec!["a", "b", "c", "d", "e", "f"],
+        })
+        .await;
+        let input = input(&server, "/")
+            .pagination(Pagination::Offset {
+                offset_param: "offset".to_string(),
+                limit_param: "limit".to_string(),
+                limit: 2,
+            })
+            .poll_interval(Duration::from_millis(10), Repoll::Continue);
+        assert_eq!(lines(input, 6).await, ["a", "b", "c", "d", "e", "f"]);
+        assert_eq!(
+            paths(&server)[..4],
+            [
+                "/?limit=2&offset=0",
+                "/?limit=2&offset=2",
+                "/?limit=2&offset=2",
+                "/?limit=2&offset=4",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn paces_retries_too() {
+        let server = Server::start(|n, _| match n {
+            0 => Reply::status(503),
+            _ => Reply::ok("a"),
+        })
+        .await;
+        let retry = RetryPolicy::default()
+            .initial_backoff(Duration::from_millis(1))
+            .jitter(0.0);
+        let rate_limit = RateLimit {
+            requests: 10,
+            per: Duration::from_secs(1),
+        };
+        let input = input(&server, "/")
+            .retry(retry)
+            .rate_limit(rate_limit)
+            .unwrap();
+        assert_eq!(lines(input, 10).await, ["a"]);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].at - requests[0].at >= Duration::from_millis(90));
+    }
+
+    #[tokio::test]
+    async fn rejects_rate_limits_without_requests() {
+        let server = Server::start(|_, _| Reply::ok("")).await;
+        let rate_limit = RateLimit {
+            requests: 0,
+            per: Duration::from_secs(1),
+        };
+        let error = input(&server, "/").rate_limit(rate_limit).unwrap_err();
+        assert!(error.to_string().contains("requests must be at least 1"));
+        let config = json!({"endpoint": "http://localhost/", "rate_limit": {"requests": 0}});
+        assert!(serde_json::from_value::<HttpInput>(config).is_err());
+    }
+
+    #[test]
+    fn polling_needs_repoll() {
+        let config = json!({"endpoint": "http://localhost/", "poll_interval": "1m"});
+        let error = serde_json::from_value::<HttpInput>(config).unwrap_err();
+        assert!(error.to_string().contains("needs `repoll`"), "{error}");
+        let config = json!({"endpoint": "http://localhost/", "repoll": "continue"});
+        assert!(serde_json::from_value::<HttpInput>(config).is_err());
+        let config = json!({
+            "endpoint": "http://localhost/",
+            "poll_interval": "1m",
+            "repoll": "restart",
+        });
+        let input: HttpInput = serde_json::from_value(config).unwrap();
+        assert_eq!(input.poll, Some((Duration::from_secs(60), Repoll::Restart)));
+    }
+}
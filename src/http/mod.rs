@@ -1,81 +1,889 @@
-use futures::{StreamExt, pin_mut};
-use http::{HeaderMap, StatusCode};
-use reqwest::Body;
+use std::collections::BTreeMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::Range;
+use std::sync::Mutex;
 
-use crate::Output;
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::{Stream, StreamExt, TryStreamExt, future, pin_mut, stream};
+use http::HeaderMap;
+use http::header::{HeaderName, HeaderValue};
+use reqwest::{RequestBuilder, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{Semaphore, SemaphorePermit};
 
-struct HttpInput {
-    endpoint: String,
-    method: http::method::Method,
-    codec: String,
-}
+use crate::codec::{Encode, Encoder};
+use crate::{Acks, Error, Failed, Output, Result};
 
-struct HttpOutput {
-    endpoint: reqwest::Url,
+mod batch;
+mod input;
+#[cfg(test)]
+pub(crate) mod mock;
+mod retry;
+mod template;
+
+use batch::{Batch, Raw, Serialized};
+pub use batch::{BatchBody, Batching, ItemErrors};
+pub use input::{HttpInput, Pagination, RateLimit, Repoll};
+pub use retry::RetryPolicy;
+use template::{Template, escape_url, verbatim};
+
+/// Sends every item as the body of a request to `endpoint` with `method`.
+///
+/// The endpoint and header values may hold `{field}` placeholders, filled in
+/// from the fields of each item, as in `http://localhost:9200/index/_doc/{id}`.
+/// Nested fields go by their dotted path, as in `{user.id}`; `{{` and `}}`
+/// stand for literal braces. Values filled into the endpoint are
+/// percent-encoded. Items written without a codec are read as JSON to find
+/// their fields.
+///
+/// Up to `concurrency` requests are in flight at once, and items are taken
+/// from the stream only as fast as requests complete. Requests complete in
+/// any order, unless `order_by` names a key, such as `{user.id}`: items
+/// with the same key are then sent one after the other, in stream order,
+/// while items with other keys go ahead. Keys share `concurrency` lanes, and
+/// requests wait in their lane for the ones before them, so that items are
+/// taken from the stream as long as fewer than 64 requests per lane are
+/// waiting.
+///
+/// With `batch`, items are sent in batches, laid out in the body of a request
+/// as [`Batching`] says. The items of a batch share a request, so the
+/// endpoint and headers cannot be filled in per item then. As items with the
+/// same key may be in any batch, `order_by` sends batches one at a time, in
+/// stream order, whatever the key.
+///
+/// Failed requests are retried according to `retry`. Items that still cannot
+/// be delivered, or that the response to a batch reports as rejected, fail
+/// the output, unless they are handed to a [`dead_letter`](Self::dead_letter)
+/// output, with the reason, and the output carries on. Single items are
+/// handed over as sent; items of batches as JSON, or as they are without a
+/// codec.
+///
+/// Items are acknowledged to a pipeline once the requests holding them, and
+/// those holding every item before them, have completed.
+pub struct HttpOutput<D = NoDeadLetter> {
+    endpoint: Endpoint,
     method: http::method::Method,
+    /// Headers whose values are filled in per item.
+    headers: Vec<(HeaderName, Template)>,
+    concurrency: usize,
+    order_by: Option<Template>,
+    batch: Option<Batching>,
+    retry: RetryPolicy,
+    dead_letter: Option<D>,
     client: reqwest::Client,
 }
 
+/// Requests that may wait per lane, while items are ordered by key, before
+/// items are no longer taken from the stream.
+const QUEUED_PER_LANE: usize = 64;
+
+/// A request ready to go, with the items in its body and the lane it is sent
+/// in when ordered by key.
+struct Pending {
+    request: RequestBuilder,
+    items: Vec<Bytes>,
+    lane: u64,
+}
+
+/// Acknowledges items as the requests holding them complete, in any order.
+struct Completed {
+    acks: Acks,
+    /// Items acknowledged so far, and the ranges of items completed after
+    /// them, by their start.
+    done: Mutex<(u64, BTreeMap<u64, u64>)>,
+}
+
+impl Completed {
+    fn new(acks: Acks) -> Self {
+        Self {
+            acks,
+            done: Mutex::default(),
+        }
+    }
+
+    fn complete(&self, items: Range<u64>) {
+        let mut done = self.done.lock().unwrap();
+        let (acked, after) = &mut *done;
+        after.insert(items.start, items.end);
+        while let Some(end) = after.remove(acked) {
+            *acked = end;
+        }
+        self.acks.acknowledge(*acked);
+    }
+}
+
+/// The dead-letter output of an [`HttpOutput`] without one, which fails on
+/// items it could not deliver.
+#[derive(Debug, Clone, Copy)]
+pub enum NoDeadLetter {}
+
+impl<T, E> Output<Failed<T, E>> for NoDeadLetter {
+    async fn output<S>(&self, _stream: S) -> Result<()>
+    where
+        S: Stream<Item = Failed<T, E>> + Send,
+    {
+        match *self {}
+    }
+}
+
+enum Endpoint {
+    Fixed(Url),
+    Template(Template),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HttpOutputConfig {
+    endpoint: String,
+    #[serde(default = "default_method")]
+    method: String,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default = "default_concurrency")]
+    concurrency: usize,
+    #[serde(default)]
+    order_by: Option<String>,
+    #[serde(default)]
+    batch: Option<Batching>,
+    #[serde(default)]
+    retry: RetryPolicy,
+}
+
+fn default_concurrency() -> usize {
+    1
+}
+
+fn default_method() -> String {
+    "POST".to_string()
+}
+
+impl TryFrom<HttpOutputConfig> for HttpOutput {
+    type Error = String;
+
+    fn try_from(config: HttpOutputConfig) -> Result<Self, String> {
+        Self::from_config(config).map_err(|e| match e {
+            Error::Config(msg) => msg,
+            e => e.to_string(),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for HttpOutput {
+    fn deserialize<De: serde::Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        let config = HttpOutputConfig::deserialize(deserializer)?;
+        Self::try_from(config).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Endpoint {
+    fn parse(endpoint: &str) -> Result<Self> {
+        let template =
+            Template::parse(endpoint).map_err(|e| Error::config(format!("endpoint: {e}")))?;
+        match template.literal() {
+            Some(url) => Url::parse(&url)
+                .map(Endpoint::Fixed)
+                .map_err(|e| Error::config(format!("endpoint `{endpoint}`: {e}"))),
+            None => Ok(Endpoint::Template(template)),
+        }
+    }
+}
+
 impl HttpOutput {
-    pub fn new<T: Into<reqwest::Url>>(
+    fn from_config(config: HttpOutputConfig) -> Result<Self> {
+        let endpoint = Endpoint::parse(&config.endpoint)?;
+        let method = config
+            .method
+            .parse()
+            .map_err(|e| Error::config(format!("method `{}`: {e}", config.method)))?;
+        let mut headers = HeaderMap::new();
+        let mut templates = Vec::new();
+        for (name, value) in &config.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| Error::config(format!("header `{name}`: {e}")))?;
+            let template = Template::parse(value)
+                .map_err(|e| Error::config(format!("header `{name}`: {e}")))?;
+            match template.literal() {
+                Some(value) => {
+                    let value = HeaderValue::from_str(&value)
+                        .map_err(|e| Error::config(format!("header `{name}`: {e}")))?;
+                    headers.insert(name, value);
+                }
+                None => templates.push((name, template)),
+            }
+        }
+        if config.concurrency == 0 {
+            return Err(Error::config("concurrency must be at least 1"));
+        }
+        if let Some(batch) = &config.batch {
+            batch
+                .check()
+                .map_err(|e| Error::config(format!("batch: {e}")))?;
+        }
+        config
+            .retry
+            .check()
+            .map_err(|e| Error::config(format!("retry: {e}")))?;
+        let mut output = Self::build(endpoint, method, Some(headers))?
+            .concurrency(config.concurrency)
+            .retry(config.retry);
+        if let Some(key) = &config.order_by {
+            output = output.order_by(key)?;
+        }
+        if let Some(batch) = config.batch {
+            output = output.batch(batch);
+        }
+        output.headers = templates;
+        output.check_batch()?;
+        Ok(output)
+    }
+
+    pub fn new<T: Into<Url>>(
         endpoint: T,
         method: http::method::Method,
         default_headers: Option<HeaderMap>,
-    ) -> Self {
+    ) -> Result<Self> {
+        Self::build(Endpoint::Fixed(endpoint.into()), method, default_headers)
+    }
+
+    fn build(
+        endpoint: Endpoint,
+        method: http::method::Method,
+        default_headers: Option<HeaderMap>,
+    ) -> Result<Self> {
         let client = {
             let mut client_builder = reqwest::ClientBuilder::new();
             if let Some(headers) = default_headers {
                 client_builder = client_builder.default_headers(headers);
             }
-            client_builder.build().unwrap()
+            client_builder.build()?
         };
-        Self {
-            endpoint: endpoint.into(),
+        Ok(Self {
+            endpoint,
             method,
+            headers: Vec::new(),
+            concurrency: 1,
+            order_by: None,
+            batch: None,
+            retry: RetryPolicy::default(),
+            dead_letter: None,
             client,
-        }
+        })
     }
 }
 
-impl<T: Into<Body> + Send> Output<T> for HttpOutput {
-    fn output<S>(
+impl<D> HttpOutput<D> {
+    /// Sends every item to the URL `template` fills in from its fields,
+    /// instead of the fixed endpoint.
+    pub fn endpoint_template(mut self, template: &str) -> Result<Self> {
+        self.endpoint = Endpoint::parse(template)?;
+        Ok(self)
+    }
+
+    /// Sends every item with a `name` header, whose value `template` fills in
+    /// from its fields.
+    pub fn header_template(mut self, name: HeaderName, template: &str) -> Result<Self> {
+        let template = Template::parse(template)
+            .map_err(|e| Error::config(format!("header `{name}`: {e}")))?;
+        self.headers.push((name, template));
+        Ok(self)
+    }
+
+    /// Keeps up to `concurrency` requests in flight, at least 1.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Sends items with the same key, filled in by `template` from their
+    /// fields, one after the other and in order.
+    pub fn order_by(mut self, template: &str) -> Result<Self> {
+        let template =
+            Template::parse(template).map_err(|e| Error::config(format!("order_by: {e}")))?;
+        self.order_by = Some(template);
+        Ok(self)
+    }
+
+    pub fn batch(mut self, batch: Batching) -> Self {
+        self.batch = Some(batch);
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Hands items that could not be delivered to `sink`, with the reason,
+    /// instead of failing.
+    pub fn dead_letter<D2>(self, sink: D2) -> HttpOutput<D2> {
+        HttpOutput {
+            endpoint: self.endpoint,
+            method: self.method,
+            headers: self.headers,
+            concurrency: self.concurrency,
+            order_by: self.order_by,
+            batch: self.batch,
+            retry: self.retry,
+            dead_letter: Some(sink),
+            client: self.client,
+        }
+    }
+
+    fn is_templated(&self) -> bool {
+        matches!(self.endpoint, Endpoint::Template(_))
+            || !self.headers.is_empty()
+            || self.order_by.is_some() && self.batch.is_none()
+    }
+
+    /// Fails for endpoint and header templates along with `batch`, as the
+    /// items of a batch share a request.
+    fn check_batch(&self) -> Result<()> {
+        if self.batch.is_some()
+            && (matches!(self.endpoint, Endpoint::Template(_)) || !self.headers.is_empty())
+        {
+            return Err(Error::config(
+                "endpoint and headers cannot be filled in per item with `batch`, \
+                 as the items of a batch share a request",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Prepares the request with `body`, holding `items`, filling in
+    /// templates from the fields `fields` gives, only asked for if there are
+    /// templates.
+    fn request(
         &self,
-        stream: S,
-    ) -> impl std::future::Future<Output = Result<(), std::io::Error>> + Send
+        body: Bytes,
+        items: Vec<Bytes>,
+        fields: impl FnOnce() -> Result<Value>,
+    ) -> Result<Pending> {
+        let fields = if self.is_templated() {
+            fields()?
+        } else {
+            Value::Null
+        };
+        let url = match &self.endpoint {
+            Endpoint::Fixed(url) => url.clone(),
+            Endpoint::Template(template) => {
+                let url = template.render(&fields, escape_url)?;
+                Url::parse(&url).map_err(|e| Error::codec(format!("endpoint `{url}`: {e}")))?
+            }
+        };
+        let mut request = self.client.request(self.method.clone(), url);
+        for (name, template) in &self.headers {
+            let value = template.render(&fields, verbatim)?;
+            let value = HeaderValue::from_str(&value)
+                .map_err(|e| Error::codec(format!("header `{name}`: {e}")))?;
+            request = request.header(name, value);
+        }
+        let lane = match &self.order_by {
+            Some(template) if self.batch.is_none() => {
+                let mut hasher = DefaultHasher::new();
+                template.render(&fields, verbatim)?.hash(&mut hasher);
+                hasher.finish()
+            }
+            _ => 0,
+        };
+        Ok(Pending {
+            request: request.body(body),
+            items,
+            lane,
+        })
+    }
+
+    /// Prepares the request for `batch`. Batches all go in the same lane,
+    /// whatever their keys, as items with the same key may be in any of them.
+    fn batch_request(&self, batch: Batch) -> Result<Pending> {
+        let Batch { body, items, .. } = batch;
+        self.request(body.into(), items, || {
+            self.check_batch()?;
+            Ok(Value::Null)
+        })
+    }
+}
+
+impl<D: Output<Failed<Bytes, String>> + Sync> HttpOutput<D> {
+    /// Sends the requests in `pending` with as many in flight as allowed,
+    /// stopping at the first error, and acknowledges their items in `acks`.
+    async fn send_all<S>(&self, pending: S, acks: Acks) -> Result<()>
     where
-        S: futures::Stream<Item = T> + Send,
+        S: Stream<Item = Result<Pending>> + Send,
     {
-        async move {
-            pin_mut!(stream);
-            while let Some(item) = stream.next().await {
-                loop {
-                    match self.client.post(self.endpoint.clone())
-                    .body(item.into()).send().await {
-                        Ok(response) => {
-                            let status = response.status();
-                            if status.is_success() {
-                                break;
-                            }
-                            match status {
-                                StatusCode::TOO_MANY_REQUESTS => {
-                                    tracing::warn!("[429] backoff");
-                                    break;
-                                }
-                                _ => {
-                                    tracing::error!("unexpected status: [{}], response: {:?}", status, response.text().await);
-                                    break;
-                                }
-                            }
-                        },
-                        Err(e) => {
-                            tracing::error!("error: {e:?}");
-                            break;
-                        },
-                    }
+        let completed = &Completed::new(acks);
+        let mut next = 0;
+        let pending = pending.map_ok(move |pending| {
+            let items = next..next + pending.items.len() as u64;
+            next = items.end;
+            (items, pending)
+        });
+        if self.order_by.is_none() {
+            return pending
+                .map_ok(|(items, pending)| async move {
+                    self.send(pending).await?;
+                    completed.complete(items);
+                    Ok(())
+                })
+                .try_buffer_unordered(self.concurrency)
+                .try_collect()
+                .await;
+        }
+        // every key maps to one of the lanes, each sending one request at a
+        // time; requests wait in their lane, so that a busy key does not hold
+        // up the others until `queued` runs out
+        let queued = &Semaphore::new(self.concurrency * QUEUED_PER_LANE);
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..self.concurrency)
+            .map(|_| mpsc::unbounded::<(Range<u64>, Pending, SemaphorePermit<'_>)>())
+            .unzip();
+        let lanes = future::try_join_all(receivers.into_iter().map(|mut receiver| async move {
+            while let Some((items, pending, permit)) = receiver.next().await {
+                drop(permit);
+                self.send(pending).await?;
+                completed.complete(items);
+            }
+            Ok::<_, Error>(())
+        }));
+        let dispatch = async move {
+            pin_mut!(pending);
+            while let Some(pending) = pending.next().await {
+                let (items, pending) = pending?;
+                let permit = queued
+                    .acquire()
+                    .await
+                    .expect("the semaphore is never closed");
+                let lane = (pending.lane % senders.len() as u64) as usize;
+                if senders[lane]
+                    .unbounded_send((items, pending, permit))
+                    .is_err()
+                {
+                    // the lane failed and reports why
+                    break;
                 }
             }
             Ok(())
+        };
+        futures::try_join!(dispatch, lanes).map(|_| ())
+    }
+
+    async fn send(&self, pending: Pending) -> Result<()> {
+        let Pending { request, items, .. } = pending;
+        let response = match self.retry.send(request).await {
+            Ok(response) => response,
+            Err(e) => {
+                let error = e.to_string();
+                let failed = items.into_iter().map(|item| Failed {
+                    item,
+                    error: error.clone(),
+                });
+                return self.undelivered(failed.collect(), e).await;
+            }
+        };
+        let Some(item_errors) = self.batch.as_ref().and_then(|b| b.item_errors.as_ref()) else {
+            return Ok(());
+        };
+        let rejected = item_errors.rejected(&response.bytes().await?, items.len())?;
+        let Some((_, error)) = rejected.first() else {
+            return Ok(());
+        };
+        let e = Error::Rejected {
+            rejected: rejected.len(),
+            items: items.len(),
+            error: error.clone(),
+        };
+        let failed = rejected.into_iter().map(|(i, error)| Failed {
+            item: items[i].clone(),
+            error,
+        });
+        self.undelivered(failed.collect(), e).await
+    }
+
+    /// Hands `failed` items to the dead-letter output, or fails with `e`
+    /// without one.
+    async fn undelivered(&self, failed: Vec<Failed<Bytes, String>>, e: Error) -> Result<()> {
+        let Some(sink) = &self.dead_letter else {
+            return Err(e);
+        };
+        sink.output(stream::iter(failed)).await
+    }
+}
+
+/// Items are sent as they are; with templates, they are read as JSON to fill
+/// them in.
+impl<T, D> Output<T> for HttpOutput<D>
+where
+    T: Into<Bytes> + Send,
+    D: Output<Failed<Bytes, String>> + Sync,
+{
+    async fn output<S>(&self, stream: S) -> Result<()>
+    where
+        S: futures::Stream<Item = T> + Send,
+    {
+        self.output_acked(stream, Acks::default()).await
+    }
+
+    async fn output_acked<S>(&self, stream: S, acks: Acks) -> Result<()>
+    where
+        S: futures::Stream<Item = T> + Send,
+    {
+        if let Some(batching) = &self.batch {
+            let batches = batching.batches(stream.map(Into::into), Raw);
+            let pending = batches.map(|batch| batch.and_then(|batch| self.batch_request(batch)));
+            return self.send_all(pending, acks).await;
         }
+        let pending = stream.map(|item| {
+            let item: Bytes = item.into();
+            self.request(item.clone(), vec![item.clone()], || {
+                serde_json::from_slice(&item)
+                    .map_err(|e| Error::codec(format!("cannot fill in templates, not JSON: {e}")))
+            })
+        });
+        self.send_all(pending, acks).await
     }
 }
 
+/// Every item, or every batch, is sent as a body of its own, holding a
+/// complete document.
+impl<T, E, D> Output<T> for Encode<HttpOutput<D>, E>
+where
+    T: Serialize + Send,
+    E: Encoder<T> + Clone + Send + Sync,
+    D: Output<Failed<Bytes, String>> + Sync,
+{
+    async fn output<S>(&self, stream: S) -> Result<()>
+    where
+        S: futures::Stream<Item = T> + Send,
+    {
+        self.output_acked(stream, Acks::default()).await
+    }
+
+    async fn output_acked<S>(&self, stream: S, acks: Acks) -> Result<()>
+    where
+        S: futures::Stream<Item = T> + Send,
+    {
+        let output = &self.output;
+        if let Some(batching) = &output.batch {
+            let batches = batching.batches(stream, Serialized::new(self.encoder.clone()));
+            let pending = batches.map(|batch| batch.and_then(|batch| output.batch_request(batch)));
+            return output.send_all(pending, acks).await;
+        }
+        let mut encoder = self.encoder.clone();
+        let pending = stream.map(move |item| {
+            let mut body = Vec::new();
+            encoder.encode(&item, &mut body)?;
+            encoder.finish(&mut body)?;
+            let body = Bytes::from(body);
+            output.request(body.clone(), vec![body], || {
+                serde_json::to_value(&item).map_err(Error::codec)
+            })
+        });
+        output.send_all(pending, acks).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::stream;
+    use serde_json::json;
+
+    use super::mock::{Reply, Server};
+    use super::*;
+    use crate::codec::Ndjson;
+
+    /// A dead-letter output keeping the items it is handed, with their
+    /// errors.
+    #[derive(Clone, Default)]
+    struct Collected(Arc<Mutex<Vec<(String, String)>>>);
+
+    impl Output<Failed<Bytes, String>> for Collected {
+        async fn output<S>(&self, stream: S) -> Result<()>
+        where
+            S: Stream<Item = Failed<Bytes, String>> + Send,
+        {
+            let failed: Vec<_> = stream.collect().await;
+            let mut collected = self.0.lock().unwrap();
+            for Failed { item, error } in failed {
+                collected.push((String::from_utf8(item.to_vec()).unwrap(), error));
+            }
+            Ok(())
+        }
+    }
+
+    fn output(server: &Server) -> HttpOutput {
+        let url = Url::parse(&server.url("/")).unwrap();
+        HttpOutput::new(url, http::Method::POST, None)
+            .unwrap()
+            .retry(RetryPolicy::none())
+    }
+
+    fn items(items: &[&'static str]) -> impl Stream<Item = Bytes> + Send {
+        stream::iter(items.iter().map(|item| Bytes::from_static(item.as_bytes())))
+    }
+
+    #[tokio::test]
+    async fn fills_in_endpoints_and_headers_per_item() {
+        let server = Server::start(|_, _| Reply::ok("")).await;
+        let output = output(&server)
+            .endpoint_template(&server.url("/users/{user.id}/events?kind={kind}"))
+            .unwrap()
+            .header_template(HeaderName::from_static("x-kind"), "{kind}")
+            .unwrap();
+        let output = HttpOutput {
+            method: http::Method::PUT,
+            ..output
+        };
+        let events = [
+            r#"{"user": {"id": "a b"}, "kind": "click"}"#,
+            r#"{"user": {"id": 7}, "kind": "view"}"#,
+        ];
+        output.output(items(&events)).await.unwrap();
+
+        let requests = server.requests();
+        let sent: Vec<_> = requests
+            .iter()
+            .map(|r| {
+                (
+                    r.method.as_str(),
+                    r.path.as_str(),
+                    r.header("x-kind").unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            sent,
+            [
+                ("PUT", "/users/a%20b/events?kind=click", "click"),
+                ("PUT", "/users/7/events?kind=view", "view"),
+            ]
+        );
+        assert_eq!(requests[1].text(), events[1]);
+    }
+
+    /// The lane `key` goes in among `lanes`.
+    fn lane(key: &str, lanes: u64) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish() % lanes
+    }
+
+    #[tokio::test]
+    async fn keeps_items_in_order_by_key() {
+        let server = Server::start(|n, _| {
+            Reply::ok("").delay(std::time::Duration::from_millis(20 - n as u64 % 20))
+        })
+        .await;
+        let output = output(&server).concurrency(3).order_by("{key}").unwrap();
+        let events: Vec<_> = (0..20)
+            .map(|n| format!(r#"{{"key": "k{}", "n": {n}}}"#, n % 4))
+            .collect();
+        let acks = Acks::default();
+        let stream = stream::iter(events.clone()).map(Bytes::from);
+        output.output_acked(stream, acks.clone()).await.unwrap();
+
+        assert_eq!(acks.count(), 20);
+        let requests = server.requests();
+        for key in 0..4 {
+            let sent: Vec<_> = requests
+                .iter()
+                .map(|request| request.text())
+                .filter(|text| text.contains(&format!("\"k{key}\"")))
+                .collect();
+            let expected: Vec<_> = events.iter().skip(key).step_by(4).cloned().collect();
+            assert_eq!(sent, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn busy_keys_do_not_hold_up_other_keys() {
+        let slow = std::time::Duration::from_millis(200);
+        let server = Server::start(move |_, request| {
+            if request.text().contains("\"slow\"") {
+                Reply::ok("").delay(slow)
+            } else {
+                Reply::ok("")
+            }
+        })
+        .await;
+        let fast = ["fast", "other", "another", "yet another"]
+            .into_iter()
+            .find(|key| lane(key, 2) != lane("slow", 2))
+            .unwrap();
+        let output = output(&server).concurrency(2).order_by("{key}").unwrap();
+        let events: Vec<_> = ["slow", "slow", "slow", fast]
+            .into_iter()
+            .map(|key| Bytes::from(format!(r#"{{"key": "{key}"}}"#)))
+            .collect();
+        output.output(stream::iter(events)).await.unwrap();
+
+        let requests = server.requests();
+        let started = requests[0].at;
+        let fast = requests
+            .iter()
+            .find(|request| !request.text().contains("\"slow\""))
+            .unwrap();
+        assert!(fast.at - started < slow, "{:?}", fast.at - started);
+        let slow_at: Vec<_> = requests
+            .iter()
+            .filter(|request| request.text().contains("\"slow\""))
+            .map(|request| request.at - started)
+            .collect();
+        assert!(slow_at[2] >= slow * 2, "{slow_at:?}");
+    }
+
+    #[tokio::test]
+    async fn refuses_to_fill_in_batch_requests_per_item() {
+        let config = json!({
+            "endpoint": "http://localhost/{index}/_bulk",
+            "batch": {"max_items": 10},
+        });
+        let error = serde_json::from_value::<HttpOutput>(config).err().unwrap();
+        assert!(error.to_string().contains("with `batch`"), "{error}");
+        let config = json!({
+            "endpoint": "http://localhost/_bulk",
+            "headers": {"x-index": "{index}"},
+            "batch": {},
+        });
+        assert!(serde_json::from_value::<HttpOutput>(config).is_err());
+        let config = json!({
+            "endpoint": "http://localhost/_bulk",
+            "headers": {"content-type": "application/x-ndjson"},
+            "order_by": "{index}",
+            "batch": {},
+        });
+        serde_json::from_value::<HttpOutput>(config).unwrap();
+
+        let server = Server::start(|_, _| Reply::ok("")).await;
+        let output = output(&server)
+            .endpoint_template(&server.url("/{index}"))
+            .unwrap()
+            .batch(Batching::default());
+        let error = output.output(items(&[r#"{"index": "a"}"#])).await;
+        assert!(matches!(error, Err(Error::Config(_))));
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn sends_batches_ordered_by_key_one_at_a_time() {
+        let server = Server::start(|n, _| {
+            Reply::ok("").delay(std::time::Duration::from_millis(30 - 10 * n as u64 % 30))
+        })
+        .await;
+        let batching = Batching {
+            max_items: 2,
+            linger: None,
+            body: BatchBody::JsonArray,
+            ..Batching::default()
+        };
+        let output = Encode::new(
+            output(&server)
+                .concurrency(4)
+                .order_by("{key}")
+                .unwrap()
+                .batch(batching),
+            Ndjson::<Value>::new(),
+        );
+        let records: Vec<_> = (0..6).map(|n| json!({"key": n % 3, "n": n})).collect();
+        output.output(stream::iter(records)).await.unwrap();
+
+        let sent: Vec<_> = server
+            .requests()
+            .iter()
+            .map(|request| serde_json::from_slice::<Value>(&request.body).unwrap())
+            .collect();
+        let expected: Vec<_> = (0..3)
+            .map(|batch| {
+                json!([
+                    {"key": 2 * batch % 3, "n": 2 * batch},
+                    {"key": (2 * batch + 1) % 3, "n": 2 * batch + 1},
+                ])
+            })
+            .collect();
+        assert_eq!(sent, expected);
+    }
+
+    #[tokio::test]
+    async fn fails_on_undelivered_items_without_a_dead_letter_output() {
+        let server = Server::start(|n, _| match n {
+            1 => Reply::status(500).body("down"),
+            _ => Reply::ok(""),
+        })
+        .await;
+        let error = output(&server)
+            .output(items(&["a", "b", "c"]))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::Status { ref body, .. } if body == "down"));
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn hands_undelivered_items_to_the_dead_letter_output() {
+        let server = Server::start(|n, _| match n {
+            1 => Reply::status(500).body("down"),
+            _ => Reply::ok(""),
+        })
+        .await;
+        let collected = Collected::default();
+        let acks = Acks::default();
+        output(&server)
+            .dead_letter(collected.clone())
+            .output_acked(items(&["a", "b", "c"]), acks.clone())
+            .await
+            .unwrap();
+        assert_eq!(server.requests().len(), 3);
+        let collected = collected.0.lock().unwrap();
+        assert_eq!(collected.len(), 1);
+        assert_eq!(collected[0].0, "b");
+        assert!(collected[0].1.contains("down"), "{}", collected[0].1);
+        assert_eq!(acks.count(), 3);
+    }
+
+    #[tokio::test]
+    async fn hands_rejected_items_of_batches_over_with_their_errors() {
+        let server = Server::start(|_, _| {
+            Reply::ok(r#"{"items": [{"error": null}, {"error": "mapping"}, {}]}"#)
+        })
+        .await;
+        let batching = Batching {
+            body: BatchBody::Ndjson,
+            item_errors: Some(ItemErrors {
+                results: Some("items".to_string()),
+                error: "error".to_string(),
+            }),
+            ..Batching::default()
+        };
+        let collected = Collected::default();
+        let output = Encode::new(
+            output(&server)
+                .batch(batching)
+                .dead_letter(collected.clone()),
+            Ndjson::<Value>::new(),
+        );
+        let records = [json!({"id": 1}), json!({"id": 2}), json!({"id": 3})];
+        output.output(stream::iter(records)).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].text(), "{\"id\":1}\n{\"id\":2}\n{\"id\":3}\n");
+        let collected = collected.0.lock().unwrap();
+        assert_eq!(
+            *collected,
+            [(r#"{"id":2}"#.to_string(), "mapping".to_string())]
+        );
+    }
+
+    #[test]
+    fn acknowledges_items_once_everything_before_them_completed() {
+        let acks = Acks::default();
+        let completed = Completed::new(acks.clone());
+        completed.complete(2..5);
+        assert_eq!(acks.count(), 0);
+        completed.complete(0..1);
+        assert_eq!(acks.count(), 1);
+        completed.complete(1..2);
+        assert_eq!(acks.count(), 5);
+    }
+}
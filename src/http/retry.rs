@@ -0,0 +1,399 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use http::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response};
+use serde::Deserialize;
+
+use super::input::Pacer;
+use crate::{Error, Result};
+
+/// When and how often a failed HTTP request is tried again.
+///
+/// Requests are retried after transport errors, such as refused connections
+/// and timeouts, and after responses with one of `statuses`. The wait before
+/// every retry doubles (by `multiplier`) from `initial_backoff` up to
+/// `max_backoff`, shortened by a random fraction of up to `jitter`, unless
+/// the response says how long to wait in a `Retry-After` header, which is
+/// waited for up to `max_retry_after`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Attempts per request, the first one included; 1 never retries.
+    max_attempts: u32,
+    /// The wait before the first retry.
+    #[serde(with = "humantime_serde")]
+    initial_backoff: Duration,
+    /// The longest wait between two attempts, unless `Retry-After` asks for
+    /// more.
+    #[serde(with = "humantime_serde")]
+    max_backoff: Duration,
+    /// What the wait is multiplied by after every retry.
+    multiplier: f64,
+    /// The largest fraction, between 0 and 1, that waits are randomly
+    /// shortened by, so that clients failing together do not retry together.
+    jitter: f64,
+    /// Give up once retrying would go on past this long after the first
+    /// attempt.
+    #[serde(with = "humantime_serde")]
+    max_elapsed: Option<Duration>,
+    /// The longest wait a `Retry-After` header is followed for.
+    #[serde(with = "humantime_serde")]
+    max_retry_after: Duration,
+    /// Response statuses worth retrying.
+    statuses: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_elapsed: None,
+            max_retry_after: Duration::from_secs(300),
+            statuses: vec![408, 425, 429, 500, 502, 503, 504],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that makes a single attempt.
+    pub fn none() -> Self {
+        Self::default().max_attempts(1)
+    }
+
+    /// Makes up to `max_attempts` attempts per request, at least 1.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Multiplies the wait by `multiplier` after every retry, at least 1.
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = if multiplier >= 1.0 { multiplier } else { 1.0 };
+        self
+    }
+
+    /// Shortens waits by a random fraction of up to `jitter`, between 0 and
+    /// 1.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = if jitter.is_nan() {
+            0.0
+        } else {
+            jitter.clamp(0.0, 1.0)
+        };
+        self
+    }
+
+    pub fn max_elapsed(mut self, max_elapsed: Duration) -> Self {
+        self.max_elapsed = Some(max_elapsed);
+        self
+    }
+
+    pub fn max_retry_after(mut self, max_retry_after: Duration) -> Self {
+        self.max_retry_after = max_retry_after;
+        self
+    }
+
+    pub fn statuses(mut self, statuses: impl IntoIterator<Item = u16>) -> Self {
+        self.statuses = statuses.into_iter().collect();
+        self
+    }
+
+    pub(crate) fn check(&self) -> Result<(), String> {
+        if self.max_attempts == 0 {
+            return Err("max_attempts must be at least 1".to_string());
+        }
+        if !(1.0..).contains(&self.multiplier) {
+            return Err(format!(
+                "multiplier must be at least 1, not {}",
+                self.multiplier
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(format!(
+                "jitter must be between 0 and 1, not {}",
+                self.jitter
+            ));
+        }
+        Ok(())
+    }
+
+    /// The wait before retry number `retry`, counting from 1.
+    fn backoff(&self, retry: u32) -> Duration {
+        let exponent = i32::try_from(retry.saturating_sub(1)).unwrap_or(i32::MAX);
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+        let backoff = backoff * (1.0 - self.jitter * fastrand::f64());
+        Duration::try_from_secs_f64(backoff)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+
+    /// The wait a response asks for with `retry_after`, up to
+    /// `max_retry_after`, or else the backoff before retry number `retry`.
+    fn wait(&self, retry: u32, retry_after: Option<Duration>) -> Duration {
+        match retry_after {
+            Some(wait) => wait.min(self.max_retry_after),
+            None => self.backoff(retry),
+        }
+    }
+
+    /// Sends `request` until it gets a successful response, fails in a way
+    /// not worth retrying, or runs out of attempts or time, returning the
+    /// last error then.
+    ///
+    /// Requests with a streaming body cannot be repeated and get a single
+    /// attempt.
+    pub(crate) async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        self.send_paced(request, &mut Pacer::default()).await
+    }
+
+    /// Like [`send`](Self::send), with every attempt waiting for `pacer`.
+    pub(crate) async fn send_paced(
+        &self,
+        mut request: RequestBuilder,
+        pacer: &mut Pacer,
+    ) -> Result<Response> {
+        let start = Instant::now();
+        let mut attempt = 0;
+        loop {
+            pacer.wait().await;
+            attempt += 1;
+            let next = if attempt < self.max_attempts {
+                request.try_clone()
+            } else {
+                None
+            };
+            let (error, retry_after) = match request.send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) => {
+                    let status = response.status();
+                    let retry_after = retry_after(&response);
+                    let body = response.text().await.unwrap_or_default();
+                    let error = Error::Status { status, body };
+                    if !self.statuses.contains(&status.as_u16()) {
+                        return Err(error);
+                    }
+                    (error, retry_after)
+                }
+                Err(e) if is_retryable(&e) => (Error::Transport(e), None),
+                Err(e) => return Err(Error::Transport(e)),
+            };
+            let wait = self.wait(attempt, retry_after);
+            let in_time = self.max_elapsed.is_none_or(|max| {
+                start
+                    .elapsed()
+                    .checked_add(wait)
+                    .is_some_and(|elapsed| elapsed <= max)
+            });
+            let Some(next) = next.filter(|_| in_time) else {
+                if attempt > 1 {
+                    tracing::warn!("giving up after {attempt} attempts: {error}");
+                }
+                return Err(error);
+            };
+            tracing::warn!("attempt {attempt} failed, retrying in {wait:?}: {error}");
+            tokio::time::sleep(wait).await;
+            request = next;
+        }
+    }
+}
+
+/// Whether a request failed on the way, rather than for being malformed.
+fn is_retryable(e: &reqwest::Error) -> bool {
+    e.is_timeout() || e.is_connect() || e.is_request()
+}
+
+/// How long a response asks to wait before retrying, given in seconds or
+/// as a date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+
+    use super::super::mock::{Reply, Server};
+    use super::*;
+
+    fn steady() -> RetryPolicy {
+        RetryPolicy::default()
+            .initial_backoff(Duration::from_millis(10))
+            .max_backoff(Duration::from_millis(50))
+            .jitter(0.0)
+    }
+
+    #[test]
+    fn backoff_grows_up_to_the_maximum() {
+        let waits: Vec<_> = (1..=5).map(|retry| steady().backoff(retry)).collect();
+        let ms = Duration::from_millis;
+        assert_eq!(waits, [ms(10), ms(20), ms(40), ms(50), ms(50)]);
+    }
+
+    #[test]
+    fn jitter_only_shortens_waits() {
+        let policy = steady().jitter(1.0);
+        for _ in 0..100 {
+            assert!(policy.backoff(2) <= Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn backoff_never_overflows() {
+        let policy: RetryPolicy = serde_json::from_value(serde_json::json!({
+            "initial_backoff": "100years",
+            "multiplier": f64::MAX,
+            "jitter": 0.0,
+        }))
+        .unwrap();
+        policy.check().unwrap();
+        let huge = policy.clone().max_backoff(Duration::MAX);
+        for retry in [1, 2, 64, u32::MAX] {
+            assert_eq!(policy.backoff(retry), Duration::from_secs(30));
+            assert!(huge.backoff(retry) >= Duration::from_secs(3_000_000_000));
+        }
+    }
+
+    #[test]
+    fn builders_clamp_what_config_rejects() {
+        let policy = RetryPolicy::default()
+            .max_attempts(0)
+            .multiplier(0.5)
+            .jitter(2.0);
+        policy.check().unwrap();
+        assert_eq!(policy.max_attempts, 1);
+        assert_eq!(policy.multiplier, 1.0);
+        assert_eq!(policy.jitter, 1.0);
+        let policy = RetryPolicy::default().multiplier(f64::NAN).jitter(f64::NAN);
+        policy.check().unwrap();
+        assert_eq!((policy.multiplier, policy.jitter), (1.0, 0.0));
+
+        for (options, error) in [
+            (serde_json::json!({"max_attempts": 0}), "max_attempts"),
+            (serde_json::json!({"multiplier": 0.5}), "multiplier"),
+            (serde_json::json!({"jitter": -0.1}), "jitter"),
+        ] {
+            let policy: RetryPolicy = serde_json::from_value(options).unwrap();
+            assert!(policy.check().unwrap_err().contains(error));
+        }
+    }
+
+    #[test]
+    fn retry_after_is_read_and_capped() {
+        let response = |value: &str| {
+            Response::from(
+                http::Response::builder()
+                    .status(503)
+                    .header(RETRY_AFTER, value)
+                    .body("")
+                    .unwrap(),
+            )
+        };
+        assert_eq!(
+            retry_after(&response("120")),
+            Some(Duration::from_secs(120))
+        );
+        let date = (Utc::now() + chrono::Duration::seconds(60)).to_rfc2822();
+        let wait = retry_after(&response(&date)).unwrap();
+        assert!(wait > Duration::from_secs(50) && wait <= Duration::from_secs(60));
+        let past = (Utc::now() - chrono::Duration::seconds(60)).to_rfc2822();
+        assert_eq!(retry_after(&response(&past)), Some(Duration::ZERO));
+        assert_eq!(retry_after(&response("soon")), None);
+
+        let policy = steady().max_retry_after(Duration::from_secs(5));
+        assert_eq!(
+            policy.wait(1, Some(Duration::from_secs(3600))),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            policy.wait(1, Some(Duration::from_secs(1))),
+            Duration::from_secs(1)
+        );
+        assert_eq!(policy.wait(1, None), Duration::from_millis(10));
+    }
+
+    #[tokio::test]
+    async fn retries_until_a_response_succeeds() {
+        let server = Server::start(|n, _| match n {
+            0 => Reply::status(503),
+            1 => Reply::status(429).header("retry-after", "3600"),
+            _ => Reply::ok("done"),
+        })
+        .await;
+        let policy = steady().max_retry_after(Duration::from_millis(10));
+        let request = reqwest::Client::new().post(server.url("/")).body("item");
+        let response = policy.send(request).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "done");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests.iter().all(|request| request.text() == "item"));
+        assert!(requests[1].at - requests[0].at >= Duration::from_millis(10));
+        assert!(requests[2].at - requests[1].at < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_other_statuses_and_after_the_last_attempt() {
+        let server = Server::start(|n, _| match n {
+            0 => Reply::status(400).body("bad"),
+            _ => Reply::status(500).body("down"),
+        })
+        .await;
+        let client = reqwest::Client::new();
+        let error = steady()
+            .send(client.get(server.url("/")))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Status { status: StatusCode::BAD_REQUEST, ref body } if body == "bad"
+        ));
+        assert_eq!(server.requests().len(), 1);
+
+        let policy = steady().max_attempts(3);
+        let error = policy.send(client.get(server.url("/"))).await.unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Status {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                ..
+            }
+        ));
+        assert_eq!(server.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn gives_up_once_out_of_time() {
+        let server = Server::start(|_, _| Reply::status(503)).await;
+        let policy = steady()
+            .initial_backoff(Duration::from_secs(60))
+            .max_backoff(Duration::from_secs(60))
+            .max_elapsed(Duration::from_secs(1));
+        let request = reqwest::Client::new().get(server.url("/"));
+        assert!(policy.send(request).await.is_err());
+        assert_eq!(server.requests().len(), 1);
+    }
+}
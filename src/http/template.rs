@@ -0,0 +1,251 @@
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::{Error, Result};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    /// A field of the item, by its dotted path.
+    Field(Vec<String>),
+}
+
+/// Text with `{field}` placeholders, filled in from the fields of an item.
+///
+/// Nested fields go by their dotted path, e.g. `{user.id}`, and array
+/// elements by index, e.g. `{tags.0}`. `{{` and `}}` stand for literal
+/// braces.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub(crate) fn parse(source: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = source.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format!("unclosed `{{` in `{source}`"))?;
+                    let path = rest[..end].trim();
+                    if path.split('.').any(str::is_empty) || path.contains('{') {
+                        return Err(format!("invalid placeholder `{{{path}}}` in `{source}`"));
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field(path.split('.').map(String::from).collect()));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(format!("unmatched `}}` in `{source}`, use `}}}}`")),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            parts,
+        })
+    }
+
+    /// The text of a template without placeholders.
+    pub(crate) fn literal(&self) -> Option<String> {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => Some(text.as_str()),
+                Part::Field(_) => None,
+            })
+            .collect()
+    }
+
+    /// Fills in the placeholders from the fields of `item`, passing their
+    /// values through `escape`.
+    pub(crate) fn render(
+        &self,
+        item: &Value,
+        escape: impl Fn(&str) -> Result<String>,
+    ) -> Result<String> {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => rendered.push_str(text),
+                Part::Field(path) => {
+                    let value = path
+                        .iter()
+                        .try_fold(item, |value, key| match value {
+                            Value::Object(fields) => fields.get(key),
+                            Value::Array(items) => {
+                                key.parse().ok().and_then(|i: usize| items.get(i))
+                            }
+                            _ => None,
+                        })
+                        .filter(|value| !value.is_null())
+                        .ok_or_else(|| {
+                            Error::codec(format!(
+                                "no field `{}` to fill in `{}`",
+                                path.join("."),
+                                self.source
+                            ))
+                        })?;
+                    match value {
+                        Value::String(s) => rendered.push_str(&escape(s)?),
+                        value => rendered.push_str(&escape(&value.to_string())?),
+                    }
+                }
+            }
+        }
+        Ok(rendered)
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// The first non-null value at the dotted `path` within `value`, where `*`
+/// stands for any field or element. An empty path stands for `value` itself.
+pub(crate) fn find<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let path: Vec<_> = path.split('.').filter(|key| !key.is_empty()).collect();
+    find_at(value, &path)
+}
+
+fn find_at<'a>(value: &'a Value, path: &[&str]) -> Option<&'a Value> {
+    let Some((key, rest)) = path.split_first() else {
+        return Some(value).filter(|value| !value.is_null());
+    };
+    match (value, *key) {
+        (Value::Object(fields), "*") => fields.values().find_map(|value| find_at(value, rest)),
+        (Value::Array(items), "*") => items.iter().find_map(|value| find_at(value, rest)),
+        (Value::Object(fields), key) => find_at(fields.get(key)?, rest),
+        (Value::Array(items), key) => find_at(items.get(key.parse::<usize>().ok()?)?, rest),
+        _ => None,
+    }
+}
+
+/// Percent-encodes all but the unreserved characters of URLs, so that a
+/// value stays within the path segment or query parameter it fills in.
+///
+/// `.` and `..` are refused, as URLs resolve them as the current and parent
+/// path segments even when percent-encoded.
+pub(crate) fn escape_url(value: &str) -> Result<String> {
+    if matches!(value, "." | "..") {
+        return Err(Error::codec(format!(
+            "`{value}` cannot be filled into a URL, as it would stand for a relative path"
+        )));
+    }
+    let mut escaped = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    Ok(escaped)
+}
+
+/// Fills in values as they are, for headers and ordering keys.
+pub(crate) fn verbatim(value: &str) -> Result<String> {
+    Ok(value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+    use serde_json::json;
+
+    use super::*;
+
+    fn render(source: &str, item: &Value) -> Result<String> {
+        Template::parse(source).unwrap().render(item, escape_url)
+    }
+
+    #[test]
+    fn fills_in_nested_fields_and_elements() {
+        let item = json!({"user": {"id": 7, "name": "ada"}, "tags": ["a", "b"]});
+        let rendered = render("/users/{user.id}/{ user.name }?tag={tags.1}", &item).unwrap();
+        assert_eq!(rendered, "/users/7/ada?tag=b");
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        let template = Template::parse("{{a}}/{b}").unwrap();
+        let rendered = template.render(&json!({"b": 1}), verbatim).unwrap();
+        assert_eq!(rendered, "{a}/1");
+        assert_eq!(
+            Template::parse("{{x}}").unwrap().literal().as_deref(),
+            Some("{x}")
+        );
+        assert_eq!(template.literal(), None);
+    }
+
+    #[test]
+    fn rejects_malformed_placeholders() {
+        assert!(Template::parse("/{id").unwrap_err().contains("unclosed"));
+        assert!(Template::parse("/id}").unwrap_err().contains("unmatched"));
+        assert!(Template::parse("/{}").unwrap_err().contains("invalid"));
+        assert!(Template::parse("/{a..b}").unwrap_err().contains("invalid"));
+    }
+
+    #[test]
+    fn missing_and_null_fields_are_errors() {
+        let item = json!({"a": null, "b": [1]});
+        for source in ["{a}", "{c}", "{b.1}", "{b.x}", "{b.0.c}"] {
+            let error = render(source, &item).unwrap_err().to_string();
+            assert!(error.contains("no field"), "{source}: {error}");
+        }
+    }
+
+    #[test]
+    fn escapes_values_within_their_segment() {
+        let item = json!({"id": "a/b?c=d&e#f g", "n": 1.5, "ok": true});
+        assert_eq!(render("{id}", &item).unwrap(), "a%2Fb%3Fc%3Dd%26e%23f%20g");
+        assert_eq!(render("{n}-{ok}", &item).unwrap(), "1.5-true");
+        assert_eq!(escape_url("caf\u{e9}~._-").unwrap(), "caf%C3%A9~._-");
+    }
+
+    #[test]
+    fn refuses_dot_only_values() {
+        for id in [".", ".."] {
+            let error = render("http://example.com/users/{id}/posts", &json!({"id": id}));
+            assert!(
+                error.unwrap_err().to_string().contains("relative path"),
+                "{id}"
+            );
+        }
+        for id in ["...", "..a", ".a", ""] {
+            let rendered = render("http://example.com/users/{id}/posts", &json!({"id": id}));
+            let url = Url::parse(&rendered.unwrap()).unwrap();
+            assert_eq!(url.path_segments().unwrap().count(), 3, "{url}");
+        }
+    }
+
+    #[test]
+    fn finds_values_by_path_with_wildcards() {
+        let value = json!({"data": [{"id": null}, {"id": 2}], "next": {"url": "u"}});
+        assert_eq!(find(&value, "data.*.id"), Some(&json!(2)));
+        assert_eq!(find(&value, "*.url"), Some(&json!("u")));
+        assert_eq!(find(&value, "data.0.id"), None);
+        assert_eq!(find(&value, ""), Some(&value));
+    }
+}
@@ -0,0 +1,154 @@
+use std::path::PathBuf;
+
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::batch::RecordBatch;
+use crate::codec::{ArrowIpc, Decode, Encode, IpcFormat};
+use crate::{Acks, Result, Stdin, Stdout, fio};
+
+/// Reads Arrow IPC record batches from `path`, or from stdin without one.
+///
+/// Both the stream and the file (Feather v2) format are read. Like for
+/// [`fio::Input`], `path` may also name a directory or a glob pattern.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Input {
+    #[serde(default)]
+    path: Option<PathBuf>,
+}
+
+impl Input {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: Some(path.into()),
+        }
+    }
+
+    pub fn stdin() -> Self {
+        Self { path: None }
+    }
+}
+
+impl crate::Input<RecordBatch> for Input {
+    fn into_stream(self) -> impl Stream<Item = Result<RecordBatch>> + Send {
+        match self.path {
+            Some(path) => Decode::new(fio::Input::new(path), ArrowIpc::default())
+                .into_stream()
+                .left_stream(),
+            None => Decode::new(Stdin::new(), ArrowIpc::default())
+                .into_stream()
+                .right_stream(),
+        }
+    }
+}
+
+/// Writes Arrow IPC record batches to `path`, replacing it, or to stdout
+/// without one.
+///
+/// `format` picks the stream format, which suits pipes, or the file format
+/// (Feather v2). The schema is taken from the first batch, and nothing is
+/// written for an empty stream.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Output {
+    #[serde(default)]
+    path: Option<PathBuf>,
+    #[serde(default)]
+    format: IpcFormat,
+}
+
+impl Output {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: Some(path.into()),
+            format: IpcFormat::default(),
+        }
+    }
+
+    pub fn stdout() -> Self {
+        Self::default()
+    }
+
+    pub fn format(mut self, format: IpcFormat) -> Self {
+        self.format = format;
+        self
+    }
+}
+
+impl crate::Output<RecordBatch> for Output {
+    async fn output<S>(&self, stream: S) -> Result<()>
+    where
+        S: Stream<Item = RecordBatch> + Send,
+    {
+        let encoder = ArrowIpc::new(self.format);
+        match &self.path {
+            Some(path) => {
+                fio::Output::new(path)
+                    .write(stream, encoder, |_| Ok(Value::Null), Acks::default())
+                    .await
+            }
+            None => Encode::new(Stdout::new(), encoder).output(stream).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::Int64Array;
+    use arrow::datatypes::{DataType, Field, Schema};
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::Output as _;
+
+    fn batch(values: Vec<i64>) -> RecordBatch {
+        let schema = Schema::new(vec![Field::new("n", DataType::Int64, false)]);
+        RecordBatch::try_new(Arc::new(schema), vec![Arc::new(Int64Array::from(values))]).unwrap()
+    }
+
+    async fn round_trip(format: IpcFormat) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("batches.arrow");
+        let batches = vec![batch(vec![1, 2]), batch(vec![3])];
+        Output::new(&path)
+            .format(format)
+            .output(futures::stream::iter(batches.clone()))
+            .await
+            .unwrap();
+        let read: Vec<_> = crate::Input::into_stream(Input::new(&path))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(read, batches);
+    }
+
+    #[tokio::test]
+    async fn round_trips_streams_through_files() {
+        round_trip(IpcFormat::Stream).await;
+    }
+
+    #[tokio::test]
+    async fn round_trips_the_file_format() {
+        round_trip(IpcFormat::File).await;
+    }
+
+    #[tokio::test]
+    async fn reads_every_file_of_a_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, values) in [("a.arrow", vec![1]), ("b.arrow", vec![2, 3])] {
+            Output::new(dir.path().join(name))
+                .output(futures::stream::iter([batch(values)]))
+                .await
+                .unwrap();
+        }
+        let read: Vec<_> = crate::Input::into_stream(Input::new(dir.path()))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(read, [batch(vec![1]), batch(vec![2, 3])]);
+    }
+}
@@ -1,26 +1,102 @@
+#[cfg(feature = "arrow")]
+pub mod batch;
+mod checkpoint;
+pub mod codec;
+mod config;
 mod console;
-mod fio;
+mod dead_letter;
+mod error;
+pub mod fio;
 mod http;
+#[cfg(feature = "arrow")]
+pub mod ipc;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+mod pipeline;
+mod registry;
 use futures::Stream;
-use std::io::Error;
 
+#[cfg(feature = "sqlite")]
+pub use checkpoint::SqliteStore;
+pub use checkpoint::{Acks, Checkpoint, CheckpointStore, Checkpoints, FileId, FileStore};
+pub use config::{ComponentConfig, Format, PipelineConfig};
 pub use console::{Stdin, Stdout};
+pub use dead_letter::{DeadLetter, ErrorPolicy, Failed, FailedItems};
+pub use error::{BoxError, Error, Result};
+pub use http::{
+    BatchBody, Batching, HttpInput, HttpOutput, ItemErrors, NoDeadLetter, Pagination, RateLimit,
+    Repoll, RetryPolicy,
+};
+pub use pipeline::{Chain, Identity, Pipeline, Summary};
+pub use registry::{
+    ConfiguredPipeline, DynInput, DynOutput, DynProcess, FromRecord, Record, Registry,
+};
 
 pub trait Input<T> {
-    fn into_stream(self) -> impl Stream<Item = T>;
+    fn into_stream(self) -> impl Stream<Item = Result<T>> + Send;
+
+    /// Where the input stages its read positions, if it tracks them.
+    ///
+    /// A pipeline commits the staged positions only once its output has
+    /// acknowledged the items read up to them, or has accepted every item,
+    /// so an input resuming from them never skips data.
+    fn checkpoints(&self) -> Option<Checkpoints> {
+        None
+    }
 }
 
 pub trait Process<T, U> {
     fn process<S>(
         &self,
         stream: S,
-    ) -> impl std::future::Future<Output = impl Stream<Item = U>> + Send
+    ) -> impl std::future::Future<Output = impl Stream<Item = Result<U>> + Send> + Send
+    where
+        S: Stream<Item = T> + Send;
+
+    /// Whether the stage holds on to items it was given while passing on
+    /// later ones, as a [`DeadLetter`] does with failed items until its
+    /// dead-letter output has written them.
+    ///
+    /// Checkpoints committed past such items would lose them on a restart,
+    /// so pipelines refuse to run these stages after inputs that track
+    /// checkpoints.
+    fn holds_items(&self) -> bool {
+        false
+    }
+}
+
+/// A process stage that can fail on individual items without failing the
+/// whole stream. Wrap it in a [`DeadLetter`] to use it as a [`Process`].
+pub trait TryProcess<T, U, E> {
+    fn try_process<S>(
+        &self,
+        stream: S,
+    ) -> impl std::future::Future<
+        Output = impl Stream<Item = std::result::Result<U, Failed<T, E>>> + Send,
+    > + Send
     where
         S: Stream<Item = T> + Send;
 }
 
 pub trait Output<T> {
-    fn output<S>(&self, stream: S) -> impl std::future::Future<Output = Result<(), Error>> + Send
+    fn output<S>(&self, stream: S) -> impl std::future::Future<Output = Result<()>> + Send
     where
         S: Stream<Item = T> + Send;
+
+    /// Like [`output`](Self::output), but acknowledges items in `acks` once
+    /// they are safely written, so that a pipeline can commit checkpoints
+    /// while it runs.
+    ///
+    /// By default nothing is acknowledged before the stream ends.
+    fn output_acked<S>(
+        &self,
+        stream: S,
+        acks: Acks,
+    ) -> impl std::future::Future<Output = Result<()>> + Send
+    where
+        S: Stream<Item = T> + Send,
+    {
+        let _ = acks;
+        self.output(stream)
+    }
 }
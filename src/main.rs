@@ -1,3 +1,185 @@
-fn main() {
-    println!("Hello, world!");
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use data_proc::codec::Codec;
+use data_proc::{ConfiguredPipeline, PipelineConfig, Registry};
+use tracing_subscriber::EnvFilter;
+
+/// Exit code when the pipeline ran but stopped with an error.
+const EXIT_PIPELINE_FAILED: u8 = 1;
+/// Exit code when the config could not be loaded or instantiated.
+const EXIT_INVALID_CONFIG: u8 = 3;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the pipeline described by a config file
+    Run { config: PathBuf },
+    /// Check that a config file describes a valid pipeline, without running it
+    Validate { config: PathBuf },
+    /// List the input, process and output types that configs can refer to
+    ListComponents,
+}
+
+fn load(registry: &Registry, path: &Path) -> data_proc::Result<ConfiguredPipeline> {
+    registry.build(&PipelineConfig::from_path(path)?)
+}
+
+/// Carries out `command` and returns the code to exit with. Pipelines stop
+/// reading once `shutdown` completes.
+async fn execute(
+    command: Command,
+    registry: &Registry,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> u8 {
+    match command {
+        Command::Run { config } => {
+            let pipeline = match load(registry, &config) {
+                Ok(pipeline) => pipeline,
+                Err(e) => {
+                    eprintln!("{}: {e}", config.display());
+                    return EXIT_INVALID_CONFIG;
+                }
+            };
+            let summary = pipeline.run_until(shutdown).await;
+            tracing::info!(
+                items_in = summary.items_in,
+                items_out = summary.items_out,
+                elapsed = ?summary.elapsed,
+                "pipeline finished"
+            );
+            match summary.error {
+                Some(e) => {
+                    eprintln!("pipeline failed: {e}");
+                    EXIT_PIPELINE_FAILED
+                }
+                None => 0,
+            }
+        }
+        Command::Validate { config } => match load(registry, &config) {
+            Ok(_) => {
+                println!("{}: ok", config.display());
+                0
+            }
+            Err(e) => {
+                eprintln!("{}: {e}", config.display());
+                EXIT_INVALID_CONFIG
+            }
+        },
+        Command::ListComponents => {
+            for (role, kinds) in [
+                ("inputs", registry.inputs().collect::<Vec<_>>()),
+                ("processes", registry.processes().collect()),
+                ("outputs", registry.outputs().collect()),
+                ("codecs", Codec::names().collect()),
+            ] {
+                println!("{role}:");
+                for kind in kinds {
+                    println!("  {kind}");
+                }
+            }
+            0
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    let cli = Cli::parse();
+    let shutdown = async {
+        // stop reading on Ctrl-C so that what was read is still written out
+        // and its checkpoints committed
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    ExitCode::from(execute(cli.command, &Registry::default(), shutdown).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future;
+
+    use super::*;
+
+    fn run(config: &Path) -> Command {
+        Command::Run {
+            config: config.to_owned(),
+        }
+    }
+
+    fn validate(config: &Path) -> Command {
+        Command::Validate {
+            config: config.to_owned(),
+        }
+    }
+
+    async fn exit_code(command: Command) -> u8 {
+        execute(command, &Registry::default(), future::pending()).await
+    }
+
+    #[tokio::test]
+    async fn exits_with_3_for_configs_that_do_not_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let unknown = dir.path().join("unknown.yaml");
+        std::fs::write(&unknown, "input: {type: kafka}\noutput: {type: stdout}").unwrap();
+        let malformed = dir.path().join("malformed.toml");
+        std::fs::write(&malformed, "[input").unwrap();
+        for config in [&unknown, &malformed, &dir.path().join("missing.json")] {
+            assert_eq!(exit_code(validate(config)).await, EXIT_INVALID_CONFIG);
+            assert_eq!(exit_code(run(config)).await, EXIT_INVALID_CONFIG);
+        }
+    }
+
+    #[tokio::test]
+    async fn exits_with_1_for_pipelines_that_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("pipeline.yaml");
+        std::fs::write(
+            &config,
+            format!(
+                "input: {{type: file, path: {:?}}}\noutput: {{type: stdout}}",
+                dir.path().join("missing.ndjson")
+            ),
+        )
+        .unwrap();
+        // the input file is only opened once the pipeline runs
+        assert_eq!(exit_code(validate(&config)).await, 0);
+        assert_eq!(exit_code(run(&config)).await, EXIT_PIPELINE_FAILED);
+    }
+
+    #[tokio::test]
+    async fn exits_with_0_once_pipelines_finish() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.ndjson");
+        let output = dir.path().join("out.ndjson");
+        std::fs::write(&input, "{\"n\":1}\n{\"n\":2}\n").unwrap();
+        let config = dir.path().join("pipeline.toml");
+        std::fs::write(
+            &config,
+            format!(
+                "[input]\ntype = \"file\"\npath = {input:?}\n\n\
+                 [output]\ntype = \"file\"\npath = {output:?}\n"
+            ),
+        )
+        .unwrap();
+        assert_eq!(exit_code(run(&config)).await, 0);
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "{\"n\":1}\n{\"n\":2}\n"
+        );
+        assert_eq!(exit_code(Command::ListComponents).await, 0);
+    }
 }
@@ -0,0 +1,447 @@
+use std::path::{Path, PathBuf};
+
+use ::parquet::arrow::arrow_reader::ArrowReaderMetadata;
+use ::parquet::arrow::{AsyncArrowWriter, ParquetRecordBatchStreamBuilder, ProjectionMask};
+use ::parquet::basic::{self, ZstdLevel};
+use ::parquet::file::metadata::RowGroupMetaData;
+use ::parquet::file::properties::WriterProperties;
+use ::parquet::file::statistics::Statistics;
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::batch::{RecordBatch, at_least_one};
+use crate::fio::with_path;
+use crate::{Error, Result, fio};
+
+const DEFAULT_BATCH_SIZE: usize = 1024;
+const DEFAULT_ROW_GROUP_SIZE: usize = 1024 * 1024;
+
+fn default_batch_size() -> usize {
+    DEFAULT_BATCH_SIZE
+}
+
+fn default_row_group_size() -> usize {
+    DEFAULT_ROW_GROUP_SIZE
+}
+
+/// Keeps the row groups whose statistics say `column` may hold values
+/// between `min` and `max`, both inclusive. Row groups without statistics
+/// for `column` are always kept.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RowGroupFilter {
+    pub column: String,
+    #[serde(default)]
+    pub min: Option<Value>,
+    #[serde(default)]
+    pub max: Option<Value>,
+}
+
+/// A value from row group statistics or a [`RowGroupFilter`] bound.
+#[derive(PartialEq)]
+enum Bound<'a> {
+    Number(f64),
+    Bytes(&'a [u8]),
+}
+
+/// Numbers and bytes do not compare, so that a filter whose bounds do not
+/// match the type of a column keeps every row group.
+impl PartialOrd for Bound<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        match (self, other) {
+            (Bound::Number(a), Bound::Number(b)) => a.partial_cmp(b),
+            (Bound::Bytes(a), Bound::Bytes(b)) => a.partial_cmp(b),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Bound<'a> {
+    fn from_value(value: &'a Value) -> Option<Self> {
+        match value {
+            Value::Number(n) => n.as_f64().map(Bound::Number),
+            Value::String(s) => Some(Bound::Bytes(s.as_bytes())),
+            Value::Bool(b) => Some(Bound::Number(f64::from(u8::from(*b)))),
+            _ => None,
+        }
+    }
+
+    fn range(statistics: &'a Statistics) -> Option<(Self, Self)> {
+        fn numbers<T: Copy + Into<f64>>(
+            min: Option<&T>,
+            max: Option<&T>,
+        ) -> Option<(Bound<'static>, Bound<'static>)> {
+            Some((Bound::Number((*min?).into()), Bound::Number((*max?).into())))
+        }
+        match statistics {
+            Statistics::Boolean(s) => Some((
+                Bound::Number(f64::from(u8::from(*s.min_opt()?))),
+                Bound::Number(f64::from(u8::from(*s.max_opt()?))),
+            )),
+            Statistics::Int32(s) => numbers(s.min_opt(), s.max_opt()),
+            // large values lose precision, which only widens the range
+            Statistics::Int64(s) => Some((
+                Bound::Number(*s.min_opt()? as f64),
+                Bound::Number(*s.max_opt()? as f64),
+            )),
+            Statistics::Float(s) => numbers(s.min_opt(), s.max_opt()),
+            Statistics::Double(s) => numbers(s.min_opt(), s.max_opt()),
+            Statistics::ByteArray(_) | Statistics::FixedLenByteArray(_) => Some((
+                Bound::Bytes(statistics.min_bytes_opt()?),
+                Bound::Bytes(statistics.max_bytes_opt()?),
+            )),
+            Statistics::Int96(_) => None,
+        }
+    }
+}
+
+impl RowGroupFilter {
+    fn keeps(&self, row_group: &RowGroupMetaData) -> bool {
+        let Some(column) = row_group
+            .columns()
+            .iter()
+            .find(|column| column.column_path().string() == self.column)
+        else {
+            return true;
+        };
+        let Some((min, max)) = column.statistics().and_then(Bound::range) else {
+            return true;
+        };
+        let below = self
+            .min
+            .as_ref()
+            .and_then(Bound::from_value)
+            .is_some_and(|bound| max.partial_cmp(&bound).is_some_and(|o| o.is_lt()));
+        let above = self
+            .max
+            .as_ref()
+            .and_then(Bound::from_value)
+            .is_some_and(|bound| min.partial_cmp(&bound).is_some_and(|o| o.is_gt()));
+        !below && !above
+    }
+}
+
+/// Reads Parquet files as Arrow record batches; wrap it in
+/// [`batch::Rows`](crate::batch::Rows) to read rows instead.
+///
+/// `path` may name a single file, a directory, or a glob pattern, like for
+/// [`fio::Input`]. Only the `columns` given are read, by name or dotted path
+/// for nested columns. Row groups can be picked by index with `row_groups`,
+/// and skipped by their statistics with `filter`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Input {
+    path: PathBuf,
+    #[serde(default)]
+    columns: Option<Vec<String>>,
+    #[serde(default)]
+    row_groups: Option<Vec<usize>>,
+    #[serde(default)]
+    filter: Vec<RowGroupFilter>,
+    #[serde(default = "default_batch_size", deserialize_with = "at_least_one")]
+    batch_size: usize,
+}
+
+impl Input {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            columns: None,
+            row_groups: None,
+            filter: Vec::new(),
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    pub fn columns<I: IntoIterator<Item = S>, S: Into<String>>(mut self, columns: I) -> Self {
+        self.columns = Some(columns.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn row_groups<I: IntoIterator<Item = usize>>(mut self, row_groups: I) -> Self {
+        self.row_groups = Some(row_groups.into_iter().collect());
+        self
+    }
+
+    pub fn filter(mut self, filter: RowGroupFilter) -> Self {
+        self.filter.push(filter);
+        self
+    }
+
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// The row groups of a file left after `row_groups` and `filter`.
+    fn select(&self, metadata: &ArrowReaderMetadata) -> Vec<usize> {
+        let row_groups = metadata.metadata().row_groups();
+        (0..row_groups.len())
+            .filter(|i| {
+                self.row_groups
+                    .as_ref()
+                    .is_none_or(|picked| picked.contains(i))
+            })
+            .filter(|&i| {
+                self.filter
+                    .iter()
+                    .all(|filter| filter.keeps(&row_groups[i]))
+            })
+            .collect()
+    }
+
+    /// Projects onto `columns`, which must all exist in the file.
+    fn projection(&self, path: &Path, metadata: &ArrowReaderMetadata) -> Result<ProjectionMask> {
+        let schema = metadata.metadata().file_metadata().schema_descr();
+        let Some(columns) = &self.columns else {
+            return Ok(ProjectionMask::all());
+        };
+        // `ProjectionMask::columns` matches any prefix, taking `name` for `n`
+        let mut leaves = Vec::new();
+        for name in columns {
+            let before = leaves.len();
+            leaves.extend(
+                schema
+                    .columns()
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, column)| {
+                        let path = column.path().string();
+                        let matches = path == *name
+                            || path
+                                .strip_prefix(name.as_str())
+                                .is_some_and(|rest| rest.starts_with('.'));
+                        matches.then_some(i)
+                    }),
+            );
+            if leaves.len() == before {
+                return Err(Error::config(format!(
+                    "{}: no column `{name}`",
+                    path.display()
+                )));
+            }
+        }
+        Ok(ProjectionMask::leaves(schema, leaves))
+    }
+}
+
+impl crate::Input<RecordBatch> for Input {
+    fn into_stream(self) -> impl Stream<Item = Result<RecordBatch>> + Send {
+        async_stream::try_stream! {
+            for path in fio::Input::new(&self.path).paths()? {
+                let mut file = tokio::fs::File::open(&path).await.map_err(with_path(&path))?;
+                let metadata = ArrowReaderMetadata::load_async(&mut file, Default::default())
+                    .await
+                    .map_err(|e| Error::codec(format!("{}: {e}", path.display())))?;
+                let projection = self.projection(&path, &metadata)?;
+                let row_groups = self.select(&metadata);
+                tracing::debug!(
+                    "reading {} of {} row groups from {}",
+                    row_groups.len(),
+                    metadata.metadata().num_row_groups(),
+                    path.display()
+                );
+                let batches = ParquetRecordBatchStreamBuilder::new_with_metadata(file, metadata)
+                    .with_projection(projection)
+                    .with_row_groups(row_groups)
+                    .with_batch_size(self.batch_size)
+                    .build()
+                    .map_err(Error::codec)?;
+                for await batch in batches {
+                    yield batch.map_err(Error::codec)?;
+                }
+            }
+        }
+    }
+}
+
+/// How [`Output`] compresses its pages.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    Uncompressed,
+    Snappy,
+    Gzip,
+    Lz4,
+    #[default]
+    Zstd,
+}
+
+impl From<Compression> for basic::Compression {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::Uncompressed => Self::UNCOMPRESSED,
+            Compression::Snappy => Self::SNAPPY,
+            Compression::Gzip => Self::GZIP(Default::default()),
+            Compression::Lz4 => Self::LZ4_RAW,
+            Compression::Zstd => Self::ZSTD(ZstdLevel::default()),
+        }
+    }
+}
+
+/// Writes Arrow record batches to a Parquet file, replacing it; wrap it in
+/// [`batch::Batches`](crate::batch::Batches) to write rows instead.
+///
+/// The schema is taken from the first batch. A row group is closed once it
+/// holds `row_group_size` rows. No file is written for an empty stream.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Output {
+    path: PathBuf,
+    #[serde(default)]
+    compression: Compression,
+    #[serde(default = "default_row_group_size", deserialize_with = "at_least_one")]
+    row_group_size: usize,
+}
+
+impl Output {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            compression: Compression::default(),
+            row_group_size: DEFAULT_ROW_GROUP_SIZE,
+        }
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn row_group_size(mut self, row_group_size: usize) -> Self {
+        self.row_group_size = row_group_size.max(1);
+        self
+    }
+}
+
+impl crate::Output<RecordBatch> for Output {
+    async fn output<S>(&self, stream: S) -> Result<()>
+    where
+        S: Stream<Item = RecordBatch> + Send,
+    {
+        futures::pin_mut!(stream);
+        let mut writer = None;
+        while let Some(batch) = stream.next().await {
+            let writer = match &mut writer {
+                Some(writer) => writer,
+                None => {
+                    let file = tokio::fs::File::create(&self.path)
+                        .await
+                        .map_err(with_path(&self.path))?;
+                    let properties = WriterProperties::builder()
+                        .set_compression(self.compression.into())
+                        .set_max_row_group_size(self.row_group_size)
+                        .build();
+                    let created = AsyncArrowWriter::try_new(file, batch.schema(), Some(properties))
+                        .map_err(Error::codec)?;
+                    writer.insert(created)
+                }
+            };
+            writer.write(&batch).await.map_err(Error::codec)?;
+        }
+        if let Some(writer) = writer {
+            writer.close().await.map_err(Error::codec)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+    use serde_json::json;
+
+    use super::*;
+    use crate::Output as _;
+    use crate::batch::{Batches, Rows};
+
+    /// Writes rows `0..40` with an `n` and a `name` column, ten to a row group.
+    async fn write(path: &Path) {
+        let rows: Vec<_> = (0..40)
+            .map(|n| json!({"n": n, "name": format!("row{n:02}")}))
+            .collect();
+        let output = Output::new(path).row_group_size(10);
+        Batches::new(output)
+            .batch_size(10)
+            .output(futures::stream::iter(rows))
+            .await
+            .unwrap();
+    }
+
+    async fn read(input: Input) -> Vec<i64> {
+        let rows: Vec<Value> = crate::Input::into_stream(Rows::new(input))
+            .try_collect()
+            .await
+            .unwrap();
+        rows.iter().map(|row| row["n"].as_i64().unwrap()).collect()
+    }
+
+    fn filter(column: &str, min: Option<Value>, max: Option<Value>) -> RowGroupFilter {
+        RowGroupFilter {
+            column: column.to_string(),
+            min,
+            max,
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_row_groups_outside_filter_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rows.parquet");
+        write(&path).await;
+
+        let input = Input::new(&path).filter(filter("n", Some(json!(15)), Some(json!(22))));
+        assert_eq!(read(input).await, (10..30).collect::<Vec<_>>());
+
+        let input = Input::new(&path).filter(filter("name", Some(json!("row35")), None));
+        assert_eq!(read(input).await, (30..40).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn keeps_row_groups_when_bounds_do_not_match_the_column_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rows.parquet");
+        write(&path).await;
+
+        let input = Input::new(&path).filter(filter("n", Some(json!("x")), None));
+        assert_eq!(read(input).await.len(), 40);
+        let input = Input::new(&path).filter(filter("name", None, Some(json!(0))));
+        assert_eq!(read(input).await.len(), 40);
+    }
+
+    #[tokio::test]
+    async fn picks_row_groups_and_columns() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rows.parquet");
+        write(&path).await;
+
+        let input = Input::new(&path).row_groups([1, 3]).columns(["n"]);
+        let rows: Vec<Value> = crate::Input::into_stream(Rows::new(input))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 20);
+        assert_eq!(rows[0], json!({"n": 10}));
+
+        let input = Input::new(&path).columns(["missing"]);
+        let result: Result<Vec<Value>> = crate::Input::into_stream(Rows::new(input))
+            .try_collect()
+            .await;
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("no column `missing`")
+        );
+    }
+
+    #[test]
+    fn rejects_empty_batches_and_row_groups() {
+        let e = serde_json::from_value::<Input>(json!({"path": "x", "batch_size": 0})).unwrap_err();
+        assert!(e.to_string().contains("must be at least 1"), "{e}");
+        let e = serde_json::from_value::<Output>(json!({"path": "x", "row_group_size": 0}))
+            .unwrap_err();
+        assert!(e.to_string().contains("must be at least 1"), "{e}");
+    }
+}
@@ -0,0 +1,635 @@
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::channel::oneshot;
+use futures::{FutureExt, Stream, StreamExt, future, stream};
+
+use crate::{Acks, Checkpoints, Error, Input, Output, Process, Result};
+
+/// How often checkpoints acknowledged by the output are committed while a
+/// pipeline runs, unless set otherwise.
+pub(crate) const DEFAULT_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Holds the first error seen by [`until_err`].
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Failure(Arc<Mutex<Option<Error>>>);
+
+impl Failure {
+    pub(crate) fn set(&self, e: Error) {
+        self.0.lock().unwrap().get_or_insert(e);
+    }
+
+    pub(crate) fn take(&self) -> Option<Error> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+/// Yields the `Ok` items of `stream` and ends at the first `Err`, which is
+/// stored in `failure`.
+pub(crate) fn until_err<S, T>(stream: S, failure: Failure) -> impl Stream<Item = T> + Send
+where
+    S: Stream<Item = Result<T>> + Send,
+    T: Send,
+{
+    async_stream::stream! {
+        futures::pin_mut!(stream);
+        while let Some(item) = stream.next().await {
+            match item {
+                Ok(item) => yield item,
+                Err(e) => {
+                    failure.set(e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Runs `output` to completion, meanwhile committing the checkpoints of the
+/// items it acknowledges in `acks` every `interval`.
+pub(crate) async fn commit_acked<F>(
+    output: F,
+    checkpoints: Option<&Checkpoints>,
+    acks: &Acks,
+    interval: Duration,
+) -> Result<()>
+where
+    F: Future<Output = Result<()>>,
+{
+    let Some(checkpoints) = checkpoints else {
+        return output.await;
+    };
+    let (done, finished) = oneshot::channel::<()>();
+    let output = output.inspect(move |_| drop(done));
+    let commits = async {
+        let mut finished = finished.fuse();
+        let mut ticks = tokio::time::interval(interval);
+        ticks.tick().await;
+        loop {
+            tokio::select! {
+                _ = &mut finished => break,
+                _ = ticks.tick() => {}
+            }
+            // not raced against the output, so that saves are never cut short
+            if let Err(e) = checkpoints.commit_acked(acks.count()).await {
+                tracing::warn!("cannot commit checkpoints: {e}");
+            }
+        }
+    };
+    let (result, ()) = future::join(output, commits).await;
+    result
+}
+
+/// Passes every item through unchanged.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Identity;
+
+impl<T: Send> Process<T, T> for Identity {
+    async fn process<S>(&self, stream: S) -> impl Stream<Item = Result<T>> + Send
+    where
+        S: Stream<Item = T> + Send,
+    {
+        stream.map(Ok)
+    }
+}
+
+/// Two process stages run back to back, `first` feeding `second`.
+///
+/// An error from `first` ends the input of `second` and is yielded once
+/// `second` has drained what it received.
+pub struct Chain<A, B, U> {
+    first: A,
+    second: B,
+    _marker: PhantomData<fn() -> U>,
+}
+
+impl<A, B, U> Chain<A, B, U> {
+    pub fn new(first: A, second: B) -> Self {
+        Self {
+            first,
+            second,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, U, V, A, B> Process<T, V> for Chain<A, B, U>
+where
+    A: Process<T, U> + Sync,
+    B: Process<U, V> + Sync,
+    U: Send,
+    V: Send,
+{
+    async fn process<S>(&self, stream: S) -> impl Stream<Item = Result<V>> + Send
+    where
+        S: Stream<Item = T> + Send,
+    {
+        let failure = Failure::default();
+        let stream = self.first.process(stream).await;
+        let stream = self
+            .second
+            .process(until_err(stream, failure.clone()))
+            .await;
+        stream.chain(stream::once(async move { failure.take().map(Err) }).filter_map(future::ready))
+    }
+
+    fn holds_items(&self) -> bool {
+        self.first.holds_items() || self.second.holds_items()
+    }
+}
+
+/// What happened during a single [`Pipeline::run`].
+#[derive(Debug)]
+pub struct Summary {
+    /// Items produced by the input.
+    pub items_in: u64,
+    /// Items handed to the output after all process stages.
+    pub items_out: u64,
+    /// Wall-clock time from start until the output finished.
+    pub elapsed: Duration,
+    /// The error that stopped the pipeline, if any.
+    pub error: Option<Error>,
+}
+
+impl Summary {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// An input, a chain of process stages and an output, connected into one
+/// runnable value.
+///
+/// Stage types are checked as the pipeline is built: each `process` must
+/// accept what the previous stage produces, and `output` must accept what
+/// the last stage produces.
+///
+/// ```no_run
+/// # async fn run() {
+/// use data_proc::{Pipeline, Stdin, Stdout};
+///
+/// let summary = Pipeline::new(Stdin::new()).output(Stdout::new()).run().await;
+/// assert!(summary.is_success());
+/// # }
+/// ```
+pub struct Pipeline<T, U, I, P, O> {
+    input: I,
+    process: P,
+    output: O,
+    checkpoint_interval: Duration,
+    _marker: PhantomData<fn(T) -> U>,
+}
+
+impl<T, I: Input<T>> Pipeline<T, T, I, Identity, ()> {
+    pub fn new(input: I) -> Self {
+        Self {
+            input,
+            process: Identity,
+            output: (),
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, U, I, P, O> Pipeline<T, U, I, P, O> {
+    /// How often the checkpoints of the items the output has acknowledged
+    /// are committed while the pipeline runs; see [`Output::output_acked`].
+    /// Everything else is committed once the input is exhausted.
+    pub fn checkpoint_interval(mut self, interval: Duration) -> Self {
+        self.checkpoint_interval = interval;
+        self
+    }
+}
+
+impl<T, U, I, P> Pipeline<T, U, I, P, ()> {
+    /// Appends a process stage consuming the items produced so far.
+    pub fn process<V, Q>(self, process: Q) -> Pipeline<T, V, I, Chain<P, Q, U>, ()>
+    where
+        Q: Process<U, V>,
+    {
+        Pipeline {
+            input: self.input,
+            process: Chain::new(self.process, process),
+            output: (),
+            checkpoint_interval: self.checkpoint_interval,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sets the output that consumes the items of the last stage.
+    pub fn output<O>(self, output: O) -> Pipeline<T, U, I, P, O>
+    where
+        O: Output<U>,
+    {
+        Pipeline {
+            input: self.input,
+            process: self.process,
+            output,
+            checkpoint_interval: self.checkpoint_interval,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, U, I, P, O> Pipeline<T, U, I, P, O>
+where
+    I: Input<T>,
+    P: Process<T, U> + Sync,
+    O: Output<U>,
+    T: Send,
+    U: Send,
+{
+    /// Drives the pipeline until the input is exhausted or any stage fails.
+    pub async fn run(self) -> Summary {
+        self.run_until(future::pending()).await
+    }
+
+    /// Like [`run`](Self::run), but stops reading the input once `shutdown`
+    /// completes. Items already read still pass through to the output.
+    ///
+    /// Fails right away if the input tracks checkpoints and a process stage
+    /// holds on to items; see [`Process::holds_items`].
+    pub async fn run_until<F: Future<Output = ()> + Send>(self, shutdown: F) -> Summary {
+        let start = Instant::now();
+        let failure = Failure::default();
+        let checkpoints = self.input.checkpoints();
+        if checkpoints.is_some() && self.process.holds_items() {
+            return Summary {
+                items_in: 0,
+                items_out: 0,
+                elapsed: start.elapsed(),
+                error: Some(Error::config(
+                    "a process stage holds on to items, such as failed ones for its \
+                     dead-letter output, so it cannot follow an input that tracks checkpoints",
+                )),
+            };
+        }
+        let items_in = AtomicU64::new(0);
+        let items_out = AtomicU64::new(0);
+
+        let stream = until_err(self.input.into_stream(), failure.clone())
+            .take_until(shutdown)
+            .inspect(|_| {
+                items_in.fetch_add(1, Ordering::Relaxed);
+            });
+        let stream = self.process.process(stream).await;
+        let stream = until_err(stream, failure.clone()).inspect(|_| {
+            let item = items_out.fetch_add(1, Ordering::Relaxed) + 1;
+            if let Some(checkpoints) = &checkpoints {
+                checkpoints.mark(item);
+            }
+        });
+        let acks = Acks::default();
+        let result = commit_acked(
+            self.output.output_acked(stream, acks.clone()),
+            checkpoints.as_ref(),
+            &acks,
+            self.checkpoint_interval,
+        )
+        .await;
+        let error = result.err().or_else(|| failure.take());
+        let error = match (error, checkpoints) {
+            (None, Some(checkpoints)) => checkpoints.commit().await.err(),
+            (error, _) => error,
+        };
+
+        Summary {
+            items_in: items_in.into_inner(),
+            items_out: items_out.into_inner(),
+            elapsed: start.elapsed(),
+            error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{CheckpointStore, FileStore, fio};
+
+    /// Collects items as strings without ever acknowledging them.
+    #[derive(Default, Clone)]
+    struct Collect(Arc<Mutex<Vec<String>>>);
+
+    impl<T: ToString + Send> Output<T> for Collect {
+        async fn output<S>(&self, stream: S) -> Result<()>
+        where
+            S: Stream<Item = T> + Send,
+        {
+            futures::pin_mut!(stream);
+            while let Some(item) = stream.next().await {
+                self.0.lock().unwrap().push(item.to_string());
+            }
+            Ok(())
+        }
+    }
+
+    /// Yields its items, then fails if told to.
+    struct Items(Vec<&'static str>, Option<&'static str>);
+
+    impl Input<String> for Items {
+        fn into_stream(self) -> impl Stream<Item = Result<String>> + Send {
+            let items = self.0.into_iter().map(|item| Ok(item.to_string()));
+            let error = self.1.map(|e| Err(Error::user(e)));
+            stream::iter(items.chain(error))
+        }
+    }
+
+    /// Repeats every item `n` times, failing on `"fail"`.
+    struct Repeat(usize);
+
+    impl Process<String, String> for Repeat {
+        async fn process<S>(&self, stream: S) -> impl Stream<Item = Result<String>> + Send
+        where
+            S: Stream<Item = String> + Send,
+        {
+            let n = self.0;
+            stream.flat_map(move |item| match item.as_str() {
+                "fail" => stream::iter(vec![Err(Error::user("cannot repeat"))]),
+                _ => stream::iter((0..n).map(|_| Ok(item.clone())).collect::<Vec<_>>()),
+            })
+        }
+    }
+
+    /// Fails once it is given an item.
+    struct Full;
+
+    impl Output<String> for Full {
+        async fn output<S>(&self, stream: S) -> Result<()>
+        where
+            S: Stream<Item = String> + Send,
+        {
+            futures::pin_mut!(stream);
+            match stream.next().await {
+                Some(_) => Err(Error::Io(std::io::ErrorKind::StorageFull.into())),
+                None => Ok(()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_items_through_every_stage_and_counts_them() {
+        let collected = Collect::default();
+        let summary = Pipeline::new(Items(vec!["a", "b"], None))
+            .process(Repeat(2))
+            .process(Identity)
+            .process(Repeat(3))
+            .output(collected.clone())
+            .run()
+            .await;
+        assert!(summary.is_success(), "{:?}", summary.error);
+        assert_eq!((summary.items_in, summary.items_out), (2, 12));
+        let items = collected.0.lock().unwrap();
+        assert_eq!(items[..6], ["a"; 6]);
+        assert_eq!(items[6..], ["b"; 6]);
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_first_error_of_any_stage() {
+        let collected = Collect::default();
+        let summary = Pipeline::new(Items(vec!["a", "b"], Some("input gone")))
+            .output(collected.clone())
+            .run()
+            .await;
+        assert_eq!(summary.error.unwrap().to_string(), "input gone");
+        assert_eq!((summary.items_in, summary.items_out), (2, 2));
+
+        // what the first stage passed on before failing still reaches the end
+        let collected = Collect::default();
+        let summary = Pipeline::new(Items(vec!["a", "fail", "b"], None))
+            .process(Repeat(1))
+            .process(Repeat(2))
+            .output(collected.clone())
+            .run()
+            .await;
+        assert_eq!(summary.error.unwrap().to_string(), "cannot repeat");
+        assert_eq!(*collected.0.lock().unwrap(), ["a", "a"]);
+
+        let summary = Pipeline::new(Items(vec!["a"], None))
+            .output(Full)
+            .run()
+            .await;
+        let error = summary.error.unwrap();
+        assert!(matches!(error, Error::Io(_)), "{error}");
+        let summary = Pipeline::new(Items(vec![], None)).output(Full).run().await;
+        assert!(summary.is_success());
+    }
+
+    #[tokio::test]
+    async fn stops_reading_on_shutdown_and_passes_on_what_was_read() {
+        let collected = Collect::default();
+        let summary = Pipeline::new(Items(vec!["a"], None))
+            .output(collected.clone())
+            .run_until(future::ready(()))
+            .await;
+        assert!(summary.is_success());
+        assert_eq!(summary.items_in, summary.items_out);
+        assert_eq!(collected.0.lock().unwrap().len() as u64, summary.items_out);
+    }
+
+    fn committed(store: &Path, input: &Path) -> Option<u64> {
+        FileStore::new(store)
+            .load(&input.to_string_lossy())
+            .unwrap()
+            .map(|checkpoint| checkpoint.offset)
+    }
+
+    async fn wait_for(mut done: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let (input, output, store) = (
+            dir.path().join("in.log"),
+            dir.path().join("out.log"),
+            dir.path().join("checkpoints.json"),
+        );
+        let run = || {
+            Pipeline::new(fio::Input::new(&input).checkpoints(Checkpoints::open(&store)))
+                .output(fio::Output::new(&output).mode(fio::WriteMode::Append))
+                .run()
+        };
+
+        std::fs::write(&input, "a\nb\n").unwrap();
+        assert!(run().await.is_success());
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&input)
+            .and_then(|mut file| std::io::Write::write_all(&mut file, b"c\n"))
+            .unwrap();
+        let summary = run().await;
+        assert!(summary.is_success());
+        assert_eq!(summary.items_in, 1);
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "a\nb\nc\n");
+        assert_eq!(committed(&store, &input), Some(6));
+    }
+
+    #[tokio::test]
+    async fn commits_acknowledged_items_while_running() {
+        let dir = tempfile::tempdir().unwrap();
+        let (input, output, store) = (
+            dir.path().join("in.log"),
+            dir.path().join("out.log"),
+            dir.path().join("checkpoints.json"),
+        );
+        std::fs::write(&input, "a\nb\n").unwrap();
+        let followed = fio::Input::new(&input)
+            .follow(true)
+            .poll_interval(Duration::from_millis(20))
+            .checkpoints(Checkpoints::open(&store));
+        let running = Pipeline::new(followed)
+            .output(fio::Output::new(&output))
+            .checkpoint_interval(Duration::from_millis(20))
+            // the line read last could still be turning into more items, so
+            // it is only committed once another one is read or the run ends
+            .run_until(wait_for(|| committed(&store, &input) == Some(2)));
+
+        let summary = tokio::time::timeout(Duration::from_secs(10), running)
+            .await
+            .unwrap();
+        assert!(summary.is_success());
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "a\nb\n");
+        assert_eq!(committed(&store, &input), Some(4));
+    }
+
+    /// Acknowledges the first `acked` items, then fails once checkpoints
+    /// had time to be committed.
+    struct CrashAfter {
+        acked: u64,
+    }
+
+    impl Output<String> for CrashAfter {
+        async fn output<S>(&self, stream: S) -> Result<()>
+        where
+            S: Stream<Item = String> + Send,
+        {
+            self.output_acked(stream, Acks::default()).await
+        }
+
+        async fn output_acked<S>(&self, stream: S, acks: Acks) -> Result<()>
+        where
+            S: Stream<Item = String> + Send,
+        {
+            futures::pin_mut!(stream);
+            for _ in 0..self.acked {
+                stream.next().await;
+            }
+            acks.acknowledge(self.acked);
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            Err(Error::user("crashed"))
+        }
+    }
+
+    #[tokio::test]
+    async fn commits_no_items_partly_written_by_expanding_stages() {
+        let dir = tempfile::tempdir().unwrap();
+        let (input, store) = (
+            dir.path().join("in.log"),
+            dir.path().join("checkpoints.json"),
+        );
+        std::fs::write(&input, "a\nb\nc\n").unwrap();
+        // "b" became the items 4 to 6, of which only the first was written
+        let summary = Pipeline::new(fio::Input::new(&input).checkpoints(Checkpoints::open(&store)))
+            .process(Repeat(3))
+            .output(CrashAfter { acked: 4 })
+            .checkpoint_interval(Duration::from_millis(20))
+            .run()
+            .await;
+        assert_eq!(summary.error.unwrap().to_string(), "crashed");
+        assert_eq!(committed(&store, &input), Some(2));
+    }
+
+    #[tokio::test]
+    async fn refuses_stages_holding_items_after_inputs_tracking_checkpoints() {
+        use crate::codec::ParseJson;
+        use crate::{DeadLetter, ErrorPolicy, FailedItems};
+
+        /// Claims to track checkpoints of the items it yields.
+        struct Tracked(Items, Checkpoints);
+
+        impl Input<String> for Tracked {
+            fn into_stream(self) -> impl Stream<Item = Result<String>> + Send {
+                self.0.into_stream()
+            }
+
+            fn checkpoints(&self) -> Option<Checkpoints> {
+                Some(self.1.clone())
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let failed = Collect::default();
+        let dead_letter = || {
+            DeadLetter::new(
+                ParseJson::<serde_json::Value>::new(),
+                FailedItems(failed.clone()),
+            )
+            .policy(ErrorPolicy::Skip)
+        };
+        let items = || Items(vec!["1", "{", "2"], None);
+
+        let tracked = Tracked(items(), Checkpoints::open(dir.path().join("c.json")));
+        let summary = Pipeline::new(tracked)
+            .process(Identity)
+            .process(dead_letter())
+            .output(Collect::default())
+            .run()
+            .await;
+        let error = summary.error.unwrap();
+        assert!(matches!(error, Error::Config(_)), "{error}");
+        assert_eq!(summary.items_in, 0);
+        assert!(failed.0.lock().unwrap().is_empty());
+
+        let collected = Collect::default();
+        let summary = Pipeline::new(items())
+            .process(dead_letter())
+            .output(collected.clone())
+            .run()
+            .await;
+        assert!(summary.is_success(), "{:?}", summary.error);
+        assert_eq!(*collected.0.lock().unwrap(), ["1", "2"]);
+        assert_eq!(*failed.0.lock().unwrap(), ["{"]);
+    }
+
+    #[tokio::test]
+    async fn keeps_unacknowledged_items_uncommitted_until_the_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let (input, store) = (
+            dir.path().join("in.log"),
+            dir.path().join("checkpoints.json"),
+        );
+        std::fs::write(&input, "a\nb\n").unwrap();
+        let followed = fio::Input::new(&input)
+            .follow(true)
+            .poll_interval(Duration::from_millis(20))
+            .checkpoints(Checkpoints::open(&store));
+        let collected = Collect::default();
+        let items = collected.0.clone();
+        let store_path = store.clone();
+        let input_path = input.clone();
+        let summary = Pipeline::new(followed)
+            .output(collected)
+            .checkpoint_interval(Duration::from_millis(20))
+            .run_until(async move {
+                wait_for(|| items.lock().unwrap().len() == 2).await;
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                assert_eq!(committed(&store_path, &input_path), None);
+            })
+            .await;
+
+        assert!(summary.is_success());
+        assert_eq!(committed(&store, &input), Some(4));
+    }
+}
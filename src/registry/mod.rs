@@ -0,0 +1,724 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::{FutureExt, StreamExt, future};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::codec::{Codec, Decode, Encode, Encoder, Malformed, ParseJson};
+use crate::config::{ComponentConfig, PipelineConfig};
+use crate::pipeline::{Failure, commit_acked, until_err};
+use crate::{
+    Acks, Checkpoints, DeadLetter, Error, ErrorPolicy, Failed, FailedItems, HttpOutput, Input,
+    Output, Process, Result, Stdin, Stdout, Summary, fio,
+};
+
+/// The item type flowing through configured pipelines.
+pub type Record = Value;
+
+/// Conversion from a [`Record`] into the item type a component consumes.
+pub trait FromRecord: Sized {
+    fn from_record(record: Record) -> Result<Self>;
+}
+
+impl FromRecord for Value {
+    fn from_record(record: Record) -> Result<Self> {
+        Ok(record)
+    }
+}
+
+/// Strings are passed through as-is, anything else is rendered as JSON.
+impl FromRecord for String {
+    fn from_record(record: Record) -> Result<Self> {
+        match record {
+            Value::String(s) => Ok(s),
+            other => Ok(other.to_string()),
+        }
+    }
+}
+
+/// An [`Input`] of records behind a trait object.
+pub trait DynInput: Send {
+    fn into_record_stream(self: Box<Self>) -> BoxStream<'static, Result<Record>>;
+
+    fn checkpoints(&self) -> Option<Checkpoints>;
+}
+
+/// A [`Process`] of records behind a trait object.
+pub trait DynProcess: Send + Sync {
+    fn process_records(
+        self: Arc<Self>,
+        stream: BoxStream<'static, Record>,
+    ) -> BoxStream<'static, Result<Record>>;
+
+    /// See [`Process::holds_items`].
+    fn holds_items(&self) -> bool {
+        false
+    }
+}
+
+/// An [`Output`] of records behind a trait object.
+pub trait DynOutput: Send + Sync {
+    /// Writes `stream`, acknowledging records in `acks` as
+    /// [`Output::output_acked`] does.
+    fn output_records(
+        self: Arc<Self>,
+        stream: BoxStream<'static, Record>,
+        acks: Acks,
+    ) -> BoxFuture<'static, Result<()>>;
+}
+
+struct RecordInput<C, T>(C, PhantomData<fn() -> T>);
+
+impl<C, T> DynInput for RecordInput<C, T>
+where
+    C: Input<T> + Send + 'static,
+    T: Into<Record> + Send + 'static,
+{
+    fn into_record_stream(self: Box<Self>) -> BoxStream<'static, Result<Record>> {
+        self.0
+            .into_stream()
+            .map(|item| item.map(Into::into))
+            .boxed()
+    }
+
+    fn checkpoints(&self) -> Option<Checkpoints> {
+        self.0.checkpoints()
+    }
+}
+
+struct RecordProcess<C, T, U>(C, PhantomData<fn(T) -> U>);
+
+impl<C, T, U> DynProcess for RecordProcess<C, T, U>
+where
+    C: Process<T, U> + Send + Sync + 'static,
+    T: FromRecord + Send + 'static,
+    U: Into<Record> + Send + 'static,
+{
+    fn process_records(
+        self: Arc<Self>,
+        stream: BoxStream<'static, Record>,
+    ) -> BoxStream<'static, Result<Record>> {
+        async_stream::stream! {
+            let failure = Failure::default();
+            let items = until_err(stream.map(T::from_record), failure.clone());
+            let processed = self.0.process(items).await;
+            futures::pin_mut!(processed);
+            while let Some(item) = processed.next().await {
+                yield item.map(Into::into);
+            }
+            if let Some(e) = failure.take() {
+                yield Err(e);
+            }
+        }
+        .boxed()
+    }
+
+    fn holds_items(&self) -> bool {
+        self.0.holds_items()
+    }
+}
+
+struct RecordOutput<C, T>(C, PhantomData<fn(T)>);
+
+impl<C, T> DynOutput for RecordOutput<C, T>
+where
+    C: Output<T> + Send + Sync + 'static,
+    T: FromRecord + Send + 'static,
+{
+    fn output_records(
+        self: Arc<Self>,
+        stream: BoxStream<'static, Record>,
+        acks: Acks,
+    ) -> BoxFuture<'static, Result<()>> {
+        async move {
+            let failure = Failure::default();
+            let items = until_err(stream.map(T::from_record), failure.clone());
+            self.0.output_acked(items, acks).await?;
+            failure.take().map_or(Ok(()), Err)
+        }
+        .boxed()
+    }
+}
+
+type Factory<C> = Box<dyn Fn(Value) -> Result<C> + Send + Sync>;
+type InputFactory = Factory<Box<dyn DynInput>>;
+type ProcessFactory = Factory<Arc<dyn DynProcess>>;
+type OutputFactory = Factory<Arc<dyn DynOutput>>;
+
+fn from_options<C: DeserializeOwned>(kind: &str, options: Value) -> Result<C> {
+    serde_json::from_value(options).map_err(|e| Error::config(format!("{kind}: {e}")))
+}
+
+/// Removes the `codec` option from `options`, for components which do not
+/// know about codecs themselves.
+fn take_codec(kind: &str, options: &mut Value) -> Result<Option<Codec>> {
+    options
+        .as_object_mut()
+        .and_then(|options| options.remove("codec"))
+        .map(|codec| from_options(kind, codec))
+        .transpose()
+}
+
+/// Options of the `parse_json` process, which parses lines into records and
+/// appends the malformed ones to `dead_letter`, if given.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ParseJsonConfig {
+    #[serde(default)]
+    on_malformed: Malformed,
+    #[serde(default)]
+    dead_letter: Option<PathBuf>,
+}
+
+fn parse_json(options: Value) -> Result<Arc<dyn DynProcess>> {
+    let config: ParseJsonConfig = from_options("parse_json", options)?;
+    let policy = match config.on_malformed {
+        Malformed::Fail => ErrorPolicy::Stop,
+        Malformed::Skip => ErrorPolicy::Skip,
+    };
+    let parse = ParseJson::<Value>::new();
+    Ok(match config.dead_letter {
+        Some(path) => {
+            let sink = FailedItems(fio::Output::new(path).mode(fio::WriteMode::Append));
+            let stage = DeadLetter::new(parse, sink).policy(policy);
+            Arc::new(RecordProcess::<_, String, Value>(stage, PhantomData))
+        }
+        None => {
+            let stage = Dropping(DeadLetter::new(parse, Discard).policy(policy));
+            Arc::new(RecordProcess::<_, String, Value>(stage, PhantomData))
+        }
+    })
+}
+
+/// Builds the `http` output, which appends the items it could not deliver to
+/// its `dead_letter` file, if given, instead of failing.
+fn http_output(mut options: Value) -> Result<Arc<dyn DynOutput>> {
+    let codec = take_codec("http", &mut options)?;
+    let dead_letter: Option<PathBuf> = options
+        .as_object_mut()
+        .and_then(|options| options.remove("dead_letter"))
+        .map(|path| from_options("http dead_letter", path))
+        .transpose()?;
+    let output: HttpOutput = from_options("http", options)?;
+    Ok(match dead_letter {
+        Some(path) => {
+            let file = fio::Output::new(path).mode(fio::WriteMode::Append);
+            let sink = FailedItems(Encode::new(file, AsSent));
+            encoded_output(output.dead_letter(sink), codec)
+        }
+        None => encoded_output(output, codec),
+    })
+}
+
+/// Wraps a byte-oriented output, which encodes records with `codec` if given,
+/// and writes lines otherwise.
+fn encoded_output<C>(output: C, codec: Option<Codec>) -> Arc<dyn DynOutput>
+where
+    C: Output<String> + Send + Sync + 'static,
+    Encode<C, Codec>: Output<Record> + Send + Sync + 'static,
+{
+    match codec {
+        Some(codec) => Arc::new(RecordOutput(Encode::new(output, codec), PhantomData)),
+        None => Arc::new(RecordOutput::<_, String>(output, PhantomData)),
+    }
+}
+
+/// Writes items as they were sent, each on a line of its own.
+#[derive(Clone)]
+struct AsSent;
+
+impl Encoder<Bytes> for AsSent {
+    fn encode(&mut self, item: &Bytes, buf: &mut Vec<u8>) -> Result<()> {
+        buf.extend_from_slice(item);
+        if !item.ends_with(b"\n") {
+            buf.push(b'\n');
+        }
+        Ok(())
+    }
+}
+
+/// A [`DeadLetter`] stage with a [`Discard`] output, which holds on to failed
+/// items only to drop them: checkpoints committed past them lose nothing.
+struct Dropping<P>(P);
+
+impl<T, U, P: Process<T, U> + Sync> Process<T, U> for Dropping<P> {
+    async fn process<S>(&self, stream: S) -> impl futures::Stream<Item = Result<U>> + Send
+    where
+        S: futures::Stream<Item = T> + Send,
+    {
+        self.0.process(stream).await
+    }
+}
+
+/// Drops failed items, logging their errors.
+struct Discard;
+
+impl<T: Send, E: std::fmt::Display + Send> Output<Failed<T, E>> for Discard {
+    async fn output<S>(&self, stream: S) -> Result<()>
+    where
+        S: futures::Stream<Item = Failed<T, E>> + Send,
+    {
+        stream
+            .for_each(|failed| {
+                tracing::warn!("dropping failed item: {}", failed.error);
+                future::ready(())
+            })
+            .await;
+        Ok(())
+    }
+}
+
+/// Maps component type names to factories building them from their options.
+///
+/// [`Registry::default`] knows the components shipped with this crate; use
+/// [`Registry::empty`] to start from scratch.
+pub struct Registry {
+    inputs: BTreeMap<String, InputFactory>,
+    processes: BTreeMap<String, ProcessFactory>,
+    outputs: BTreeMap<String, OutputFactory>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register_decoded_input::<Stdin>("stdin");
+        registry.register_decoded_input::<fio::Input>("file");
+        registry.register_decoded_input::<crate::http::HttpInput>("http");
+        registry.register_encoded_output::<Stdout>("stdout");
+        registry.register_encoded_output::<fio::Output>("file");
+        registry.register_output_with("http", http_output);
+        registry.register_process_with("parse_json", parse_json);
+        #[cfg(feature = "arrow")]
+        {
+            registry.register_batch_input::<crate::ipc::Input>("arrow_ipc");
+            registry.register_batch_output::<crate::ipc::Output>("arrow_ipc");
+        }
+        #[cfg(feature = "parquet")]
+        {
+            registry.register_batch_input::<crate::parquet::Input>("parquet");
+            registry.register_batch_output::<crate::parquet::Output>("parquet");
+        }
+        registry
+    }
+}
+
+impl Registry {
+    pub fn empty() -> Self {
+        Self {
+            inputs: BTreeMap::new(),
+            processes: BTreeMap::new(),
+            outputs: BTreeMap::new(),
+        }
+    }
+
+    /// Registers an input deserialized from its options.
+    pub fn register_input<C, T>(&mut self, kind: &str)
+    where
+        C: DeserializeOwned + Input<T> + Send + 'static,
+        T: Into<Record> + Send + 'static,
+    {
+        let name = kind.to_string();
+        self.register_input_with(kind, move |options| {
+            let input: C = from_options(&name, options)?;
+            Ok(Box::new(RecordInput(input, PhantomData)))
+        });
+    }
+
+    /// Registers a byte-oriented input which decodes records with its `codec`
+    /// option if given, and reads lines otherwise.
+    fn register_decoded_input<C>(&mut self, kind: &str)
+    where
+        C: DeserializeOwned + Input<String> + Send + 'static,
+        Decode<C, Codec>: Input<Record> + Send + 'static,
+    {
+        let name = kind.to_string();
+        self.register_input_with(kind, move |mut options| {
+            let codec = take_codec(&name, &mut options)?;
+            let input: C = from_options(&name, options)?;
+            Ok(match codec {
+                Some(codec) => Box::new(RecordInput(Decode::new(input, codec), PhantomData)),
+                None => Box::new(RecordInput::<_, String>(input, PhantomData)),
+            })
+        });
+    }
+
+    /// Registers an input of record batches, which reads their rows as records.
+    #[cfg(feature = "arrow")]
+    fn register_batch_input<C>(&mut self, kind: &str)
+    where
+        C: DeserializeOwned + Input<crate::batch::RecordBatch> + Send + 'static,
+    {
+        let name = kind.to_string();
+        self.register_input_with(kind, move |options| {
+            let input: C = from_options(&name, options)?;
+            Ok(Box::new(RecordInput(
+                crate::batch::Rows::<C, Record>::new(input),
+                PhantomData,
+            )))
+        });
+    }
+
+    /// Registers an input built by a custom factory.
+    pub fn register_input_with<F>(&mut self, kind: &str, factory: F)
+    where
+        F: Fn(Value) -> Result<Box<dyn DynInput>> + Send + Sync + 'static,
+    {
+        self.inputs.insert(kind.to_string(), Box::new(factory));
+    }
+
+    /// Registers a process stage deserialized from its options.
+    pub fn register_process<C, T, U>(&mut self, kind: &str)
+    where
+        C: DeserializeOwned + Process<T, U> + Send + Sync + 'static,
+        T: FromRecord + Send + 'static,
+        U: Into<Record> + Send + 'static,
+    {
+        let name = kind.to_string();
+        self.register_process_with(kind, move |options| {
+            let process: C = from_options(&name, options)?;
+            Ok(Arc::new(RecordProcess(process, PhantomData)))
+        });
+    }
+
+    /// Registers a process stage built by a custom factory.
+    pub fn register_process_with<F>(&mut self, kind: &str, factory: F)
+    where
+        F: Fn(Value) -> Result<Arc<dyn DynProcess>> + Send + Sync + 'static,
+    {
+        self.processes.insert(kind.to_string(), Box::new(factory));
+    }
+
+    /// Registers an output deserialized from its options.
+    pub fn register_output<C, T>(&mut self, kind: &str)
+    where
+        C: DeserializeOwned + Output<T> + Send + Sync + 'static,
+        T: FromRecord + Send + 'static,
+    {
+        let name = kind.to_string();
+        self.register_output_with(kind, move |options| {
+            let output: C = from_options(&name, options)?;
+            Ok(Arc::new(RecordOutput(output, PhantomData)))
+        });
+    }
+
+    /// Registers a byte-oriented output which encodes records with its
+    /// `codec` option if given, and writes lines otherwise.
+    fn register_encoded_output<C>(&mut self, kind: &str)
+    where
+        C: DeserializeOwned + Output<String> + Send + Sync + 'static,
+        Encode<C, Codec>: Output<Record> + Send + Sync + 'static,
+    {
+        let name = kind.to_string();
+        self.register_output_with(kind, move |mut options| {
+            let codec = take_codec(&name, &mut options)?;
+            let output: C = from_options(&name, options)?;
+            Ok(encoded_output(output, codec))
+        });
+    }
+
+    /// Registers an output of record batches, which writes records as rows,
+    /// laid out according to its `schema` option if given.
+    #[cfg(feature = "arrow")]
+    fn register_batch_output<C>(&mut self, kind: &str)
+    where
+        C: DeserializeOwned + Output<crate::batch::RecordBatch> + Send + Sync + 'static,
+    {
+        let name = kind.to_string();
+        self.register_output_with(kind, move |mut options| {
+            let schema = options
+                .as_object_mut()
+                .and_then(|options| options.remove("schema"))
+                .map(crate::batch::schema_of)
+                .transpose()?;
+            let output: C = from_options(&name, options)?;
+            let mut batches = crate::batch::Batches::new(output);
+            if let Some(schema) = schema {
+                batches = batches.schema(schema);
+            }
+            Ok(Arc::new(RecordOutput::<_, Record>(batches, PhantomData)))
+        });
+    }
+
+    /// Registers an output built by a custom factory.
+    pub fn register_output_with<F>(&mut self, kind: &str, factory: F)
+    where
+        F: Fn(Value) -> Result<Arc<dyn DynOutput>> + Send + Sync + 'static,
+    {
+        self.outputs.insert(kind.to_string(), Box::new(factory));
+    }
+
+    pub fn inputs(&self) -> impl Iterator<Item = &str> {
+        self.inputs.keys().map(String::as_str)
+    }
+
+    pub fn processes(&self) -> impl Iterator<Item = &str> {
+        self.processes.keys().map(String::as_str)
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = &str> {
+        self.outputs.keys().map(String::as_str)
+    }
+
+    /// Instantiates every component of `config` and connects them.
+    ///
+    /// Process stages holding on to items cannot follow an input tracking
+    /// checkpoints; see [`Process::holds_items`].
+    pub fn build(&self, config: &PipelineConfig) -> Result<ConfiguredPipeline> {
+        let input = instantiate("input", &self.inputs, &config.input)?;
+        let processes: Vec<_> = config
+            .process
+            .iter()
+            .map(|process| instantiate("process", &self.processes, process))
+            .collect::<Result<_>>()?;
+        if input.checkpoints().is_some() {
+            let mut stages = config.process.iter().zip(&processes);
+            if let Some((process, _)) = stages.find(|(_, stage)| stage.holds_items()) {
+                return Err(Error::config(format!(
+                    "process `{}` holds on to items, such as failed ones for its dead-letter \
+                     output, so it cannot follow an input that tracks checkpoints",
+                    process.kind
+                )));
+            }
+        }
+        Ok(ConfiguredPipeline {
+            input,
+            processes,
+            output: instantiate("output", &self.outputs, &config.output)?,
+            checkpoint_interval: config.checkpoint_interval,
+        })
+    }
+}
+
+fn instantiate<C>(
+    role: &str,
+    factories: &BTreeMap<String, Factory<C>>,
+    config: &ComponentConfig,
+) -> Result<C> {
+    let factory = factories
+        .get(&config.kind)
+        .ok_or_else(|| Error::config(format!("unknown {role} type `{}`", config.kind)))?;
+    factory(Value::Object(config.options.clone()))
+}
+
+/// A pipeline assembled by [`Registry::build`].
+pub struct ConfiguredPipeline {
+    input: Box<dyn DynInput>,
+    processes: Vec<Arc<dyn DynProcess>>,
+    output: Arc<dyn DynOutput>,
+    checkpoint_interval: Duration,
+}
+
+impl ConfiguredPipeline {
+    /// Drives the pipeline until the input is exhausted or any stage fails.
+    pub async fn run(self) -> Summary {
+        self.run_until(future::pending()).await
+    }
+
+    /// Like [`run`](Self::run), but stops reading the input once `shutdown`
+    /// completes. Items already read still pass through to the output.
+    pub async fn run_until<F: Future<Output = ()> + Send + 'static>(self, shutdown: F) -> Summary {
+        let start = Instant::now();
+        let failure = Failure::default();
+        let checkpoints = self.input.checkpoints();
+        let items_in = Arc::new(AtomicU64::new(0));
+        let items_out = Arc::new(AtomicU64::new(0));
+
+        let mut stream = {
+            let items_in = items_in.clone();
+            self.input
+                .into_record_stream()
+                .take_until(shutdown)
+                .inspect(move |item| {
+                    if item.is_ok() {
+                        items_in.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+                .boxed()
+        };
+        for process in self.processes {
+            stream = process.process_records(until_err(stream, failure.clone()).boxed());
+        }
+        let stream = {
+            let items_out = items_out.clone();
+            let checkpoints = checkpoints.clone();
+            until_err(stream, failure.clone())
+                .inspect(move |_| {
+                    let item = items_out.fetch_add(1, Ordering::Relaxed) + 1;
+                    if let Some(checkpoints) = &checkpoints {
+                        checkpoints.mark(item);
+                    }
+                })
+                .boxed()
+        };
+        let acks = Acks::default();
+        let result = commit_acked(
+            self.output.output_records(stream, acks.clone()),
+            checkpoints.as_ref(),
+            &acks,
+            self.checkpoint_interval,
+        )
+        .await;
+        let error = result.err().or_else(|| failure.take());
+        let error = match (error, checkpoints) {
+            (None, Some(checkpoints)) => checkpoints.commit().await.err(),
+            (error, _) => error,
+        };
+
+        Summary {
+            items_in: items_in.load(Ordering::Relaxed),
+            items_out: items_out.load(Ordering::Relaxed),
+            elapsed: start.elapsed(),
+            error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Format;
+
+    #[tokio::test]
+    async fn configured_pipelines_resume_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.ndjson");
+        let output = dir.path().join("out.ndjson");
+        let config = serde_json::json!({
+            "input": {
+                "type": "file",
+                "path": input,
+                "checkpoint": dir.path().join("checkpoints.json"),
+                "codec": "ndjson",
+            },
+            "output": {"type": "file", "path": output, "mode": "append", "codec": "ndjson"},
+            "checkpoint_interval": "100ms",
+        });
+        let config = PipelineConfig::parse(&config.to_string(), Format::Json).unwrap();
+        let run = || async { Registry::default().build(&config).unwrap().run().await };
+
+        std::fs::write(&input, "{\"n\":1}\n").unwrap();
+        assert!(run().await.is_success());
+        std::fs::write(&input, "{\"n\":1}\n{\"n\":2}\n").unwrap();
+        let summary = run().await;
+        assert!(summary.is_success());
+        assert_eq!(summary.items_in, 1);
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "{\"n\":1}\n{\"n\":2}\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn parse_json_sets_malformed_lines_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.ndjson");
+        let output = dir.path().join("out.ndjson");
+        let dead = dir.path().join("dead.ndjson");
+        let config = serde_json::json!({
+            "input": {"type": "file", "path": input, "codec": "lines"},
+            "process": [{"type": "parse_json", "on_malformed": "skip", "dead_letter": dead}],
+            "output": {"type": "file", "path": output, "codec": "ndjson"},
+        });
+        let config = PipelineConfig::parse(&config.to_string(), Format::Json).unwrap();
+
+        std::fs::write(&input, "{\"n\":1}\n{\"n\":\n{\"n\":3}\n").unwrap();
+        let summary = Registry::default().build(&config).unwrap().run().await;
+        assert!(summary.is_success());
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "{\"n\":1}\n{\"n\":3}\n"
+        );
+        assert_eq!(std::fs::read_to_string(&dead).unwrap(), "{\"n\":\n");
+    }
+
+    #[tokio::test]
+    async fn parse_json_stops_at_malformed_lines_by_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.ndjson");
+        let config = serde_json::json!({
+            "input": {"type": "file", "path": input, "codec": "lines"},
+            "process": [{"type": "parse_json"}],
+            "output": {"type": "file", "path": dir.path().join("out.ndjson"), "codec": "ndjson"},
+        });
+        let config = PipelineConfig::parse(&config.to_string(), Format::Json).unwrap();
+
+        std::fs::write(&input, "1\nnope\n2\n").unwrap();
+        let summary = Registry::default().build(&config).unwrap().run().await;
+        assert!(!summary.is_success());
+    }
+
+    #[test]
+    fn refuses_dead_letter_files_after_inputs_tracking_checkpoints() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = |input: Value, process: Value| {
+            let config = serde_json::json!({
+                "input": input,
+                "process": [process],
+                "output": {"type": "stdout"},
+            });
+            PipelineConfig::parse(&config.to_string(), Format::Json).unwrap()
+        };
+        let file = serde_json::json!({"type": "file", "path": dir.path().join("in.ndjson")});
+        let tracked = serde_json::json!({
+            "type": "file",
+            "path": dir.path().join("in.ndjson"),
+            "checkpoint": dir.path().join("checkpoints.json"),
+        });
+        let dead_letter =
+            serde_json::json!({"type": "parse_json", "dead_letter": dir.path().join("dead")});
+        let dropping = serde_json::json!({"type": "parse_json", "on_malformed": "skip"});
+
+        let registry = Registry::default();
+        let Err(e) = registry.build(&config(tracked.clone(), dead_letter.clone())) else {
+            panic!("built a pipeline that can lose failed items");
+        };
+        assert!(
+            e.to_string()
+                .starts_with("invalid configuration: process `parse_json` holds on to items"),
+            "{e}"
+        );
+        assert!(registry.build(&config(file, dead_letter)).is_ok());
+        assert!(registry.build(&config(tracked, dropping)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn http_appends_undelivered_items_to_its_dead_letter_file() {
+        let server = crate::http::mock::Server::start(|_, request| {
+            if request.text().contains("\"n\":2") {
+                crate::http::mock::Reply::status(400).body("no")
+            } else {
+                crate::http::mock::Reply::ok("")
+            }
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.ndjson");
+        let dead = dir.path().join("dead.ndjson");
+        let config = serde_json::json!({
+            "input": {"type": "file", "path": input, "codec": "ndjson"},
+            "output": {
+                "type": "http",
+                "endpoint": server.url("/"),
+                "codec": "ndjson",
+                "dead_letter": dead,
+            },
+        });
+        let config = PipelineConfig::parse(&config.to_string(), Format::Json).unwrap();
+
+        std::fs::write(&input, "{\"n\":1}\n{\"n\":2}\n{\"n\":3}\n").unwrap();
+        let summary = Registry::default().build(&config).unwrap().run().await;
+        assert!(summary.is_success());
+        assert_eq!(server.requests().len(), 3);
+        assert_eq!(std::fs::read_to_string(&dead).unwrap(), "{\"n\":2}\n");
+    }
+}